@@ -9,14 +9,22 @@ use glutin::event::{Event, WindowEvent};
 use cpal::Stream;
 use cpal::traits::*;
 use ringbuf::*;
+use anyhow::Context;
 use std::f32::consts::PI;
-use crate::filter::*;
+use crate::sequencer::*;
+use crate::arpeggiator::*;
+use crate::looper::*;
+use crate::oscilloscope::*;
+use crate::convolver::*;
+use crate::midi::*;
+use crate::config::*;
 
 pub struct Application {
     gl: glow::Context,
     window: glutin::WindowedContext<glutin::PossiblyCurrent>,
 
     renderer: KRenderer,
+    spectrogram_texture: NativeTexture,
     event_aggregator: EventAggregator,
 
     pub xres: f32,
@@ -26,6 +34,8 @@ pub struct Application {
 
     synth: Synth,
     channel: Producer<SoundMessage>,
+    analysis: Consumer<AnalysisFrame>,
+    garbage: Consumer<Garbage>,
 }
 
 pub fn load_file(paths: &[&str]) -> String {
@@ -38,11 +48,13 @@ pub fn load_file(paths: &[&str]) -> String {
 }
 
 impl Application {
-    pub fn new(event_loop: &glutin::event_loop::EventLoop<()>) -> Application {
-        let default_xres = 1600.0;
-        let default_yres = 1600.0;
-
-        let (gl, window) = unsafe { opengl_boilerplate(default_xres, default_yres, event_loop) };
+    pub fn new(event_loop: &glutin::event_loop::EventLoop<()>, args: &clap::ArgMatches) -> anyhow::Result<Application> {
+        // logical so a hidpi screen gets a window the same size on the desk, whatever it ends
+        // up as in pixels is what the aggregator gets told
+        let (gl, window) = unsafe { opengl_boilerplate(1280.0, 900.0, event_loop) };
+        let size = window.window().inner_size();
+        let (xres, yres) = (size.width as f32, size.height as f32);
+        let scale = window.window().scale_factor() as f32;
         
         let uvv = &[
             "src/uv.vert",
@@ -66,25 +78,62 @@ impl Application {
         let renderer = KRenderer::new(&gl, uv_shader, atlas);
         check_gl_errors(&gl, "after KRenderer::new");
 
-        let rb = RingBuffer::<SoundMessage>::new(5);
+        let rb = RingBuffer::<SoundMessage>::new(64);
         let (mut prod, mut cons) = rb.split();
+        // a bit over a quarter second, if the gui stalls longer than that the viewers just skip
+        let analysis_rb = RingBuffer::<AnalysisFrame>::new(16384);
+        let (analysis_prod, analysis_cons) = analysis_rb.split();
+        let garbage_rb = RingBuffer::<Garbage>::new(16);
+        let (garbage_prod, garbage_cons) = garbage_rb.split();
+
+        // the device decides the sample rate, everything sized by it gets made here off the audio thread
+        let (_host, device, config) = host_device_setup().expect("no output device");
+        let sample_rate = config.sample_rate().0 as f32;
+        let looper = Looper::new(sample_rate);
+
+        let mut synth = Synth::new(sample_rate);
+        if let Some(path) = args.value_of("scl") {
+            synth.tuning.load_scl(path).with_context(|| format!("couldn't load scl {}", path))?;
+        }
+        if let Some(path) = args.value_of("kbm") {
+            synth.tuning.load_kbm(path).with_context(|| format!("couldn't load kbm {}", path))?;
+        }
+        synth.keyboard.tuning = synth.tuning.tuning.clone();
+        synth.config = Config::load(CONFIG_PATH);
+        synth.ui.load_bindings(&synth.config);
+        synth.keyboard.keymap = synth.config.keymap;
+        if let Some(device) = args.value_of("midi") {
+            synth.midi = Some(MidiInput::open(device).with_context(|| format!("couldn't open midi device {}", device))?);
+        }
+        if let Some(path) = args.value_of("ir") {
+            let ir = load_impulse_response(path).with_context(|| format!("couldn't load impulse response {}", path))?;
+            prod.push(SoundMessage::SetReverb(Box::new(reverb_convolver(&ir))));
+            synth.reverb_mix = 0.3;
+            prod.push(SoundMessage::ReverbMix(synth.reverb_mix));
+        }
+
+        let spectrogram_texture = renderer.stream_texture(&gl, synth.spectrogram.width, synth.spectrogram.height);
+        check_gl_errors(&gl, "after stream_texture");
 
         let app = Application {
             gl,
             window,
             renderer,
-            event_aggregator: EventAggregator::new(default_xres, default_yres),
+            spectrogram_texture,
+            event_aggregator: EventAggregator::new(xres, yres, scale),
 
-            synth: Synth::new(),
+            synth,
 
-            xres: default_xres,
-            yres: default_yres,
+            xres,
+            yres,
 
             channel: prod,
-            audio_stream: stream_setup_for(sample_next, cons).expect("no can make stream"),
+            analysis: analysis_cons,
+            garbage: garbage_cons,
+            audio_stream: stream_setup_for(&device, config, sample_next, cons, analysis_prod, garbage_prod, looper).expect("no can make stream"),
         };
         app.audio_stream.play().expect("no can play stream");
-        app
+        Ok(app)
     }
 
     pub fn handle_event(&mut self, event: &glutin::event::Event<()>) {
@@ -96,6 +145,12 @@ impl Application {
                     self.yres = physical_size.height as f32;
                     unsafe {self.gl.viewport(0, 0, physical_size.width as i32, physical_size.height as i32)};
                 },
+                WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                    self.window.resize(**new_inner_size);
+                    self.xres = new_inner_size.width as f32;
+                    self.yres = new_inner_size.height as f32;
+                    unsafe {self.gl.viewport(0, 0, new_inner_size.width as i32, new_inner_size.height as i32)};
+                },
                 _ => {},
             _ => {},
             }
@@ -111,16 +166,32 @@ impl Application {
 
             let mut kc = KRCanvas::new();
 
-            self.synth.frame(&inputs, &mut kc, &mut self.channel);
+            self.synth.frame(&inputs, &mut kc, &mut self.channel, &mut self.analysis);
+            // freed here instead of in the audio callback
+            while let Some(g) = self.garbage.pop() {
+                match g {
+                    Garbage::Filter(f) => drop(f),
+                    Garbage::Convolver(c) => drop(c),
+                }
+            }
 
             self.renderer.send(&self.gl, &kc.bytes());
 
+            for (x, column) in self.synth.spectrogram.take_pending() {
+                self.renderer.upload_column(&self.gl, self.spectrogram_texture, x, &column);
+            }
+            let mut spectrogram_kc = KRCanvas::new();
+            spectrogram_kc.set_camera(inputs.screen_rect);
+            self.synth.spectrogram.draw(&mut spectrogram_kc);
+            self.renderer.send_with(&self.gl, &spectrogram_kc.bytes(), self.spectrogram_texture);
+
             self.window.swap_buffers().unwrap();
         }
     }
 
     pub fn destroy(&mut self) {
         self.renderer.destroy(&self.gl);
+        unsafe { self.gl.delete_texture(self.spectrogram_texture) };
     }
 }
 
@@ -181,7 +252,9 @@ fn  make_shader(gl: &glow::Context, vert_paths: &[&str], frag_paths: &[&str]) ->
 unsafe fn opengl_boilerplate(xres: f32, yres: f32, event_loop: &glutin::event_loop::EventLoop<()>) -> (glow::Context, glutin::WindowedContext<glutin::PossiblyCurrent>) {
     let window_builder = glutin::window::WindowBuilder::new()
         .with_title("synth")
-        .with_inner_size(glutin::dpi::PhysicalSize::new(xres, yres));
+        .with_inner_size(glutin::dpi::LogicalSize::new(xres, yres))
+        // smallest the panels minimum sizes can mostly live with
+        .with_min_inner_size(glutin::dpi::LogicalSize::new(800.0, 600.0));
     let window = glutin::ContextBuilder::new()
         // .with_depth_buffer(0)
         // .with_srgb(true)
@@ -223,7 +296,15 @@ unsafe fn opengl_boilerplate(xres: f32, yres: f32, event_loop: &glutin::event_lo
 }
 
 fn sample_next(o: &mut SampleRequestOptions) -> f32 {
-    o.mixer.tick()
+    let mut events = std::mem::take(&mut o.events);
+    o.arpeggiator.tick(&mut events);
+    for msg in events.drain(..) {
+        handle_message(o, msg);
+    }
+    o.events = events;
+    o.sequencer.tick(&mut o.mixer);
+    let dry = o.mixer.tick();
+    o.reverb.tick(o.looper.tick(dry))
     // o.filter.tick()
 
     // let distorted = pa_fuzz(samp);
@@ -240,20 +321,25 @@ pub struct SampleRequestOptions {
     // pub filter: Filter,
 
     pub mixer: Mixer,
+    pub sequencer: SequencePlayer,
+    pub arpeggiator: Arpeggiator,
+    pub looper: Looper,
+    pub reverb: Reverb,
+    pub events: Vec<SoundMessage>,  // generated on the audio thread eg by the arp
 
     pub channel: Consumer<SoundMessage>,
+    pub analysis: Producer<AnalysisFrame>,
+    pub garbage: Producer<Garbage>,
 }
 
-pub fn stream_setup_for<F>(on_sample: F, channel: Consumer<SoundMessage>) -> Result<cpal::Stream, anyhow::Error>
+pub fn stream_setup_for<F>(device: &cpal::Device, config: cpal::SupportedStreamConfig, on_sample: F, channel: Consumer<SoundMessage>, analysis: Producer<AnalysisFrame>, garbage: Producer<Garbage>, looper: Looper) -> Result<cpal::Stream, anyhow::Error>
 where
     F: FnMut(&mut SampleRequestOptions) -> f32 + std::marker::Send + 'static + Copy,
 {
-    let (_host, device, config) = host_device_setup()?;
-
     match config.sample_format() {
-        cpal::SampleFormat::F32 => stream_make::<f32, _>(&device, &config.into(), on_sample, channel),
-        cpal::SampleFormat::I16 => stream_make::<i16, _>(&device, &config.into(), on_sample, channel),
-        cpal::SampleFormat::U16 => stream_make::<u16, _>(&device, &config.into(), on_sample, channel),
+        cpal::SampleFormat::F32 => stream_make::<f32, _>(device, &config.into(), on_sample, channel, analysis, garbage, looper),
+        cpal::SampleFormat::I16 => stream_make::<i16, _>(device, &config.into(), on_sample, channel, analysis, garbage, looper),
+        cpal::SampleFormat::U16 => stream_make::<u16, _>(device, &config.into(), on_sample, channel, analysis, garbage, looper),
     }
 }
 
@@ -277,6 +363,9 @@ pub fn stream_make<T, F>(
     config: &cpal::StreamConfig,
     on_sample: F,
     channel: Consumer<SoundMessage>,
+    analysis: Producer<AnalysisFrame>,
+    garbage: Producer<Garbage>,
+    looper: Looper,
 ) -> Result<cpal::Stream, anyhow::Error>
 where
     T: cpal::Sample,
@@ -305,10 +394,16 @@ where
         // would be good to plot response etc
 
         // filter: Filter::new(),
-        // filter: Filter::lowpass(256, 44100.0, 400.0),
         mixer: Mixer::new(sample_rate),
+        sequencer: SequencePlayer::new(sample_rate),
+        arpeggiator: Arpeggiator::new(sample_rate),
+        looper,
+        reverb: Reverb::new(),
+        events: Vec::with_capacity(8),
 
         channel,
+        analysis,
+        garbage,
     };
     let err_fn = |err| eprintln!("Error building output sound stream: {}", err);
 
@@ -323,23 +418,85 @@ where
     Ok(stream)
 }
 
+fn handle_message(request: &mut SampleRequestOptions, msg: SoundMessage) {
+    match msg {
+        SoundMessage::PlaySound(s, id) => {
+            request.mixer.add_sound(s, id);
+        },
+        SoundMessage::StopSound(id) => {
+            request.mixer.stop_sound(id);
+        },
+        SoundMessage::SetTransport(t) => {
+            request.sequencer.set_transport(t);
+            request.arpeggiator.set_transport(t);
+            request.looper.set_transport(t);
+        },
+        SoundMessage::SetPattern(p, freqs, s) => {
+            request.sequencer.set_pattern(p, freqs, s);
+        },
+        SoundMessage::ArpPeriod(period) => {
+            request.arpeggiator.set_period(period);
+        },
+        SoundMessage::SetArp(settings) => {
+            request.arpeggiator.set_settings(settings);
+        },
+        SoundMessage::ArpNoteOn(s, id) => {
+            request.arpeggiator.note_on(s, id);
+        },
+        SoundMessage::ArpNoteOff(id) => {
+            request.arpeggiator.note_off(id);
+        },
+        SoundMessage::Looper(track, cmd) => {
+            request.looper.command(track, cmd);
+        },
+        SoundMessage::LoopQuantize(q) => {
+            request.looper.quantize = q;
+        },
+        SoundMessage::SetReverb(c) => {
+            if let Some(old) = request.reverb.set_convolver(c) {
+                discard(&mut request.garbage, Garbage::Convolver(old));
+            }
+        },
+        SoundMessage::ReverbMix(mix) => {
+            request.reverb.mix = mix;
+        },
+        SoundMessage::PitchBend(semitones) => {
+            request.mixer.set_bend(semitones);
+        },
+        SoundMessage::Pedals(sustain, sostenuto) => {
+            request.mixer.set_sustain(sustain);
+            request.mixer.set_sostenuto(sostenuto);
+        },
+        SoundMessage::SetFilter(f) => {
+            let old = request.mixer.set_filter(f);
+            discard(&mut request.garbage, Garbage::Filter(old));
+        },
+    }
+}
+
+// back to the gui to be freed. if thats backed up leaking it is still better than freeing here
+fn discard(garbage: &mut Producer<Garbage>, g: Garbage) {
+    if let Err(g) = garbage.push(g) {
+        std::mem::forget(g);
+    }
+}
+
 fn on_window<T, F>(output: &mut [T], request: &mut SampleRequestOptions, mut on_sample: F)
 where
     T: cpal::Sample,
     F: FnMut(&mut SampleRequestOptions) -> f32 + std::marker::Send + 'static,
 {
-    if let Some(msg) = request.channel.pop() {
-        match msg {
-            SoundMessage::PlaySound(s, id) => {
-                request.mixer.add_sound(s, id);
-            },
-            SoundMessage::StopSound(id) => {
-                request.mixer.stop_sound(id);
-            }
-        }
+    while let Some(msg) = request.channel.pop() {
+        handle_message(request, msg);
     }
+    let mut voices = [0.0; SCOPE_VOICES];
     for frame in output.chunks_mut(request.nchannels) {
-        let value: T = cpal::Sample::from::<f32>(&on_sample(request));
+        let out = on_sample(request);
+        // full means the gui isnt keeping up, dropping is fine
+        request.mixer.voice_outputs(&mut voices);
+        let loops = request.looper.states();
+        let _ = request.analysis.push(AnalysisFrame { out, voices, loops });
+        let value: T = cpal::Sample::from::<f32>(&out);
         for sample in frame.iter_mut() {
             *sample = value;
         }
@@ -0,0 +1,300 @@
+use crate::kmath::*;
+use crate::sound::*;
+use crate::widgets::*;
+use crate::units::*;
+use crate::transport::*;
+
+// arp voices get their own id space next to the sequencer ones, keyboard ids have both bits off
+pub const ARP_ID_BIT: u32 = 0x4000_0000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArpOrder {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    AsPlayed,
+}
+
+impl ArpOrder {
+    pub fn next(&self) -> ArpOrder {
+        match self {
+            ArpOrder::Up => ArpOrder::Down,
+            ArpOrder::Down => ArpOrder::UpDown,
+            ArpOrder::UpDown => ArpOrder::Random,
+            ArpOrder::Random => ArpOrder::AsPlayed,
+            ArpOrder::AsPlayed => ArpOrder::Up,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ArpOrder::Up => "up",
+            ArpOrder::Down => "down",
+            ArpOrder::UpDown => "updn",
+            ArpOrder::Random => "rand",
+            ArpOrder::AsPlayed => "play",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArpSettings {
+    pub enabled: bool,
+    pub order: ArpOrder,
+    pub octaves: u32,
+    pub rate: u32,      // notes per beat
+    pub gate: f32,      // fraction of a note
+    pub latch: bool,    // keep going after keys are let go, until the next fresh press
+}
+
+impl ArpSettings {
+    pub fn new() -> ArpSettings {
+        ArpSettings { enabled: false, order: ArpOrder::Up, octaves: 2, rate: 4, gate: 0.5, latch: false }
+    }
+}
+
+// most notes the arp will hold at once, past this new presses are ignored
+pub const MAX_ARP_NOTES: usize = MAX_CHANNELS;
+
+// how long the walk is through n notes, without building it
+pub fn arp_len(n: usize, order: ArpOrder, octaves: u32) -> usize {
+    let up = n * octaves.max(1) as usize;
+    if order == ArpOrder::UpDown {
+        // dont double up the top and bottom notes
+        up + up.saturating_sub(2)
+    } else {
+        up
+    }
+}
+
+// step i of the walk. notes have to be sorted already unless its AsPlayed. period is the
+// ratio an octave up is in the tuning, 2 unless the scale says otherwise
+pub fn arp_note(notes: &[f32], order: ArpOrder, octaves: u32, period: f32, i: usize) -> f32 {
+    let up = notes.len() * octaves.max(1) as usize;
+    let i = i % arp_len(notes.len(), order, octaves);
+    let j = match order {
+        ArpOrder::Down => up - 1 - i,
+        ArpOrder::UpDown if i >= up => 2 * up - 2 - i,
+        _ => i,
+    };
+    notes[j % notes.len()] * period.powi((j / notes.len()) as i32)
+}
+
+#[test]
+fn test_arp_sequence() {
+    let seq = |notes: &[f32], order, octaves| {
+        (0..arp_len(notes.len(), order, octaves)).map(|i| arp_note(notes, order, octaves, 2.0, i)).collect::<Vec<f32>>()
+    };
+    assert_eq!(seq(&[100.0, 200.0], ArpOrder::Up, 1), vec![100.0, 200.0]);
+    assert_eq!(seq(&[200.0, 100.0], ArpOrder::AsPlayed, 1), vec![200.0, 100.0]);
+    assert_eq!(seq(&[100.0, 200.0], ArpOrder::Down, 2), vec![400.0, 200.0, 200.0, 100.0]);
+    assert_eq!(seq(&[100.0, 150.0], ArpOrder::UpDown, 2), vec![100.0, 150.0, 200.0, 300.0, 200.0, 150.0]);
+    assert_eq!(seq(&[100.0], ArpOrder::UpDown, 1), vec![100.0]);
+    // wraps around
+    assert_eq!(arp_note(&[100.0, 200.0], ArpOrder::Up, 1, 2.0, 3), 200.0);
+    assert_eq!(arp_note(&[100.0, 200.0], ArpOrder::Up, 2, 3.0, 3), 600.0);
+}
+
+// audio thread side. gets held notes instead of PlaySound/StopSound when its on
+pub struct Arpeggiator {
+    settings: ArpSettings,
+    clock: Clock,
+    period: f32,
+    held: [(u32, Sound); MAX_ARP_NOTES],    // in the order they were pressed
+    held_len: usize,
+    down: [u32; MAX_ARP_NOTES],             // keys physically down, differs from held when latching
+    down_len: usize,
+    latched: bool,              // everything was let go while latched, next press starts over
+    voice: Option<u32>,
+    gate_left: u64,
+    counter: u32,
+}
+
+impl Arpeggiator {
+    pub fn new(sample_rate: f32) -> Arpeggiator {
+        Arpeggiator {
+            settings: ArpSettings::new(),
+            clock: Clock::new(sample_rate),
+            period: 2.0,
+            held: [(0, Sound::new()); MAX_ARP_NOTES],
+            held_len: 0,
+            down: [0; MAX_ARP_NOTES],
+            down_len: 0,
+            latched: false,
+            voice: None,
+            gate_left: 0,
+            counter: 0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: ArpSettings) {
+        if !settings.latch {
+            let mut kept = 0;
+            for k in 0..self.held_len {
+                if self.down[..self.down_len].contains(&self.held[k].0) {
+                    self.held[kept] = self.held[k];
+                    kept += 1;
+                }
+            }
+            self.held_len = kept;
+            self.latched = false;
+        }
+        self.settings = settings;
+        self.sync_clock(self.clock.transport.bpm, self.clock.transport.swing);
+    }
+
+    pub fn set_transport(&mut self, t: Transport) {
+        self.sync_clock(t.bpm, t.swing);
+    }
+
+    pub fn set_period(&mut self, period: f32) {
+        self.period = period;
+    }
+
+    fn sync_clock(&mut self, bpm: f32, swing: f32) {
+        let mut t = self.clock.transport;
+        t.bpm = bpm;
+        t.swing = swing;
+        t.beat_unit = 4;
+        t.steps_per_beat = self.settings.rate.max(1);
+        t.playing = self.held_len > 0;
+        self.clock.set_transport(t);
+    }
+
+    pub fn note_on(&mut self, sound: Sound, id: u32) {
+        if self.latched {
+            self.held_len = 0;
+            self.latched = false;
+        }
+        if self.held_len == 0 {
+            // fresh chord, start right away
+            self.clock.reset();
+        }
+        self.remove_held(id);
+        if self.held_len < MAX_ARP_NOTES {
+            self.held[self.held_len] = (id, sound);
+            self.held_len += 1;
+        }
+        if self.down_len < MAX_ARP_NOTES && !self.down[..self.down_len].contains(&id) {
+            self.down[self.down_len] = id;
+            self.down_len += 1;
+        }
+        self.sync_clock(self.clock.transport.bpm, self.clock.transport.swing);
+    }
+
+    pub fn note_off(&mut self, id: u32) {
+        if let Some(k) = self.down[..self.down_len].iter().position(|i| *i == id) {
+            self.down.copy_within(k + 1..self.down_len, k);
+            self.down_len -= 1;
+        }
+        if self.settings.latch {
+            // holding on to them til the next press after letting go of everything
+            self.latched = self.down_len == 0;
+            return;
+        }
+        self.remove_held(id);
+        self.sync_clock(self.clock.transport.bpm, self.clock.transport.swing);
+    }
+
+    // keeps the pressed order
+    fn remove_held(&mut self, id: u32) {
+        if let Some(k) = self.held[..self.held_len].iter().position(|(i, _)| *i == id) {
+            self.held.copy_within(k + 1..self.held_len, k);
+            self.held_len -= 1;
+        }
+    }
+
+    // feeds PlaySound and StopSound the same way the gui would
+    pub fn tick(&mut self, out: &mut Vec<SoundMessage>) {
+        if self.voice.is_some() {
+            if self.gate_left == 0 || !self.settings.enabled || self.held_len == 0 {
+                out.push(SoundMessage::StopSound(self.voice.take().unwrap()));
+            } else {
+                self.gate_left -= 1;
+            }
+        }
+        if !self.settings.enabled || self.held_len == 0 {
+            return;
+        }
+        let n = match self.clock.tick() {
+            Some(n) => n,
+            None => return,
+        };
+
+        let mut notes = [0.0; MAX_ARP_NOTES];
+        let notes = &mut notes[..self.held_len];
+        for (f, (_, s)) in notes.iter_mut().zip(self.held.iter()) {
+            *f = s.freq;
+        }
+        let order = self.settings.order;
+        if order != ArpOrder::AsPlayed {
+            notes.sort_unstable_by(|a, b| a.total_cmp(b));
+        }
+        let i = if order == ArpOrder::Random {
+            khash(n as u32 ^ self.counter) as usize
+        } else {
+            n as usize
+        };
+        let freq = arp_note(notes, order, self.settings.octaves, self.period, i);
+
+        if let Some(id) = self.voice.take() {
+            out.push(SoundMessage::StopSound(id));
+        }
+        // latest pressed sound has the latest patch
+        let mut s = self.held[self.held_len - 1].1;
+        s.freq = freq;
+        self.counter = self.counter.wrapping_add(1);
+        let id = ARP_ID_BIT | (self.counter & (ARP_ID_BIT - 1));
+        out.push(SoundMessage::PlaySound(s, id));
+        self.voice = Some(id);
+        self.gate_left = (self.settings.gate * self.clock.step_len() as f32) as u64;
+    }
+}
+
+// gui side
+pub struct ArpPanel {
+    pub settings: ArpSettings,
+}
+
+impl ArpPanel {
+    pub fn new() -> ArpPanel {
+        ArpPanel { settings: ArpSettings::new() }
+    }
+
+    // returns modification
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect) -> bool {
+        ui.push_id("arp");
+        let mut changed = false;
+
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.4, 0.5, 0.6, 1.0));
+        ui.kc.rect(rect);
+
+        let (buttons, sliders) = rect.split_ud(0.3);
+        let (title, buttons) = buttons.split_ud(0.5);
+        ui.kc.set_depth(1.2);
+        ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        ui.kc.text_center("arp".as_bytes(), title);
+
+        let buttons = buttons.split_lrn(3);
+        changed |= ui.toggle("on", buttons[0], &mut self.settings.enabled);
+        if ui.button(self.settings.order.name(), buttons[1]) {
+            self.settings.order = self.settings.order.next();
+            changed = true;
+        }
+        changed |= ui.toggle("latch", buttons[2], &mut self.settings.latch);
+
+        let sliders = sliders.split_lrn(3);
+        let mut octaves = self.settings.octaves as f32;
+        let mut rate = self.settings.rate as f32;
+        changed |= ui.slider("oct", sliders[0], 1.0, 4.0, &mut octaves, SliderMapping::Stepped(1.0), Unit::None) |
+            ui.slider("rate", sliders[1], 1.0, 8.0, &mut rate, SliderMapping::Stepped(1.0), Unit::None) |
+            ui.slider("gate", sliders[2], 0.05, 1.0, &mut self.settings.gate, SliderMapping::Linear, Unit::Percent);
+        self.settings.octaves = octaves.round() as u32;
+        self.settings.rate = rate.round() as u32;
+
+        ui.pop_id();
+        changed
+    }
+}
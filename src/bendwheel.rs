@@ -0,0 +1,95 @@
+use crate::krenderer::*;
+use crate::kinput::*;
+use crate::kmath::*;
+use crate::midi::*;
+
+// pitch wheel next to the keys. drag it up or down and it springs back to the middle when let go.
+// a controllers wheel moves it too
+pub struct BendWheel {
+    pub value: f32,     // -1..1
+    pub range: f32,     // semitones at full throw
+    held: bool,
+    midi_value: Option<f32>,    // latest from a controller, goes in next frame
+    from_midi: bool,            // the controllers wheel springs back on its own, dont do it here too
+}
+
+impl BendWheel {
+    pub fn new() -> BendWheel {
+        BendWheel { value: 0.0, range: 2.0, held: false, midi_value: None, from_midi: false }
+    }
+
+    // any channel, theres only the one wheel
+    pub fn midi(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            if let MidiMessage::PitchBend { value, .. } = *m {
+                self.midi_value = Some(value);
+            }
+        }
+    }
+
+    pub fn semitones(&self) -> f32 {
+        self.value * self.range
+    }
+
+    // true when the bend moved and needs sending
+    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, r: Rect) -> bool {
+        kc.set_depth(1.1);
+        kc.set_colour(Vec4::new(0.2, 0.2, 0.2, 1.0));
+        kc.rect(r);
+
+        let track = r.dilate_pc(-0.15);
+        if track.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::JustPressed {
+            self.held = true;
+            self.from_midi = false;
+        }
+        if inputs.lmb != KeyStatus::Pressed && inputs.lmb != KeyStatus::JustPressed {
+            self.held = false;
+        }
+
+        let before = self.value;
+        if let Some(v) = self.midi_value.take() {
+            if !self.held {
+                self.value = v;
+                self.from_midi = true;
+            }
+        }
+        if self.held {
+            self.value = remap(inputs.mouse_pos.y, track.bot(), track.top(), -1.0, 1.0).max(-1.0).min(1.0);
+        } else if self.value != 0.0 && !self.from_midi {
+            // spring back quick but not instantly, snapping is a click
+            self.value *= (-20.0 * inputs.dt as f32).exp();
+            if self.value.abs() < 0.001 {
+                self.value = 0.0;
+            }
+        }
+
+        kc.set_depth(1.2);
+        kc.set_colour(Vec4::new(0.05, 0.05, 0.05, 1.0));
+        kc.rect(track);
+        kc.set_colour(Vec4::new(0.4, 0.4, 0.4, 1.0));
+        kc.rect(Rect::new(track.x, track.centroid().y, track.w, 0.001));
+        let y = remap(self.value, -1.0, 1.0, track.bot(), track.top());
+        kc.set_depth(1.3);
+        kc.set_colour(if self.held { Vec4::new(1.0, 0.9, 0.3, 1.0) } else { Vec4::new(0.8, 0.8, 0.8, 1.0) });
+        kc.rect(Rect::new_centered(track.centroid().x, y, track.w, 0.01));
+        self.value != before
+    }
+}
+
+#[test]
+fn test_midi_bend() {
+    use crate::widgets::*;
+    let mut w = BendWheel::new();
+    let mut kc = KRCanvas::new();
+    let r = Rect::new(0.0, 0.0, 0.1, 0.5);
+    let idle = test_inputs(Vec2::new(0.9, 0.9), KeyStatus::Released);
+    w.midi(&[MidiMessage::PitchBend { channel: 0, value: 0.25 }, MidiMessage::PitchBend { channel: 0, value: 0.5 }]);
+    assert!(w.frame(&idle, &mut kc, r));
+    assert_eq!(w.semitones(), 1.0);
+    // stays where the controller put it rather than springing back
+    assert!(!w.frame(&idle, &mut kc, r));
+    assert_eq!(w.value, 0.5);
+    w.midi(&[MidiMessage::PitchBend { channel: 3, value: 0.0 }]);
+    assert!(w.frame(&idle, &mut kc, r));
+    assert_eq!(w.value, 0.0);
+}
@@ -0,0 +1,428 @@
+use crate::krenderer::*;
+use crate::kinput::*;
+use crate::kmath::*;
+use crate::widgets::*;
+use crate::units::*;
+
+// fixed so Sound stays Copy and can go down the ringbuf
+pub const MAX_BREAKPOINTS: usize = 16;
+
+// what the shape drives
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ShapeTarget {
+    Off,
+    Amplitude,  // on top of the adsr, amount is how deep
+    Pitch,      // amount of 1 is two octaves up at the top
+    Filter,     // sweeps a resonant lowpass, amount is how many octaves it closes by (8 at 1)
+    Detune,     // adds up to 100 cents to the unison spread
+}
+
+impl ShapeTarget {
+    pub fn next(&self) -> ShapeTarget {
+        match self {
+            ShapeTarget::Off => ShapeTarget::Amplitude,
+            ShapeTarget::Amplitude => ShapeTarget::Pitch,
+            ShapeTarget::Pitch => ShapeTarget::Filter,
+            ShapeTarget::Filter => ShapeTarget::Detune,
+            ShapeTarget::Detune => ShapeTarget::Off,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapeTarget::Off => "off",
+            ShapeTarget::Amplitude => "amp",
+            ShapeTarget::Pitch => "pitch",
+            ShapeTarget::Filter => "filter",
+            ShapeTarget::Detune => "detune",
+        }
+    }
+}
+
+// c1 and c2 are the bezier handles for the segment leading out of this point. x is how far along
+// the segment (kept in 0..1 so the curve never goes back in time), y is level relative to this
+// point for c1 and to the next point for c2, so the handles ride along when a point gets moved
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub t: f32,
+    pub level: f32,
+    pub c1: Vec2,
+    pub c2: Vec2,
+}
+
+impl Breakpoint {
+    // flat handles, eases in and out of every point
+    pub fn new(t: f32, level: f32) -> Breakpoint {
+        Breakpoint { t, level, c1: Vec2::new(1.0 / 3.0, 0.0), c2: Vec2::new(2.0 / 3.0, 0.0) }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ShapeDrag {
+    Point(usize),
+    C1(usize),
+    C2(usize),
+}
+
+// free form envelope, any number of points with bezier segments between them. first point is
+// always at 0. holds at the sustain point while the key is down, if there is one
+#[derive(Clone, Copy)]
+pub struct BreakpointEnvelope {
+    points: [Breakpoint; MAX_BREAKPOINTS],
+    n: usize,
+    pub sustain: Option<usize>,
+    pub target: ShapeTarget,
+    pub amount: f32,
+}
+
+impl BreakpointEnvelope {
+    pub fn new() -> BreakpointEnvelope {
+        let mut points = [Breakpoint::new(0.0, 0.0); MAX_BREAKPOINTS];
+        points[1] = Breakpoint::new(0.05, 1.0);
+        points[2] = Breakpoint::new(0.4, 0.5);
+        points[3] = Breakpoint::new(0.8, 0.0);
+        BreakpointEnvelope {
+            points,
+            n: 4,
+            sustain: Some(2),
+            target: ShapeTarget::Off,
+            amount: 1.0,
+        }
+    }
+
+    pub fn points(&self) -> &[Breakpoint] {
+        &self.points[..self.n]
+    }
+
+    pub fn length(&self) -> f32 {
+        self.points[self.n - 1].t
+    }
+
+    // keeps them in time order, returns where it went
+    pub fn insert(&mut self, t: f32, level: f32) -> Option<usize> {
+        if self.n == MAX_BREAKPOINTS || t <= 0.0 {
+            return None;
+        }
+        let i = self.points().iter().position(|p| p.t > t).unwrap_or(self.n);
+        self.points.copy_within(i..self.n, i + 1);
+        self.points[i] = Breakpoint::new(t, level);
+        self.n += 1;
+        if let Some(s) = self.sustain.as_mut() {
+            if *s >= i {
+                *s += 1;
+            }
+        }
+        Some(i)
+    }
+
+    // the first point stays, and theres always at least two
+    pub fn remove(&mut self, i: usize) {
+        if i == 0 || i >= self.n || self.n <= 2 {
+            return;
+        }
+        self.points.copy_within(i + 1..self.n, i);
+        self.n -= 1;
+        self.sustain = match self.sustain {
+            Some(s) if s == i => None,
+            Some(s) if s > i => Some(s - 1),
+            s => s,
+        };
+    }
+
+    // x along segment i from 0..1
+    fn segment_level(&self, i: usize, x: f32) -> f32 {
+        let (p, q) = (self.points[i], self.points[i + 1]);
+        let bez = |u: f32| cubic_bezier(
+            Vec2::new(0.0, p.level),
+            Vec2::new(p.c1.x, p.level + p.c1.y),
+            Vec2::new(p.c2.x, q.level + p.c2.y),
+            Vec2::new(1.0, q.level),
+            u,
+        );
+        // x only goes one way with the handles inside the segment, so bisect for the u that lands on it
+        let (mut lo, mut hi) = (0.0, 1.0);
+        for _ in 0..20 {
+            let mid = (lo + hi) / 2.0;
+            if bez(mid).x < x {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        bez((lo + hi) / 2.0).y
+    }
+
+    pub fn level(&self, t: f32) -> f32 {
+        let pts = self.points();
+        for i in 0..self.n - 1 {
+            if t < pts[i + 1].t {
+                let len = pts[i + 1].t - pts[i].t;
+                let x = ((t - pts[i].t) / len).max(0.0);
+                return self.segment_level(i, x).max(0.0).min(1.0);
+            }
+        }
+        pts[self.n - 1].level
+    }
+}
+
+// gui side, the shape plus what the editor has selected and is dragging
+pub struct ShapePanel {
+    pub shape: BreakpointEnvelope,
+    selected: usize,
+    drag: Option<(ShapeDrag, f32)>,     // seconds per unit width, fixed for the drag
+}
+
+impl ShapePanel {
+    pub fn new(shape: BreakpointEnvelope) -> ShapePanel {
+        ShapePanel { shape, selected: 0, drag: None }
+    }
+
+    fn editor(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, r: Rect) -> bool {
+        kc.set_depth(1.2);
+        kc.set_colour(Vec4::new(0.1, 0.1, 0.15, 1.0));
+        kc.rect(r);
+
+        let sec_per_w = match self.drag {
+            Some((_, s)) => s,
+            None => (self.shape.length() * 1.2).max(0.5) / r.w,
+        };
+        let x_at = |t: f32| r.x + t / sec_per_w;
+        let y_at = |level: f32| lerp(r.bot(), r.top(), level);
+        let t_at = |x: f32| (x - r.x) * sec_per_w;
+        let level_at = |y: f32| unlerp(y, r.bot(), r.top());
+
+        if let Some(s) = self.shape.sustain {
+            kc.set_depth(1.25);
+            kc.set_colour(Vec4::new(0.3, 0.3, 0.45, 1.0));
+            kc.rect(Rect::new(x_at(self.shape.points[s].t), r.y, 0.001, r.h));
+        }
+
+        let steps = 150;
+        kc.set_depth(1.3);
+        kc.set_colour(Vec4::new(0.6, 0.6, 1.0, 1.0));
+        for i in 1..steps {
+            let t0 = t_at(r.x + r.w * (i - 1) as f32 / (steps - 1) as f32);
+            let t1 = t_at(r.x + r.w * i as f32 / (steps - 1) as f32);
+            kc.line(Vec2::new(x_at(t0), y_at(self.shape.level(t0))), Vec2::new(x_at(t1), y_at(self.shape.level(t1))), 0.002);
+        }
+
+        // control handles only for the segment out of the selected point, its too busy otherwise
+        let anchor = |i: usize| Vec2::new(x_at(self.shape.points[i].t), y_at(self.shape.points[i].level));
+        let mut handles: Vec<(ShapeDrag, Vec2)> = Vec::new();
+        // an undo can take points away from under it too
+        self.selected = self.selected.min(self.shape.n - 1);
+        let sel = self.selected;
+        if sel + 1 < self.shape.n {
+            let (p, q) = (self.shape.points[sel], self.shape.points[sel + 1]);
+            let x_in = |x: f32| lerp(x_at(p.t), x_at(q.t), x);
+            handles.push((ShapeDrag::C1(sel), Vec2::new(x_in(p.c1.x), y_at(p.level + p.c1.y))));
+            handles.push((ShapeDrag::C2(sel), Vec2::new(x_in(p.c2.x), y_at(q.level + p.c2.y))));
+            kc.set_colour(Vec4::new(0.4, 0.4, 0.5, 1.0));
+            kc.line(anchor(sel), handles[0].1, 0.001);
+            kc.line(anchor(sel + 1), handles[1].1, 0.001);
+        }
+        for i in 0..self.shape.n {
+            handles.push((ShapeDrag::Point(i), anchor(i)));
+        }
+
+        let near = |p: Vec2| p.dist(inputs.mouse_pos) < 0.012;
+        let mut changed = false;
+        if r.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::JustPressed {
+            let hit = handles.iter().find(|(_, p)| near(*p)).map(|(h, _)| *h);
+            let hit = match hit {
+                Some(h) => Some(h),
+                // empty space, drop a new point there and keep hold of it
+                None => self.shape.insert(t_at(inputs.mouse_pos.x), level_at(inputs.mouse_pos.y).max(0.0).min(1.0)).map(|i| {
+                    changed = true;
+                    ShapeDrag::Point(i)
+                }),
+            };
+            if let Some(ShapeDrag::Point(i)) = hit {
+                self.selected = i;
+            }
+            self.drag = hit.map(|h| (h, sec_per_w));
+        }
+        if r.contains(inputs.mouse_pos) && inputs.rmb == KeyStatus::JustPressed {
+            if let Some((ShapeDrag::Point(i), _)) = handles.iter().find(|(h, p)| matches!(h, ShapeDrag::Point(_)) && near(*p)) {
+                self.shape.remove(*i);
+                changed = true;
+            }
+        }
+        if inputs.lmb != KeyStatus::Pressed && inputs.lmb != KeyStatus::JustPressed {
+            self.drag = None;
+        }
+
+        kc.set_depth(1.4);
+        for (h, p) in handles.iter() {
+            let active = self.drag.map(|(d, _)| d) == Some(*h);
+            let is_point = matches!(h, ShapeDrag::Point(_));
+            kc.set_colour(if active {
+                Vec4::new(1.0, 0.9, 0.3, 1.0)
+            } else if near(*p) {
+                Vec4::new(1.0, 1.0, 1.0, 1.0)
+            } else if is_point {
+                Vec4::new(0.7, 0.7, 0.8, 1.0)
+            } else {
+                Vec4::new(0.5, 0.5, 0.9, 1.0)
+            });
+            let size = if is_point { 0.008 } else { 0.006 };
+            kc.rect(Rect::new_centered(p.x, p.y, size, size));
+        }
+
+        let h = match self.drag {
+            Some((h, _)) => h,
+            None => return changed,
+        };
+        let m = inputs.mouse_pos;
+        let level = level_at(m.y);
+        match h {
+            ShapeDrag::Point(i) => {
+                if i > 0 {
+                    let lo = self.shape.points[i - 1].t + 0.001;
+                    let hi = if i + 1 < self.shape.n { self.shape.points[i + 1].t - 0.001 } else { 4.0 };
+                    self.shape.points[i].t = t_at(m.x).max(lo).min(hi);
+                }
+                self.shape.points[i].level = level.max(0.0).min(1.0);
+            },
+            ShapeDrag::C1(i) | ShapeDrag::C2(i) => {
+                let (p, q) = (self.shape.points[i], self.shape.points[i + 1]);
+                let x = unlerp(m.x, x_at(p.t), x_at(q.t)).max(0.0).min(1.0);
+                // let them overshoot a bit, the level gets clamped anyway
+                if let ShapeDrag::C1(_) = h {
+                    self.shape.points[i].c1 = Vec2::new(x, (level - p.level).max(-2.0).min(2.0));
+                } else {
+                    self.shape.points[i].c2 = Vec2::new(x, (level - q.level).max(-2.0).min(2.0));
+                }
+            },
+        }
+        true
+    }
+
+    // returns modification
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect) -> bool {
+        ui.push_id("shape");
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.4, 0.4, 0.6, 1.0));
+        ui.kc.rect(rect);
+
+        let (editor, controls) = rect.split_lr(0.8);
+        let inputs = ui.raw_inputs();
+        let mut changed = self.editor(&inputs, ui.kc, editor.dilate_pc(-0.03));
+
+        let (buttons, amount) = controls.split_ud(0.4);
+        let (target_rect, sus_rect) = buttons.split_ud(0.5);
+        if ui.button(self.shape.target.name(), target_rect) {
+            self.shape.target = self.shape.target.next();
+            changed = true;
+        }
+        // sustain on the selected point, or off if its already there
+        let mut sus = self.shape.sustain == Some(self.selected);
+        if ui.toggle("sus", sus_rect, &mut sus) {
+            self.shape.sustain = if sus { Some(self.selected) } else { None };
+            changed = true;
+        }
+        changed |= ui.slider("amt", amount, -1.0, 1.0, &mut self.shape.amount, SliderMapping::Linear, Unit::None);
+        ui.pop_id();
+        changed
+    }
+}
+
+// only the points in use count, whats past n is leftovers from removing
+impl PartialEq for BreakpointEnvelope {
+    fn eq(&self, other: &BreakpointEnvelope) -> bool {
+        self.points() == other.points() && self.sustain == other.sustain && self.target == other.target && self.amount == other.amount
+    }
+}
+
+// the curve gets solved this often and ramped between, solving it is a bisection so not every sample
+pub const SHAPE_BLOCK: u32 = 32;
+
+// per voice position along the shape
+#[derive(Clone, Copy)]
+pub struct BreakpointState {
+    t: f32,         // where the current block ends up
+    released: bool,
+    pub value: f32,
+    target: f32,
+    step: f32,
+    left: u32,      // samples til the block is done
+}
+
+impl BreakpointState {
+    pub fn new() -> BreakpointState {
+        BreakpointState { t: 0.0, released: false, value: 0.0, target: 0.0, step: 0.0, left: 0 }
+    }
+
+    pub fn release(&mut self) {
+        self.released = true;
+    }
+
+    pub fn tick(&mut self, env: &BreakpointEnvelope, sample_rate: f32) -> f32 {
+        if self.left == 0 {
+            let dt = SHAPE_BLOCK as f32 / sample_rate;
+            self.t = match env.sustain {
+                Some(s) if !self.released && self.t <= env.points[s].t => (self.t + dt).min(env.points[s].t),
+                _ => self.t + dt,
+            };
+            self.target = env.level(self.t);
+            self.step = (self.target - self.value) / SHAPE_BLOCK as f32;
+            self.left = SHAPE_BLOCK;
+        }
+        self.left -= 1;
+        // lands exactly on it so a shape that ends at zero is really zero
+        self.value = if self.left == 0 { self.target } else { self.value + self.step };
+        self.value
+    }
+
+    pub fn finished(&self, env: &BreakpointEnvelope) -> bool {
+        self.released && self.t >= env.length() && self.left == 0
+    }
+}
+
+#[test]
+fn test_breakpoints() {
+    let mut e = BreakpointEnvelope::new();
+    // hits every point exactly and eases between them
+    for p in e.points().to_vec() {
+        assert!((e.level(p.t) - p.level).abs() < 1e-4, "{:?} {}", p, e.level(p.t));
+    }
+    assert!((e.level(0.025) - 0.5).abs() < 1e-3);
+    assert!(e.level(0.01) < 0.15);
+    assert_eq!(e.level(10.0), 0.0);
+
+    // straight line handles give a straight line
+    e.points[0].c1 = Vec2::new(1.0 / 3.0, 1.0 / 3.0);
+    e.points[0].c2 = Vec2::new(2.0 / 3.0, -1.0 / 3.0);
+    assert!((e.level(0.01) - 0.2).abs() < 1e-3);
+
+    // inserting and removing keeps the order and the sustain on the same point
+    assert_eq!(e.insert(0.2, 0.7), Some(2));
+    assert_eq!(e.sustain, Some(3));
+    assert!(e.points().windows(2).all(|w| w[0].t < w[1].t));
+    e.remove(2);
+    assert_eq!(e.sustain, Some(2));
+    e.remove(0);
+    assert_eq!(e.points().len(), 4);
+
+    // holds at the sustain point til released then runs out the rest, give or take a block
+    let mut s = BreakpointState::new();
+    for _ in 0..1000 {
+        s.tick(&e, 1000.0);
+    }
+    assert!((s.value - 0.5).abs() < 1e-4);
+    s.release();
+    let mut n = 0;
+    while !s.finished(&e) {
+        s.tick(&e, 1000.0);
+        n += 1;
+    }
+    assert!(n >= 400 && n <= 400 + 2 * SHAPE_BLOCK, "{}", n);
+    assert_eq!(s.value, 0.0);
+
+    // in between blocks its a straight line from one solved point to the next
+    let mut s = BreakpointState::new();
+    let v: Vec<f32> = (0..2 * SHAPE_BLOCK).map(|_| s.tick(&e, 1000.0)).collect();
+    let b = SHAPE_BLOCK as usize;
+    assert!((v[b - 1] - e.level(SHAPE_BLOCK as f32 / 1000.0)).abs() < 1e-6);
+    assert!((v[b + b / 2 - 1] - (v[b - 1] + v[2 * b - 1]) / 2.0).abs() < 1e-4);
+}
@@ -0,0 +1,90 @@
+use crate::midi::*;
+use crate::keymap::*;
+
+// settings that stick around between runs, a line each so its easy to edit by hand:
+//   cc <channel> <cc> <lo> <hi> <control>
+//   keymap <layout>
+//   key <action> <key>
+// control is the path the ui knows it by, like filter/cutoff. actions are octave_up and so on
+pub const CONFIG_PATH: &str = "reeser.cfg";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    pub midi: Vec<(String, MidiBinding)>,
+    pub keymap: Keymap,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config { midi: Vec::new(), keymap: Keymap::new() }
+    }
+
+    // not there is fine, it just hasnt been saved yet
+    pub fn load(path: &str) -> Config {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Config::parse(&s),
+            Err(_) => Config::new(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_text())?;
+        Ok(())
+    }
+
+    pub fn parse(s: &str) -> Config {
+        let mut config = Config::new();
+        for line in s.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["cc", channel, cc, lo, hi, control] => {
+                    match (channel.parse(), cc.parse(), lo.parse(), hi.parse()) {
+                        (Ok(channel), Ok(cc), Ok(lo), Ok(hi)) => {
+                            config.midi.push((control.to_string(), MidiBinding { channel, cc, lo, hi }));
+                        },
+                        _ => eprintln!("{}: bad cc line: {}", CONFIG_PATH, line),
+                    }
+                },
+                ["keymap", layout] => match KeymapLayout::from_name(layout) {
+                    Some(layout) => config.keymap.layout = layout,
+                    None => eprintln!("{}: no keymap called {}", CONFIG_PATH, layout),
+                },
+                ["key", action, key] => {
+                    let ok = key_from_name(key).map_or(false, |k| config.keymap.set_action(action, k));
+                    if !ok {
+                        eprintln!("{}: bad key line: {}", CONFIG_PATH, line);
+                    }
+                },
+                _ => eprintln!("{}: dont know what this is: {}", CONFIG_PATH, line),
+            }
+        }
+        config
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut s = format!("keymap {}\n", self.keymap.layout.name());
+        for (action, k) in self.keymap.actions() {
+            s += &format!("key {} {}\n", action, key_name(k));
+        }
+        for (control, b) in self.midi.iter() {
+            s += &format!("cc {} {} {} {} {}\n", b.channel, b.cc, b.lo, b.hi, control);
+        }
+        s
+    }
+}
+
+#[test]
+fn test_config() {
+    let mut config = Config::new();
+    config.midi.push(("filter/cutoff".to_string(), MidiBinding::new(0, 74)));
+    config.midi.push(("envelope/A".to_string(), MidiBinding { channel: 3, cc: 20, lo: 0.25, hi: 0.0 }));
+    config.keymap.layout = KeymapLayout::Tracker;
+    config.keymap.octave_up = glutin::event::VirtualKeyCode::PageUp;
+    assert_eq!(Config::parse(&config.to_text()), config);
+
+    // comments and rubbish are skipped, the rest still loads
+    let c = Config::parse("# hi\n\ncc 0 1 0 1 volume\ncc x 1 0 1 detune\nwhatever\nkeymap dvorak\nkey octave_up Nope\n");
+    assert_eq!(c.midi, vec![("volume".to_string(), MidiBinding::new(0, 1))]);
+    assert_eq!(c.keymap.layout, KeymapLayout::Dvorak);
+    assert_eq!(c.keymap.octave_up, Keymap::new().octave_up);
+}
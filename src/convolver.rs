@@ -0,0 +1,262 @@
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
+use std::sync::Arc;
+
+// the last however many inputs for a dot product. stored twice so the window is always one
+// contiguous slice, no modulo per tap. sized for the longest kernel it might see so a voice
+// can switch kernels without allocating
+#[derive(Clone)]
+pub struct FirHistory {
+    history: Vec<f32>,
+    head: usize,
+}
+
+impl FirHistory {
+    pub fn new(max_len: usize) -> FirHistory {
+        FirHistory { history: vec![0.0; 2 * max_len.max(1)], head: 0 }
+    }
+
+    pub fn reset(&mut self) {
+        self.history.iter_mut().for_each(|x| *x = 0.0);
+    }
+
+    pub fn tick(&mut self, coeffs: &[f32], x: f32) -> f32 {
+        let cap = self.history.len() / 2;
+        self.head = (self.head + 1) % cap;
+        self.history[self.head] = x;
+        self.history[self.head + cap] = x;
+        // newest is at head + cap
+        let len = coeffs.len().min(cap);
+        coeffs.iter()
+            .zip(self.history[self.head + cap + 1 - len..=self.head + cap].iter().rev())
+            .map(|(h, x)| h * x)
+            .sum()
+    }
+}
+
+// uniformly partitioned overlap-save. the first block of the kernel runs direct form and the
+// rest goes through the fft one block behind, which is exactly when it's needed, so no latency.
+// the kernel is the planned and transformed part, shared, the state is whatever each user needs
+#[derive(Clone)]
+pub struct ConvolverKernel {
+    block: usize,
+    head: Vec<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    ifft: Arc<dyn Fft<f32>>,
+    scratch_len: usize,
+    partitions: Vec<Vec<Complex<f32>>>,     // spectrum of each block of the tail of the kernel
+}
+
+impl ConvolverKernel {
+    pub fn new(kernel: &[f32], block: usize) -> ConvolverKernel {
+        let block = block.max(1);
+        let n = 2 * block;
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(n);
+        let ifft = planner.plan_fft_inverse(n);
+        let scratch_len = fft.get_inplace_scratch_len().max(ifft.get_inplace_scratch_len());
+        let mut scratch = vec![Complex::new(0.0, 0.0); scratch_len];
+
+        let split = block.min(kernel.len());
+        let partitions: Vec<Vec<Complex<f32>>> = kernel[split..].chunks(block).map(|chunk| {
+            let mut buf = vec![Complex::new(0.0, 0.0); n];
+            for (b, h) in buf.iter_mut().zip(chunk) {
+                *b = Complex::new(*h, 0.0);
+            }
+            fft.process_with_scratch(&mut buf, &mut scratch);
+            buf
+        }).collect();
+
+        ConvolverKernel { block, head: kernel[..split].to_vec(), fft, ifft, scratch_len, partitions }
+    }
+
+    pub fn partitions(&self) -> usize {
+        self.partitions.len()
+    }
+}
+
+#[derive(Clone)]
+pub struct ConvolverState {
+    block: usize,
+    head: FirHistory,
+    scratch: Vec<Complex<f32>>,
+    fdl: Vec<Vec<Complex<f32>>>,    // spectra of past input windows, newest at fdl_head
+    fdl_head: usize,
+    input: Vec<f32>,        // last block then the one being filled
+    pos: usize,
+    acc: Vec<Complex<f32>>,
+    tail_out: Vec<f32>,     // tail contribution for the block being filled
+}
+
+impl ConvolverState {
+    // big enough for any kernel with this block size and up to max_partitions. plans an fft
+    // just to see how much scratch it wants, so not on the audio thread
+    pub fn new(block: usize, max_partitions: usize) -> ConvolverState {
+        let block = block.max(1);
+        let n = 2 * block;
+        let mut planner = FftPlanner::<f32>::new();
+        let scratch_len = planner.plan_fft_forward(n).get_inplace_scratch_len()
+            .max(planner.plan_fft_inverse(n).get_inplace_scratch_len());
+        ConvolverState {
+            block,
+            head: FirHistory::new(block),
+            scratch: vec![Complex::new(0.0, 0.0); scratch_len],
+            fdl: vec![vec![Complex::new(0.0, 0.0); n]; max_partitions],
+            fdl_head: 0,
+            input: vec![0.0; n],
+            pos: 0,
+            acc: vec![Complex::new(0.0, 0.0); n],
+            tail_out: vec![0.0; block],
+        }
+    }
+
+    // whether a kernel fits without reallocating
+    pub fn fits(&self, k: &ConvolverKernel) -> bool {
+        k.block == self.block && k.partitions.len() <= self.fdl.len() && k.scratch_len <= self.scratch.len()
+    }
+
+    pub fn reset(&mut self) {
+        self.head.reset();
+        self.fdl.iter_mut().for_each(|w| w.iter_mut().for_each(|x| *x = Complex::new(0.0, 0.0)));
+        self.input.iter_mut().for_each(|x| *x = 0.0);
+        self.tail_out.iter_mut().for_each(|x| *x = 0.0);
+        self.pos = 0;
+        self.fdl_head = 0;
+    }
+
+    pub fn tick(&mut self, k: &ConvolverKernel, x: f32) -> f32 {
+        let y = self.head.tick(&k.head, x) + self.tail_out[self.pos];
+        self.input[self.block + self.pos] = x;
+        self.pos += 1;
+        if self.pos == self.block {
+            self.pos = 0;
+            self.process_block(k);
+        }
+        y
+    }
+
+    // tail(n) = sum h[block + m] x[n - block - m], so the block just finished feeds the next one out
+    fn process_block(&mut self, k: &ConvolverKernel) {
+        let p = k.partitions.len();
+        if p == 0 {
+            return;
+        }
+        let n = 2 * self.block;
+        let scratch = &mut self.scratch[..k.scratch_len];
+        self.fdl_head = (self.fdl_head + 1) % p;
+        let window = &mut self.fdl[self.fdl_head];
+        for (w, x) in window.iter_mut().zip(self.input.iter()) {
+            *w = Complex::new(*x, 0.0);
+        }
+        k.fft.process_with_scratch(window, scratch);
+        self.input.copy_within(self.block.., 0);
+
+        self.acc.iter_mut().for_each(|a| *a = Complex::new(0.0, 0.0));
+        for i in 0..p {
+            let x = &self.fdl[(self.fdl_head + p - i) % p];
+            for ((a, x), h) in self.acc.iter_mut().zip(x).zip(&k.partitions[i]) {
+                *a += x * h;
+            }
+        }
+        k.ifft.process_with_scratch(&mut self.acc, scratch);
+        // overlap-save, the first half wrapped around and is junk
+        for (o, a) in self.tail_out.iter_mut().zip(&self.acc[self.block..]) {
+            *o = a.re / n as f32;
+        }
+    }
+}
+
+// kernel and state together, for when theres only one user like the reverb
+pub struct Convolver {
+    kernel: ConvolverKernel,
+    state: ConvolverState,
+}
+
+impl Convolver {
+    pub fn new(kernel: &[f32], block: usize) -> Convolver {
+        let kernel = ConvolverKernel::new(kernel, block);
+        let state = ConvolverState::new(kernel.block, kernel.partitions());
+        Convolver { kernel, state }
+    }
+
+    pub fn tick(&mut self, x: f32) -> f32 {
+        self.state.tick(&self.kernel, x)
+    }
+}
+
+// mono, channels averaged, for use as a reverb impulse response
+pub fn load_impulse_response(path: &str) -> anyhow::Result<Vec<f32>> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1u32 << (spec.bits_per_sample - 1)) as f32;
+            reader.samples::<i32>().map(|s| s.map(|s| s as f32 * scale)).collect::<Result<_, _>>()?
+        },
+    };
+    let channels = spec.channels.max(1) as usize;
+    Ok(samples.chunks(channels).map(|frame| frame.iter().sum::<f32>() / channels as f32).collect())
+}
+
+// convolution reverb on the master bus
+pub struct Reverb {
+    convolver: Option<Box<Convolver>>,
+    pub mix: f32,
+}
+
+impl Reverb {
+    pub fn new() -> Reverb {
+        Reverb { convolver: None, mix: 0.0 }
+    }
+
+    // hands back the old one, it shouldnt be freed on the audio thread either
+    pub fn set_convolver(&mut self, c: Box<Convolver>) -> Option<Box<Convolver>> {
+        self.convolver.replace(c)
+    }
+
+    pub fn tick(&mut self, dry: f32) -> f32 {
+        match self.convolver.as_mut() {
+            Some(c) => dry * (1.0 - self.mix) + c.tick(dry) * self.mix,
+            None => dry,
+        }
+    }
+}
+
+// scaled so a full scale sine comes out around full scale too, a raw ir can be very hot
+pub fn reverb_convolver(ir: &[f32]) -> Convolver {
+    let energy: f32 = ir.iter().map(|x| x * x).sum::<f32>().sqrt().max(1e-6);
+    let kernel: Vec<f32> = ir.iter().map(|x| x / energy).collect();
+    Convolver::new(&kernel, 256)
+}
+
+#[test]
+fn test_convolver() {
+    let kernel: Vec<f32> = (0..300).map(|i| ((i * 7919) % 113) as f32 / 113.0 - 0.5).collect();
+    let input: Vec<f32> = (0..2000).map(|i| ((i * 104729) % 97) as f32 / 97.0 - 0.5).collect();
+    let expected: Vec<f32> = (0..input.len()).map(|n| {
+        (0..kernel.len()).filter(|k| *k <= n).map(|k| kernel[k] * input[n - k]).sum()
+    }).collect();
+
+    for block in [1, 16, 64, 512] {
+        let mut c = Convolver::new(&kernel, block);
+        for (n, x) in input.iter().enumerate() {
+            let y = c.tick(*x);
+            assert!((y - expected[n]).abs() < 1e-3, "block {} sample {}: {} vs {}", block, n, y, expected[n]);
+        }
+    }
+
+    let mut d = FirHistory::new(kernel.len());
+    for (n, x) in input.iter().enumerate() {
+        assert!((d.tick(&kernel, *x) - expected[n]).abs() < 1e-4);
+    }
+
+    // state made bigger than the kernel needs, like a voice has, gives the same thing
+    let k = ConvolverKernel::new(&kernel, 64);
+    let mut state = ConvolverState::new(64, 16);
+    assert!(state.fits(&k));
+    let mut short = FirHistory::new(512);
+    for (n, x) in input.iter().enumerate() {
+        assert!((state.tick(&k, *x) - expected[n]).abs() < 1e-3);
+        assert!((short.tick(&kernel[..1], *x) - kernel[0] * x).abs() < 1e-6);
+    }
+}
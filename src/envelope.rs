@@ -1,78 +1,427 @@
 use crate::krenderer::*;
 use crate::kinput::*;
 use crate::kmath::*;
-use crate::synth::*;
+use crate::widgets::*;
+use crate::units::*;
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EnvMode {
+    Sustain,    // normal, holds at s til release
+    Loop,       // attack hold decay over and over while held, each attack from s
+    OneShot,    // goes straight into release after decay, note off doesnt matter
+}
+
+impl EnvMode {
+    pub const ALL: [EnvMode; 3] = [EnvMode::Sustain, EnvMode::Loop, EnvMode::OneShot];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EnvMode::Sustain => "sus",
+            EnvMode::Loop => "loop",
+            EnvMode::OneShot => "shot",
+        }
+    }
+}
+
+// 0 is a straight line, positive starts slow and ends fast, negative the other way round
+// (so negative is the usual exponential decay shape)
+pub fn curve(t: f32, k: f32) -> f32 {
+    let t = t.max(0.0).min(1.0);
+    if k.abs() < 1e-3 {
+        return t;
+    }
+    ((k * t).exp() - 1.0) / (k.exp() - 1.0)
+}
+
+fn segment(from: f32, to: f32, t: f32, k: f32) -> f32 {
+    lerp(from, to, curve(t, k))
+}
+
+// which bit of the editor is being dragged
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvHandle {
+    Delay,
+    Attack,
+    Hold,
+    Decay,
+    Release,
+    AttackCurve,
+    DecayCurve,
+    ReleaseCurve,
+}
+
+// times in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Envelope {
+    pub delay: f32,
     pub a: f32,
+    pub hold: f32,
     pub d: f32,
     pub s: f32,
     pub r: f32,
+    pub attack_curve: f32,
+    pub decay_curve: f32,
+    pub release_curve: f32,
+    pub mode: EnvMode,
 }
 
 impl Envelope {
     pub fn new() -> Envelope {
-        Envelope { a: 0.2, d: 0.2, s: 0.7, r: 0.3 }
+        Envelope {
+            delay: 0.0,
+            a: 0.2,
+            hold: 0.0,
+            d: 0.2,
+            s: 0.7,
+            r: 0.3,
+            attack_curve: 0.0,
+            decay_curve: 0.0,
+            release_curve: 0.0,
+            mode: EnvMode::Sustain,
+        }
     }
 
-    // returns modification
-    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, rect: Rect) -> bool {
-        kc.set_depth(1.1);
-        kc.set_colour(Vec4::new(0.4, 0.6, 0.4, 1.0));
-        kc.rect(rect);
+    // level while held, t seconds after the note started from level start
+    pub fn held_level(&self, t: f32, start: f32) -> f32 {
+        if t < self.delay {
+            return start;
+        }
+        let mut t = t - self.delay;
+        let mut start = start;
+        let cycle = self.a + self.hold + self.d;
+        if self.mode == EnvMode::Loop && cycle > 0.0 && t >= cycle {
+            // later rounds come up from the sustain level so theres no jump
+            t %= cycle;
+            start = self.s;
+        }
+        if t < self.a {
+            return segment(start, 1.0, t / self.a, self.attack_curve);
+        }
+        if t < self.a + self.hold {
+            return 1.0;
+        }
+        if t < cycle {
+            return segment(1.0, self.s, (t - self.a - self.hold) / self.d, self.decay_curve);
+        }
+        self.s
+    }
+
+    pub fn release_level(&self, t: f32, from: f32) -> f32 {
+        if t >= self.r {
+            return 0.0;
+        }
+        segment(from, 0.0, t / self.r, self.release_curve)
+    }
+}
+
+// gui side, the envelope plus what the editor is in the middle of
+pub struct EnvelopePanel {
+    pub env: Envelope,
+    drag: Option<(EnvHandle, f32)>,     // handle and seconds per unit width, fixed for the drag
+}
+
+impl EnvelopePanel {
+    pub fn new(env: Envelope) -> EnvelopePanel {
+        EnvelopePanel { env, drag: None }
+    }
+
+    fn draw_handle(&self, kc: &mut KRCanvas, p: Vec2, h: EnvHandle, hover: bool) {
+        let active = self.drag.map(|(d, _)| d) == Some(h);
+        kc.set_colour(if active {
+            Vec4::new(1.0, 0.9, 0.3, 1.0)
+        } else if hover {
+            Vec4::new(1.0, 1.0, 1.0, 1.0)
+        } else {
+            Vec4::new(0.7, 0.7, 0.7, 1.0)
+        });
+        kc.rect(Rect::new_centered(p.x, p.y, 0.008, 0.008));
+    }
+
+    // curve across the top, drag the corners for times and sustain and the middles of the
+    // segments up and down for their shape
+    fn editor(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, r: Rect) -> bool {
         kc.set_depth(1.2);
-        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
-        let (text, sliders) = rect.split_ud(0.15);
-        kc.text_center("envelope".as_bytes(), text);
+        kc.set_colour(Vec4::new(0.1, 0.15, 0.1, 1.0));
+        kc.rect(r);
 
-        let sliders = sliders.split_lrn(4);
-        label_slider("A", sliders[0].dilate_pc(-0.05), 0.0, 1.0, &mut self.a, false, inputs, kc) |
-        label_slider("D", sliders[1].dilate_pc(-0.05), 0.0, 1.0, &mut self.d, false, inputs, kc) |
-        label_slider("S", sliders[2].dilate_pc(-0.05), 0.0, 1.0, &mut self.s, false, inputs, kc) |
-        label_slider("R", sliders[3].dilate_pc(-0.05), 0.0, 1.0, &mut self.r, false, inputs, kc)
+        let sustain_show = 0.3;
+        let total = self.env.delay + self.env.a + self.env.hold + self.env.d + sustain_show + self.env.r;
+        let sec_per_w = match self.drag {
+            Some((_, s)) => s,
+            None => total.max(0.5) / r.w,
+        };
+        let x_at = |t: f32| r.x + t / sec_per_w;
+        let y_at = |level: f32| lerp(r.bot(), r.top(), level);
+
+        let t_attack = self.env.delay;
+        let t_hold = t_attack + self.env.a;
+        let t_decay = t_hold + self.env.hold;
+        let t_sustain = t_decay + self.env.d;
+        let t_release = t_sustain + sustain_show;
+        let t_end = t_release + self.env.r;
+
+        // the curve itself, drawn as the held part then the release off the sustain level
+        let points = 120;
+        let level_at = |t: f32| {
+            if t < t_release {
+                // dont draw loop repeats in the editor, its one round then sustain
+                let mut e = self.env;
+                e.mode = EnvMode::Sustain;
+                e.held_level(t, 0.0)
+            } else {
+                self.env.release_level(t - t_release, self.env.s)
+            }
+        };
+        kc.set_depth(1.3);
+        kc.set_colour(Vec4::new(0.5, 1.0, 0.5, 1.0));
+        for i in 1..points {
+            let t0 = t_end * (i - 1) as f32 / (points - 1) as f32;
+            let t1 = t_end * i as f32 / (points - 1) as f32;
+            if x_at(t1) > r.right() {
+                break;
+            }
+            kc.line(Vec2::new(x_at(t0), y_at(level_at(t0))), Vec2::new(x_at(t1), y_at(level_at(t1))), 0.002);
+        }
+        kc.set_colour(Vec4::new(0.3, 0.4, 0.3, 1.0));
+        kc.rect(Rect::new(x_at(t_release), r.y, 0.001, r.h));
+
+        let handles = [
+            (EnvHandle::Delay, Vec2::new(x_at(t_attack), y_at(0.0))),
+            (EnvHandle::Attack, Vec2::new(x_at(t_hold), y_at(1.0))),
+            (EnvHandle::Hold, Vec2::new(x_at(t_decay), y_at(1.0))),
+            (EnvHandle::Decay, Vec2::new(x_at(t_sustain), y_at(self.env.s))),
+            (EnvHandle::Release, Vec2::new(x_at(t_end), y_at(0.0))),
+            (EnvHandle::AttackCurve, Vec2::new(x_at((t_attack + t_hold) / 2.0), y_at(level_at((t_attack + t_hold) / 2.0)))),
+            (EnvHandle::DecayCurve, Vec2::new(x_at((t_decay + t_sustain) / 2.0), y_at(level_at((t_decay + t_sustain) / 2.0)))),
+            (EnvHandle::ReleaseCurve, Vec2::new(x_at((t_release + t_end) / 2.0), y_at(level_at((t_release + t_end) / 2.0)))),
+        ];
+
+        let near = |p: Vec2| p.dist(inputs.mouse_pos) < 0.012;
+        if inputs.lmb == KeyStatus::JustPressed && r.contains(inputs.mouse_pos) {
+            self.drag = handles.iter().find(|(_, p)| near(*p)).map(|(h, _)| (*h, sec_per_w));
+        }
+        if inputs.lmb != KeyStatus::Pressed && inputs.lmb != KeyStatus::JustPressed {
+            self.drag = None;
+        }
+
+        kc.set_depth(1.4);
+        for (h, p) in handles.iter() {
+            self.draw_handle(kc, *p, *h, near(*p));
+        }
+
+        let (h, _) = match self.drag {
+            Some(d) => d,
+            None => return false,
+        };
+        let m = inputs.mouse_pos;
+        let t = ((m.x - r.x) * sec_per_w).max(0.0);
+        let level = unlerp(m.y, r.bot(), r.top()).max(0.0).min(1.0);
+        // curve handles: pull the middle up or down, relative to the straight line there
+        let bend = |from: f32, to: f32| ((level - (from + to) / 2.0) * 16.0 * if to > from { -1.0 } else { 1.0 }).max(-8.0).min(8.0);
+        match h {
+            EnvHandle::Delay => self.env.delay = t.min(1.0),
+            EnvHandle::Attack => self.env.a = (t - t_attack).max(0.0).min(1.0),
+            EnvHandle::Hold => self.env.hold = (t - t_hold).max(0.0).min(1.0),
+            EnvHandle::Decay => {
+                self.env.d = (t - t_decay).max(0.0).min(1.0);
+                self.env.s = level;
+            },
+            EnvHandle::Release => self.env.r = (t - t_release).max(0.0).min(1.0),
+            EnvHandle::AttackCurve => self.env.attack_curve = bend(0.0, 1.0),
+            EnvHandle::DecayCurve => self.env.decay_curve = bend(1.0, self.env.s),
+            EnvHandle::ReleaseCurve => self.env.release_curve = bend(self.env.s, 0.0),
+        }
+        true
     }
 
-    // maybe needs to smoothstep instead of lerp
-    // no help
-    // plot values maybe
-    // doesnt go negative or anything does it?
-    // lol can we do some sheesh filters, nonlinear filters, averager, moog ladder?? might be easy to parameterize
+    // returns modification
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect) -> bool {
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.4, 0.6, 0.4, 1.0));
+        ui.kc.rect(rect);
+        ui.push_id("envelope");
+
+        let (text, rest) = rect.split_ud(0.1);
+        let (text, mode_rect) = text.split_lr(0.6);
+        ui.label("envelope", text);
+
+        let (editor, sliders) = rest.split_ud(0.45);
+        let inputs = ui.raw_inputs();
+        let mut changed = self.editor(&inputs, ui.kc, editor.dilate_pc(-0.03));
 
-    pub fn amplitude(&self, curr_sample: u32, sample_rate: u32, released_sample: Option<u32>) -> f32 {
-        // +1 for useful recursion
-        let A = self.a * sample_rate as f32;
-        let D = self.d * sample_rate as f32;
-        let S = self.s;
-        let R = self.r * sample_rate as f32;
+        let names: Vec<&str> = EnvMode::ALL.iter().map(|m| m.name()).collect();
+        let mut mode = EnvMode::ALL.iter().position(|m| *m == self.env.mode).unwrap_or(0);
+        if ui.dropdown("mode", mode_rect, &names, &mut mode) {
+            self.env.mode = EnvMode::ALL[mode];
+            changed = true;
+        }
+
+        let sliders = sliders.split_lrn(6);
+        changed |= ui.slider("dl", sliders[0].dilate_pc(-0.05), 0.0, 1.0, &mut self.env.delay, SliderMapping::Linear, Unit::Seconds) |
+        ui.slider("A", sliders[1].dilate_pc(-0.05), 0.0, 1.0, &mut self.env.a, SliderMapping::Linear, Unit::Seconds) |
+        ui.slider("H", sliders[2].dilate_pc(-0.05), 0.0, 1.0, &mut self.env.hold, SliderMapping::Linear, Unit::Seconds) |
+        ui.slider("D", sliders[3].dilate_pc(-0.05), 0.0, 1.0, &mut self.env.d, SliderMapping::Linear, Unit::Seconds) |
+        ui.slider("S", sliders[4].dilate_pc(-0.05), 0.0, 1.0, &mut self.env.s, SliderMapping::Linear, Unit::Percent) |
+        ui.slider("R", sliders[5].dilate_pc(-0.05), 0.0, 1.0, &mut self.env.r, SliderMapping::Linear, Unit::Seconds);
+        ui.pop_id();
+        changed
+    }
+}
+
+// pitch starts off semitones away and falls back to the note over time, for plucks and kicks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PitchEnvelope {
+    pub semitones: f32,
+    pub time: f32,
+    pub curve: f32,
+}
+
+impl PitchEnvelope {
+    pub fn new() -> PitchEnvelope {
+        PitchEnvelope { semitones: 0.0, time: 0.05, curve: -4.0 }
+    }
 
-        if let Some(released_on) = released_sample {
-            let num_released = curr_sample - released_on;
-            let release_value = self.amplitude(released_on, sample_rate, None);
-            return lerp(release_value, 0.0, num_released as f32 / R);
+    // semitones off the note t seconds in
+    pub fn offset(&self, t: f32) -> f32 {
+        if t >= self.time {
+            return 0.0;
         }
-        if curr_sample as f32 <= A {
-            return lerp(0.0, 1.0, curr_sample as f32 / A);
+        segment(self.semitones, 0.0, t / self.time, self.curve)
+    }
+}
+
+// per voice, so a retrigger can pick up from wherever the level is instead of clicking back to 0
+#[derive(Clone, Copy)]
+pub struct EnvelopeState {
+    start: f32,
+    held: u32,
+    released: Option<(f32, u32)>,     // level it was let go at, samples since
+    pub level: f32,
+}
+
+impl EnvelopeState {
+    pub fn new() -> EnvelopeState {
+        EnvelopeState { start: 0.0, held: 0, released: None, level: 0.0 }
+    }
+
+    pub fn retrigger(&mut self) {
+        *self = EnvelopeState { start: self.level, held: 0, released: None, level: self.level };
+    }
+
+    pub fn release(&mut self) {
+        if self.released.is_none() {
+            self.released = Some((self.level, 0));
         }
-        if curr_sample as f32 <= D + A {
-            return lerp(1.0, S, (curr_sample as f32 - A)/D);
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.released.is_some()
+    }
+
+    pub fn tick(&mut self, env: &Envelope, sample_rate: f32) -> f32 {
+        self.level = match self.released.as_mut() {
+            Some((from, n)) => {
+                *n += 1;
+                env.release_level(*n as f32 / sample_rate, *from)
+            },
+            None => {
+                self.held += 1;
+                let t = self.held as f32 / sample_rate;
+                if env.mode == EnvMode::OneShot && t >= env.delay + env.a + env.hold + env.d {
+                    self.released = Some((env.s, 0));
+                }
+                env.held_level(t, self.start)
+            },
+        };
+        self.level
+    }
+
+    pub fn finished(&self, env: &Envelope, sample_rate: f32) -> bool {
+        match self.released {
+            Some((_, n)) => n as f32 > env.r * sample_rate,
+            None => false,
         }
-        S
     }
 }
 
 #[test]
 fn test_env() {
-    let env = Envelope {a: 1.0, d: 1.0, s: 0.5, r: 1.0};
-    assert_eq!(env.amplitude(0, 1000, None), 0.0);
-    assert_eq!(env.amplitude(1000, 1000, None), 1.0);
-    assert_eq!(env.amplitude(500, 1000, None), 0.5);
-    assert_eq!(env.amplitude(1000, 1000, Some(500)), 0.25);
-    assert_eq!(env.amplitude(1500, 1000, Some(500)), 0.0);
-    
-    assert_eq!(env.amplitude(3000, 1000, Some(3000)), 0.5);
-
-    assert_eq!(env.amplitude(2000, 1000, None), 0.5);   
-    assert_eq!(env.amplitude(1100, 1000, None), 0.95);  // l 0.5 r 0.75 // str8 to 0.5
-    assert_eq!(env.amplitude(1500, 1000, None), 0.75);  // l 0.5 r 0.75
-}
\ No newline at end of file
+    let env = Envelope {a: 1.0, d: 1.0, s: 0.5, r: 1.0, ..Envelope::new()};
+    // level after n samples at 1k, let go at sample release
+    let run = |n: u32, release: Option<u32>| {
+        let mut st = EnvelopeState::new();
+        for i in 0..n {
+            if Some(i) == release {
+                st.release();
+            }
+            st.tick(&env, 1000.0);
+        }
+        st.level
+    };
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+    assert_eq!(run(0, None), 0.0);
+    assert!(close(run(1000, None), 1.0));
+    assert!(close(run(500, None), 0.5));
+    assert!(close(run(1000, Some(500)), 0.25));
+    assert_eq!(run(1500, Some(500)), 0.0);
+
+    // let go on the sustain, the release starts from there
+    assert!((run(3001, Some(3000)) - 0.5).abs() < 1e-3);
+
+    assert!(close(run(2000, None), 0.5));
+    assert!(close(run(1100, None), 0.95));  // l 0.5 r 0.75 // str8 to 0.5
+    assert!(close(run(1500, None), 0.75));  // l 0.5 r 0.75
+}
+
+#[test]
+fn test_env_stages() {
+    let env = Envelope {delay: 0.5, a: 1.0, hold: 0.5, d: 1.0, s: 0.5, r: 1.0, decay_curve: -5.0, ..Envelope::new()};
+    assert_eq!(env.held_level(0.25, 0.0), 0.0);
+    assert_eq!(env.held_level(1.0, 0.0), 0.5);
+    assert_eq!(env.held_level(1.75, 0.0), 1.0);
+    // exponential decay is already most of the way down by halfway
+    let mid = env.held_level(2.5, 0.0);
+    assert!(mid < 0.6 && mid > 0.5, "{}", mid);
+    assert_eq!(env.held_level(10.0, 0.0), 0.5);
+
+    let looped = Envelope {mode: EnvMode::Loop, ..env};
+    assert_eq!(looped.held_level(0.5 + 2.5 + 0.5, 0.0), 0.75);
+
+    // retrigger carries on from the current level
+    let mut st = EnvelopeState::new();
+    for _ in 0..2000 {
+        st.tick(&env, 1000.0);
+    }
+    let before = st.level;
+    st.retrigger();
+    assert!((st.tick(&env, 1000.0) - before).abs() < 1e-6);
+
+    // release from wherever it is, not from a recomputed value
+    st.release();
+    let r = st.tick(&env, 1000.0);
+    assert!(r <= before && r > before - 0.01);
+    for _ in 0..1001 {
+        st.tick(&env, 1000.0);
+    }
+    assert!(st.finished(&env, 1000.0));
+
+    let shot = Envelope {mode: EnvMode::OneShot, ..env};
+    let mut st = EnvelopeState::new();
+    for _ in 0..4100 {
+        st.tick(&shot, 1000.0);
+    }
+    assert!(st.finished(&shot, 1000.0));
+}
+
+#[test]
+fn test_pitch_env() {
+    let p = PitchEnvelope { semitones: 24.0, time: 0.1, curve: 0.0 };
+    assert_eq!(p.offset(0.0), 24.0);
+    assert_eq!(p.offset(0.05), 12.0);
+    assert_eq!(p.offset(0.1), 0.0);
+    assert_eq!(p.offset(5.0), 0.0);
+    // fast start is most of the way down by halfway
+    let p = PitchEnvelope { curve: -4.0, ..p };
+    assert!(p.offset(0.05) < 4.0);
+    assert_eq!(PitchEnvelope { time: 0.0, ..p }.offset(0.0), 0.0);
+}
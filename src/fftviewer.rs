@@ -1,79 +1,315 @@
 use crate::kmath::*;
-use crate::krenderer::*;
-use rustfft::num_complex::ComplexFloat;
-use rustfft::{FftPlanner, num_complex::Complex};
+use crate::sound::*;
+use crate::widgets::*;
+use crate::units::*;
+use rustfft::{Fft, FftPlanner, num_complex::Complex};
 use std::f32::consts::PI;
+use std::sync::Arc;
 
-pub fn blackman(n: usize, N: usize) -> f32 {
-    let a0 = 0.42;
-    let a1 = 0.5;
-    let a2 = 0.08;
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Blackman,
+    FlatTop,    // wide main lobe but peak levels read true
+}
+
+impl Window {
+    pub fn coeff(&self, n: usize, len: usize) -> f32 {
+        let x = 2.0 * PI * n as f32 / len as f32;
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Hann => 0.5 - 0.5 * x.cos(),
+            Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+            Window::FlatTop => 0.21557895 - 0.41663158 * x.cos() + 0.277263158 * (2.0 * x).cos()
+                - 0.083578947 * (3.0 * x).cos() + 0.006947368 * (4.0 * x).cos(),
+        }
+    }
 
-    a0 - a1 * (2.0*PI*n as f32/N as f32).cos() + a2 * (4.0*PI*n as f32/N as f32).cos()
+    pub fn next(&self) -> Window {
+        match self {
+            Window::Rectangular => Window::Hann,
+            Window::Hann => Window::Blackman,
+            Window::Blackman => Window::FlatTop,
+            Window::FlatTop => Window::Rectangular,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Window::Rectangular => "rect",
+            Window::Hann => "hann",
+            Window::Blackman => "blackman",
+            Window::FlatTop => "flattop",
+        }
+    }
+}
+
+pub fn to_db(power: f32) -> f32 {
+    10.0 * power.max(1e-20).log10()
 }
 
 pub struct FftViewer {
     samples: Vec<f32>,
     head: usize,
-    downsample: u32,
-    ds_counter: u32,
-}
+    sample_rate: f32,
 
-// maybe if I zero pad I can get more continuous looking - zero padding -> interpolation
+    // planned once, planning every frame was most of the cost
+    fft: Arc<dyn Fft<f32>>,
+    buf: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
 
-// need to rescale
-// dont care pas like 3khz
+    window: Window,
+    window_coeffs: Vec<f32>,
+    window_gain: f32,
 
-// i dont think downsample going in is right
-// although it should be? i want fs from 44100hz to 6000hz so the fft is displaying on a range from 0 to 3000hz
+    hop: usize,         // overlap, run the fft every this many samples
+    since_fft: usize,
 
-// remember to window for spectral leakage as well
+    spectrum: Vec<f32>, // averaged power per bin
+    peaks: Vec<f32>,    // dB
+    columns: Vec<Vec<f32>>, // raw power of each fft since the last take, for the spectrogram
 
-// should this be log?
+    pub averaging: f32,     // 0 is none, towards 1 is slower
+    pub peak_hold: bool,
+    pub floor_db: f32,
+    pub ceil_db: f32,
+    pub min_freq: f32,
+}
 
 impl FftViewer {
     // pref power of 2 len
-    pub fn new(len: usize) -> FftViewer {
-        FftViewer {
+    pub fn new(len: usize, sample_rate: f32) -> FftViewer {
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(len);
+        let scratch = vec![Complex { re: 0.0, im: 0.0 }; fft.get_inplace_scratch_len()];
+        let mut v = FftViewer {
             samples: vec![0.0; len],
             head: 0,
-            downsample: 8,
-            ds_counter: 0,
-        }
+            sample_rate,
+            fft,
+            buf: vec![Complex { re: 0.0, im: 0.0 }; len],
+            scratch,
+            window: Window::Hann,
+            window_coeffs: Vec::new(),
+            window_gain: 1.0,
+            hop: len / 4,
+            since_fft: 0,
+            spectrum: vec![0.0; len / 2],
+            peaks: vec![-200.0; len / 2],
+            columns: Vec::new(),
+            averaging: 0.7,
+            peak_hold: true,
+            floor_db: -90.0,
+            ceil_db: 0.0,
+            min_freq: 20.0,
+        };
+        v.set_window(Window::Hann);
+        v
+    }
+
+    pub fn set_window(&mut self, w: Window) {
+        let n = self.samples.len();
+        self.window = w;
+        self.window_coeffs = (0..n).map(|i| w.coeff(i, n)).collect();
+        // coherent gain, so a full scale sine reads 0dB whatever the window
+        self.window_gain = self.window_coeffs.iter().sum::<f32>() / n as f32;
     }
 
     pub fn tick(&mut self, x: f32) {
-        if self.ds_counter == 0 {
-            self.samples[self.head] = x;
-            self.head = (self.head + 1) % self.samples.len();
+        self.samples[self.head] = x;
+        self.head = (self.head + 1) % self.samples.len();
+        self.since_fft += 1;
+        if self.since_fft >= self.hop {
+            self.since_fft = 0;
+            self.analyze();
         }
-        self.ds_counter = (self.ds_counter + 1) % self.downsample;
     }
 
-    pub fn frame(&self, kc: &mut KRCanvas, r: Rect) {
-        kc.set_depth(1.5);
-        kc.set_colour(Vec4::new(0.4, 0.4, 0.6, 1.0));
-        kc.rect(r);
-        kc.set_depth(1.6);
-        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+    fn analyze(&mut self) {
+        let n = self.samples.len();
+        for i in 0..n {
+            // oldest first
+            let x = self.samples[(self.head + i) % n] * self.window_coeffs[i];
+            self.buf[i] = Complex { re: x, im: 0.0 };
+        }
+        self.fft.process_with_scratch(&mut self.buf, &mut self.scratch);
 
-        let rects = r.split_lrn(self.samples.len() as i32/2);
+        let scale = 2.0 / (n as f32 * self.window_gain);
+        let mut column = Vec::with_capacity(n / 2);
+        for i in 0..n / 2 {
+            let mag = self.buf[i].norm() * scale;
+            let p = mag * mag;
+            column.push(p);
+            self.spectrum[i] = self.averaging * self.spectrum[i] + (1.0 - self.averaging) * p;
+            // peaks fall off slowly
+            self.peaks[i] = (self.peaks[i] - 0.3).max(to_db(p));
+        }
+        // if nobody is taking them dont pile up forever
+        if self.columns.len() < 64 {
+            self.columns.push(column);
+        }
+    }
 
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(self.samples.len());
-        // let mut buf: Vec<Complex<f32>> = self.samples.iter().enumerate().map(|(i, x)| Complex{re: *x * blackman(i, self.samples.len()), im: 0.0}).collect();
-        let mut buf: Vec<Complex<f32>> = self.samples.iter().map(|x| Complex{re: *x, im: 0.0}).collect();
-        // for i in 0..self.samples.len() {
-        //     buf.push(Complex{re: 0.0, im: 0.0});
-        // }
-        fft.process(&mut buf);
-        
-        // and probably need to fftshift as well
-
-        for i in 0..rects.len() {
-            let h = buf[i].abs()/buf.len() as f32;
-            // let h = (h + 1.0).ln();
-            kc.rect(rects[i].child(0.0, 1.0 - h, 1.0, h));
+    pub fn take_columns(&mut self) -> Vec<Vec<f32>> {
+        std::mem::take(&mut self.columns)
+    }
+
+    // strongest bin above min_freq, moved between bins by fitting a parabola through it and its
+    // neighbours in dB. a bin only says its somewhere in a band df wide
+    pub fn loudest(&self) -> (f32, f32) {
+        let df = self.sample_rate / self.samples.len() as f32;
+        let lo = ((self.min_freq / df).ceil() as usize).max(1);
+        let hi = self.spectrum.len() - 1;
+        if lo >= hi {
+            return (0.0, -200.0);
         }
+        let k = (lo..hi).max_by(|a, b| self.spectrum[*a].total_cmp(&self.spectrum[*b])).unwrap();
+        let (a, b, c) = (to_db(self.spectrum[k - 1]), to_db(self.spectrum[k]), to_db(self.spectrum[k + 1]));
+        let denom = a - 2.0 * b + c;
+        let p = if denom.abs() > 1e-9 { (0.5 * (a - c) / denom).max(-0.5).min(0.5) } else { 0.0 };
+        ((k as f32 + p) * df, b - 0.25 * (a - c) * p)
+    }
+
+    // log frequency axis, 0..1 across the panel
+    fn freq_x(&self, f: f32) -> f32 {
+        let max = self.sample_rate / 2.0;
+        (f / self.min_freq).ln() / (max / self.min_freq).ln()
     }
-}
\ No newline at end of file
+
+    fn x_freq(&self, x: f32) -> f32 {
+        let max = self.sample_rate / 2.0;
+        self.min_freq * (max / self.min_freq).powf(x)
+    }
+
+    // loudest of the bins that land in [f0, f1), or the nearest one if none do
+    fn column_db(&self, values: &dyn Fn(usize) -> f32, f0: f32, f1: f32) -> f32 {
+        let df = self.sample_rate / self.samples.len() as f32;
+        let b0 = (f0 / df).round() as usize;
+        let b1 = ((f1 / df).round() as usize).max(b0 + 1).min(self.spectrum.len());
+        (b0..b1).map(|b| values(b)).fold(-200.0, f32::max)
+    }
+
+    pub fn frame(&mut self, ui: &mut Ui, r: Rect) {
+        ui.push_id("fft");
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.4, 0.4, 0.6, 1.0));
+        ui.kc.rect(r);
+
+        let (controls, plot) = r.split_ud(0.3);
+        let (buttons, sliders) = controls.split_ud(0.3);
+        let buttons = buttons.split_lrn(2);
+        if ui.button(self.window.name(), buttons[0]) {
+            self.set_window(self.window.next());
+        }
+        if ui.toggle("peak", buttons[1], &mut self.peak_hold) {
+            self.peaks.iter_mut().for_each(|p| *p = -200.0);
+        }
+        let sliders = sliders.split_lrn(3);
+        ui.slider("floor", sliders[0], -140.0, -30.0, &mut self.floor_db, SliderMapping::Linear, Unit::Db);
+        ui.slider("ceil", sliders[1], -20.0, 20.0, &mut self.ceil_db, SliderMapping::Linear, Unit::Db);
+        ui.slider("avg", sliders[2], 0.0, 0.98, &mut self.averaging, SliderMapping::Linear, Unit::Percent);
+
+        let (plot, axis) = plot.split_ud(0.9);
+        ui.kc.set_depth(1.6);
+        ui.kc.set_colour(Vec4::new(0.1, 0.1, 0.15, 1.0));
+        ui.kc.rect(plot);
+
+        let db_y = |db: f32| {
+            let t = unlerp(db, self.floor_db, self.ceil_db).max(0.0).min(1.0);
+            lerp(plot.bot(), plot.top(), t)
+        };
+
+        // 10dB gridlines
+        ui.kc.set_depth(1.65);
+        ui.kc.set_colour(Vec4::new(0.2, 0.2, 0.3, 1.0));
+        let mut db = (self.floor_db / 10.0).ceil() * 10.0;
+        while db <= self.ceil_db {
+            ui.kc.rect(Rect::new(plot.x, db_y(db), plot.w, 0.001));
+            db += 10.0;
+        }
+
+        // hz labels and a line at each A so you can read off notes
+        ui.kc.set_depth(1.62);
+        ui.kc.set_colour(Vec4::new(0.3, 0.2, 0.2, 1.0));
+        let mut a = 27.5;
+        while a < self.sample_rate / 2.0 {
+            if a >= self.min_freq {
+                let x = lerp(plot.left(), plot.right(), self.freq_x(a));
+                ui.kc.rect(Rect::new(x, plot.y, 0.001, plot.h));
+            }
+            a *= 2.0;
+        }
+        ui.kc.set_depth(1.65);
+        for f in [50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0] {
+            let x = lerp(plot.left(), plot.right(), self.freq_x(f));
+            ui.kc.set_colour(Vec4::new(0.25, 0.25, 0.35, 1.0));
+            ui.kc.rect(Rect::new(x, plot.y, 0.001, plot.h));
+            ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+            let label = if f >= 1000.0 { format!("{}k", f / 1000.0) } else { format!("{}", f) };
+            ui.kc.text_center(label.as_bytes(), Rect::new(x - 0.02, axis.y, 0.04, axis.h));
+        }
+
+        let columns = 200;
+        let w = plot.w / columns as f32;
+        let spectrum = |b: usize| to_db(self.spectrum[b]);
+        let peaks = |b: usize| self.peaks[b];
+        for c in 0..columns {
+            let f0 = self.x_freq(c as f32 / columns as f32);
+            let f1 = self.x_freq((c + 1) as f32 / columns as f32);
+            let x = plot.x + c as f32 * w;
+
+            let db = self.column_db(&spectrum, f0, f1);
+            ui.kc.set_depth(1.7);
+            ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+            let y = db_y(db);
+            ui.kc.rect(Rect::new(x, y, w, plot.bot() - y));
+
+            if self.peak_hold {
+                ui.kc.set_depth(1.75);
+                ui.kc.set_colour(Vec4::new(1.0, 0.6, 0.3, 1.0));
+                ui.kc.rect(Rect::new(x, db_y(self.column_db(&peaks, f0, f1)), w, 0.002));
+            }
+        }
+
+        let (loudest_freq, loudest_db) = self.loudest();
+        if loudest_db > self.floor_db {
+            let note = (12.0 * (loudest_freq / 440.0).log2()).round() as i32;
+            ui.kc.set_depth(1.8);
+            ui.kc.set_colour(Vec4::new(1.0, 1.0, 0.6, 1.0));
+            ui.kc.text_left(format!("{:.0}hz {} {:.0}db", loudest_freq, note_name(note), loudest_db).as_bytes(), plot.child(0.02, 0.02, 1.0, 0.07));
+        }
+        ui.pop_id();
+    }
+}
+
+#[test]
+fn test_analyzer_level() {
+    // half scale sine sitting right on a bin should read -6dB whatever the window
+    for w in [Window::Rectangular, Window::Hann, Window::Blackman, Window::FlatTop] {
+        let mut v = FftViewer::new(1024, 1024.0);
+        v.set_window(w);
+        v.averaging = 0.0;
+        for i in 0..1024 {
+            v.tick(0.5 * (2.0 * PI * 100.0 * i as f32 / 1024.0).sin());
+        }
+        let db = to_db(v.spectrum[100]);
+        assert!((db - -6.02).abs() < 0.1, "{:?} read {}", w, db);
+        assert!(to_db(v.spectrum[300]) < -60.0 || w == Window::Rectangular);
+    }
+}
+
+#[test]
+fn test_loudest() {
+    // halfway between two bins reads as halfway, not the lower bins edge
+    let mut v = FftViewer::new(1024, 1024.0);
+    v.averaging = 0.0;
+    for f in [100.0, 100.5, 237.25] {
+        for i in 0..1024 {
+            v.tick(0.5 * (2.0 * PI * f * i as f32 / 1024.0).sin());
+        }
+        let (freq, db) = v.loudest();
+        assert!((freq - f).abs() < 0.1, "{} read {}", f, freq);
+        assert!(db > -8.0 && db < -5.0, "{}", db);
+    }
+}
@@ -1,151 +1,389 @@
 use std::f32::consts::PI;
 
 use crate::krenderer::*;
-use crate::kinput::*;
 use crate::kmath::*;
-use crate::synth::*;
-use rustfft::{FftPlanner, num_complex::Complex};
+use crate::widgets::*;
+use crate::units::*;
+use crate::fir::*;
+use crate::convolver::*;
+use rustfft::num_complex::Complex;
 
 use plotlib::page::Page;
 use plotlib::repr::Plot;
 use plotlib::view::ContinuousView;
-use plotlib::style::{PointMarker, PointStyle};
+use plotlib::style::{LineStyle, PointMarker, PointStyle};
 
 
 
-pub fn blackmanize(w: &mut Vec<f32>) {
-    let N = w.len();
-    for n in 0..N {
-        let a0 = 0.42;
-        let a1 = 0.5;
-        let a2 = 0.08;
+// past this many taps the fft convolution is cheaper than the dot product
+pub const DIRECT_MAX_TAPS: usize = 64;
+// longest design a voice has room for, the len slider stays well under
+pub const MAX_FILTER_TAPS: usize = 1024;
 
-        let wn = a0 - a1 * (2.0*PI*n as f32/N as f32).cos() + a2 * (4.0*PI*n as f32/N as f32).cos();
-        w[n] *= wn;
-    }
+#[derive(Clone)]
+enum FilterEngine {
+    Direct,
+    Partitioned(ConvolverKernel),
 }
 
-// FIR filter
+// FIR filter. the design, made on the gui side and shared by every voice, each with its own FilterState
 #[derive(Clone)]
 pub struct Filter {
-    len: usize,
-    head: usize,
     coeffs: Vec<f32>,
-    samples: Vec<f32>,
+    engine: FilterEngine,
+}
+
+// per voice, allocated once with room for any design
+#[derive(Clone)]
+pub struct FilterState {
+    direct: FirHistory,
+    partitioned: ConvolverState,
+}
+
+impl FilterState {
+    pub fn new() -> FilterState {
+        let max_partitions = (MAX_FILTER_TAPS - DIRECT_MAX_TAPS + DIRECT_MAX_TAPS - 1) / DIRECT_MAX_TAPS;
+        FilterState {
+            direct: FirHistory::new(DIRECT_MAX_TAPS),
+            partitioned: ConvolverState::new(DIRECT_MAX_TAPS, max_partitions),
+        }
+    }
+
+    pub fn fits(&self, filter: &Filter) -> bool {
+        match &filter.engine {
+            FilterEngine::Direct => true,
+            FilterEngine::Partitioned(k) => self.partitioned.fits(k),
+        }
+    }
+
+    // whats in there belongs to the last design
+    pub fn reset(&mut self) {
+        self.direct.reset();
+        self.partitioned.reset();
+    }
 }
 
 impl Filter {
     pub fn new() -> Filter {
         let coeffs = vec![-0.000000, 0.000016, 0.000055, 0.000092, 0.000099, 0.000062, 0.000000, -0.000028, 0.000076, 0.000431, 0.001127, 0.002181, 0.003483, 0.004785, 0.005721, 0.005890, 0.004985, 0.002929, 0.000000, -0.003123, -0.005419, -0.005694, -0.002844, 0.003849, 0.014463, 0.028291, 0.043872, 0.059199, 0.072072, 0.080528, 0.083237, 0.079782, 0.070741, 0.057562, 0.042256, 0.026988, 0.013662, 0.003600, -0.002633, -0.005216, -0.004910, -0.002798, 0.000000, 0.002562, 0.004305, 0.005019, 0.004804, 0.003955, 0.002830, 0.001738, 0.000879, 0.000327, 0.000056, -0.000020, 0.000000, 0.000039, 0.000054, 0.000040, 0.000014, -0.000000,];
-        Filter {
-            len: 60,
-            head: 0,
-            coeffs,
-            samples: vec![0.0; 60],
-        }
+        Filter::from_coeffs(coeffs)
     }
-    pub fn lowpass(len: usize, fs: f32, fc: f32) -> Filter {
-        let mut ideal = vec![Complex{ re: 0.0f32, im: 0.0f32 }; len];
-        let n_ones = (len as f32 * (fc / fs)) as usize;
-        for i in 0..n_ones {
-            ideal[i] = Complex{re: 1.0, im: 0.0f32};
-        }
-        for i in len - n_ones..len {
-            ideal[i] = Complex{re: 1.0, im: 0.0f32};
-        }
 
-        // this gain is a bit low
-        // probably does need fftshift
-        // what baout no blackman
-
-        // ok kinda my bad for not hooking up the inputs. ideal should be complex.
-
-        // oh sheesh need to set for other side as well?
-        // then take ifft of ideal
-        // then window ideal (pointwise mul)
-        // real to real???
-        // flip it maybe just do complex to complex and chuck out...
-
-        //lol how to actually validate
-
-        // power of 2 fft is best, could restrict filter length to that..
-        // yea dunno what youre playing at, should maek my own.....
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_inverse(len);
-        fft.process(&mut ideal);
-
-        // so this sheesh is wasteful of real-complex, its symmetric after all or should be
-        // not sure about fftshift or whatever
-        // gonna need to plot
-        let mut coeffs: Vec<f32> = ideal.iter().map(|x| x.re / len as f32).collect();
-        // fftshift. nup wasnt it lmao
-        // i think im doing this wrong anyway, meant to swap from ends.? idk
-        for i in 0..len/2 {
-            coeffs.swap(i, i + len/2);
-        }
-        blackmanize(&mut coeffs);
-        let coeffs_sum: f32 = coeffs.iter().sum();
-        for i in 0..len {
-            coeffs[i] /= coeffs_sum;
-        }
-        // hmm suspicious lack of negatives
+    pub fn from_coeffs(mut coeffs: Vec<f32>) -> Filter {
+        coeffs.truncate(MAX_FILTER_TAPS);
+        let engine = if coeffs.len() > DIRECT_MAX_TAPS {
+            FilterEngine::Partitioned(ConvolverKernel::new(&coeffs, DIRECT_MAX_TAPS))
+        } else {
+            FilterEngine::Direct
+        };
+        Filter { coeffs, engine }
+    }
 
-        // and what do you know compiling is starting to take forever too thanks all these libraries
+    // the impulse response of an fir is just its coefficients
+    pub fn coeffs(&self) -> &[f32] {
+        &self.coeffs
+    }
 
+    // H(f) = sum h[n] e^(-j 2pi f n / fs)
+    pub fn response(&self, f: f32, fs: f32) -> Complex<f32> {
+        let w = -2.0 * PI * f / fs;
+        self.coeffs.iter().enumerate()
+            .map(|(n, h)| Complex::from_polar(*h, w * n as f32))
+            .sum()
+    }
 
-        //yea dis filter aint work
-        // probably plot the impulse response to see. now i need a plotting library lol. is it because i iant fft shift?
+    pub fn magnitude_db(&self, f: f32, fs: f32) -> f32 {
+        20.0 * self.response(f, fs).norm().max(1e-10).log10()
+    }
 
-        Filter {
-            len,
-            head: 0,
-            coeffs,
-            samples: vec![0.0; len],
+    // magnitude, phase and impulse response as svgs, path_stem_magnitude.svg etc
+    pub fn write_response_svg(&self, fs: f32, path_stem: &str) -> anyhow::Result<()> {
+        let freqs: Vec<f32> = (0..512).map(|i| 20.0 * (fs / 2.0 / 20.0).powf(i as f32 / 511.0)).collect();
+        let magnitude = Plot::new(freqs.iter().map(|f| (f.log10() as f64, self.magnitude_db(*f, fs).max(-120.0) as f64)).collect())
+            .line_style(LineStyle::new().colour("#2060c0"));
+        let phase = Plot::new(freqs.iter().map(|f| (f.log10() as f64, self.response(*f, fs).arg() as f64)).collect())
+            .line_style(LineStyle::new().colour("#c06020"));
+        let impulse = Plot::new(self.coeffs.iter().enumerate().map(|(n, h)| (n as f64, *h as f64)).collect())
+            .point_style(PointStyle::new().marker(PointMarker::Circle).colour("#208040"));
 
+        let views = [
+            ("magnitude", ContinuousView::new().add(magnitude).y_range(-120.0, 6.0).x_label("log10 hz").y_label("db")),
+            ("phase", ContinuousView::new().add(phase).y_range(-PI as f64, PI as f64).x_label("log10 hz").y_label("radians")),
+            ("impulse", ContinuousView::new().add(impulse).x_label("sample").y_label("h[n]")),
+        ];
+        for (name, view) in views.iter() {
+            // plotlib errors arent std errors
+            Page::single(view).save(format!("{}_{}.svg", path_stem, name))
+                .map_err(|e| anyhow::Error::msg(format!("saving {} plot: {}", name, e)))?;
         }
+        Ok(())
     }
 
-    pub fn tick(&mut self, sample: f32) -> f32 {
-        let mut acc = 0.0;
-        self.head = (self.head + 1) % self.len;
-        self.samples[self.head] = sample;
-        for i in 0..self.len {
-            acc += self.coeffs[i] * self.samples[((self.head + self.len - i) % self.len)];
+    pub fn tick(&self, state: &mut FilterState, sample: f32) -> f32 {
+        match &self.engine {
+            FilterEngine::Direct => state.direct.tick(&self.coeffs, sample),
+            FilterEngine::Partitioned(k) => state.partitioned.tick(k, sample),
         }
-        acc
     }
 }
 
+// state variable lowpass, the trapezoidal (zero delay feedback) kind. the fir cant move once its
+// designed, this one is cheap enough to set the cutoff every sample so envelopes can sweep it,
+// and unlike the chamberlin one it stays stable right up near nyquist
 #[derive(Clone, Copy)]
+pub struct StateVariable {
+    s1: f32,    // integrator states
+    s2: f32,
+}
+
+impl StateVariable {
+    pub fn new() -> StateVariable {
+        StateVariable { s1: 0.0, s2: 0.0 }
+    }
+
+    // damping is 1/q, 2 is no resonance
+    pub fn tick(&mut self, x: f32, cutoff: f32, damping: f32, sample_rate: f32) -> f32 {
+        let g = (PI * cutoff.max(0.0).min(0.45 * sample_rate) / sample_rate).tan();
+        let a1 = 1.0 / (1.0 + g * (g + damping));
+        let a2 = g * a1;
+        let a3 = g * a2;
+        let v3 = x - self.s2;
+        let v1 = a1 * self.s1 + a2 * v3;
+        let v2 = self.s2 + a2 * self.s1 + a3 * v3;
+        self.s1 = 2.0 * v1 - self.s1;
+        self.s2 = 2.0 * v2 - self.s2;
+        v2
+    }
+}
+
+// designing can take a while (equiripple especially) so only redo it when the plan changes.
+// gui side, the audio thread only ever gets finished designs
+pub struct FilterCache {
+    planner: Option<FilterPlanner>,
+    filter: Filter,
+}
+
+impl FilterCache {
+    pub fn new() -> FilterCache {
+        FilterCache { planner: None, filter: Filter::new() }
+    }
+
+    pub fn get(&mut self, planner: FilterPlanner) -> &Filter {
+        if self.planner != Some(planner) {
+            self.filter = planner.design();
+            self.planner = Some(planner);
+        }
+        &self.filter
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub struct FilterPlanner {
     pub fs: f32,
     pub fc: f32,
+    pub width: f32,     // hz above fc for the other edge of bandpass and bandstop
     pub len: f32,
+    pub kind: ResponseKind,
+    pub method: FirMethod,
+    pub window: FirWindow,
+    pub beta: f32,
+    pub transition: f32,
+    pub phase: FirPhase,
 }
 
 impl FilterPlanner {
-    pub fn new() -> FilterPlanner {
-        FilterPlanner { fs: 44100.0, fc: 800.0, len: 64.0 }
+    pub fn new(fs: f32) -> FilterPlanner {
+        FilterPlanner {
+            fs,
+            fc: 800.0,
+            width: 800.0,
+            len: 64.0,
+            kind: ResponseKind::Lowpass,
+            method: FirMethod::WindowedSinc,
+            window: FirWindow::Blackman,
+            beta: 8.0,
+            transition: 200.0,
+            phase: FirPhase::Linear,
+        }
+    }
+
+    pub fn spec(&self) -> FirSpec {
+        FirSpec {
+            kind: self.kind,
+            f1: self.fc,
+            f2: self.fc + self.width,
+            len: (self.len as usize).min(MAX_FILTER_TAPS - 1),
+            method: self.method,
+            window: self.window,
+            beta: self.beta,
+            transition: self.transition,
+            phase: self.phase,
+        }
     }
 
-    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, rect: Rect) -> bool {
-        kc.set_colour(Vec4::new(0.6, 0.4, 0.4, 1.0));
-        kc.set_depth(1.1);
-        kc.rect(rect);
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect, cache: &mut FilterCache) -> bool {
+        ui.kc.set_colour(Vec4::new(0.6, 0.4, 0.4, 1.0));
+        ui.kc.set_depth(1.1);
+        ui.kc.rect(rect);
+        ui.push_id("filter");
+
+        let (t, b) = rect.split_ud(0.1);
+        let (t, svg_rect) = t.split_lr(0.7);
+        ui.label("filter", t);
+
+        let (buttons, b) = b.split_ud(0.12);
+        let buttons = buttons.split_lrn(4);
+        let (b, plots) = b.split_ud(0.5);
+        let sliders = b.split_lrn(4);
+        let mut changed = ui.slider("cutoff", sliders[0].dilate_pc(-0.05), 50.0, 3000.0, &mut self.fc, SliderMapping::Exp, Unit::Hz) |
+        ui.slider("width", sliders[1].dilate_pc(-0.05), 50.0, 5000.0, &mut self.width, SliderMapping::Exp, Unit::Hz) |
+        ui.slider("len", sliders[2].dilate_pc(-0.05), 4.0, 512.0, &mut self.len, SliderMapping::Stepped(1.0), Unit::None);
+        // beta only matters for kaiser, transition only for equiripple
+        changed |= if self.method == FirMethod::Equiripple {
+            ui.slider("trans", sliders[3].dilate_pc(-0.05), 20.0, 2000.0, &mut self.transition, SliderMapping::Exp, Unit::Hz)
+        } else {
+            ui.slider("beta", sliders[3].dilate_pc(-0.05), 0.0, 14.0, &mut self.beta, SliderMapping::Linear, Unit::None)
+        };
+
+        let kinds: Vec<&str> = ResponseKind::ALL.iter().map(|k| k.name()).collect();
+        let mut kind = ResponseKind::ALL.iter().position(|k| *k == self.kind).unwrap_or(0);
+        if ui.dropdown("kind", buttons[0], &kinds, &mut kind) {
+            self.kind = ResponseKind::ALL[kind];
+            changed = true;
+        }
+        let mut method = if self.method == FirMethod::WindowedSinc { 0 } else { 1 };
+        if ui.dropdown("method", buttons[1], &["sinc", "pm"], &mut method) {
+            self.method = if method == 0 { FirMethod::WindowedSinc } else { FirMethod::Equiripple };
+            changed = true;
+        }
+        let windows: Vec<&str> = FirWindow::ALL.iter().map(|w| w.name()).collect();
+        let mut window = FirWindow::ALL.iter().position(|w| *w == self.window).unwrap_or(0);
+        if ui.dropdown("window", buttons[2], &windows, &mut window) {
+            self.window = FirWindow::ALL[window];
+            changed = true;
+        }
+        let mut min_phase = self.phase == FirPhase::Minimum;
+        if ui.toggle("minph", buttons[3], &mut min_phase) {
+            self.phase = if min_phase { FirPhase::Minimum } else { FirPhase::Linear };
+            changed = true;
+        }
+
+        let filter = cache.get(*self);
+        self.plot_response(filter, ui.kc, plots);
 
-        let (t, b) = rect.split_ud(0.15);
-        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        if ui.button("svg", svg_rect.dilate_pc(-0.05)) {
+            if let Err(e) = filter.write_response_svg(self.fs, "filter") {
+                eprintln!("filter: {}", e);
+            }
+        }
+        ui.pop_id();
+        changed
+    }
+
+    // magnitude with phase over it on a log frequency axis, impulse response underneath
+    fn plot_response(&self, filter: &Filter, kc: &mut KRCanvas, r: Rect) {
+        let (freq_rect, impulse_rect) = r.split_ud(0.6);
+        let freq_rect = freq_rect.dilate_pc(-0.04);
+        let impulse_rect = impulse_rect.dilate_pc(-0.04);
         kc.set_depth(1.2);
-        kc.text_center("filter".as_bytes(), t);
+        kc.set_colour(Vec4::new(0.1, 0.1, 0.1, 1.0));
+        kc.rect(freq_rect);
+        kc.rect(impulse_rect);
+
+        let min_f: f32 = 20.0;
+        let max_f = self.fs / 2.0;
+        let x_freq = |t: f32| min_f * (max_f / min_f).powf(t);
+        let freq_x = |f: f32| lerp(freq_rect.left(), freq_rect.right(), (f / min_f).ln() / (max_f / min_f).ln());
 
-        let (l, r) = b.split_lr(0.5);
-        // sliders etc
-        label_slider("cutoff", l.dilate_pc(-0.05), 50.0, 3000.0, &mut self.fc, true, inputs, kc) |
-        label_slider("len", r.dilate_pc(-0.05), 4.0, 512.0, &mut self.len, false, inputs, kc)
+        // cutoff and -3db marks
+        kc.set_depth(1.25);
+        kc.set_colour(Vec4::new(0.5, 0.3, 0.3, 1.0));
+        kc.rect(Rect::new(freq_x(self.fc), freq_rect.y, 0.001, freq_rect.h));
+        let db_y = |db: f32| lerp(freq_rect.top(), freq_rect.bot(), (-db / 90.0).max(0.0).min(1.0));
+        kc.set_colour(Vec4::new(0.3, 0.3, 0.3, 1.0));
+        kc.rect(Rect::new(freq_rect.x, db_y(-3.0), freq_rect.w, 0.001));
+
+        let points = 100;
+        let phase_y = |p: f32| lerp(freq_rect.bot(), freq_rect.top(), (p + PI) / (2.0 * PI));
+        for i in 1..points {
+            let f0 = x_freq((i - 1) as f32 / (points - 1) as f32);
+            let f1 = x_freq(i as f32 / (points - 1) as f32);
+            kc.set_depth(1.3);
+            kc.set_colour(Vec4::new(0.9, 0.6, 0.3, 0.7));
+            let p0 = Vec2::new(freq_x(f0), phase_y(filter.response(f0, self.fs).arg()));
+            let p1 = Vec2::new(freq_x(f1), phase_y(filter.response(f1, self.fs).arg()));
+            // dont draw the vertical jump where phase wraps
+            if (p0.y - p1.y).abs() < freq_rect.h * 0.5 {
+                kc.line(p0, p1, 0.0015);
+            }
+            kc.set_depth(1.35);
+            kc.set_colour(Vec4::new(0.4, 0.7, 1.0, 1.0));
+            kc.line(
+                Vec2::new(freq_x(f0), db_y(filter.magnitude_db(f0, self.fs))),
+                Vec2::new(freq_x(f1), db_y(filter.magnitude_db(f1, self.fs))),
+                0.002,
+            );
+        }
+
+        let h = filter.coeffs();
+        let peak = h.iter().fold(0.0f32, |m, x| m.max(x.abs())).max(1e-10);
+        let mid = impulse_rect.centroid().y;
+        let w = impulse_rect.w / h.len().max(1) as f32;
+        kc.set_depth(1.3);
+        kc.set_colour(Vec4::new(0.4, 0.9, 0.5, 1.0));
+        for (n, x) in h.iter().enumerate() {
+            let y = mid - x / peak * impulse_rect.h / 2.0;
+            kc.rect(Rect::new(impulse_rect.x + n as f32 * w, y.min(mid), w.max(0.001), (y - mid).abs().max(0.001)));
+        }
+    }
 
+    pub fn design(&self) -> Filter {
+        Filter::from_coeffs(self.spec().coeffs(self.fs))
     }
+}
+
+#[test]
+fn test_state_variable() {
+    // passes dc, and wide open it barely touches something well under it
+    let fs = 44100.0;
+    let gain = |fc: f32, f: f32| {
+        let mut svf = StateVariable::new();
+        let mut peak = 0.0f32;
+        for i in 0..8820 {
+            let y = svf.tick((2.0 * PI * f * i as f32 / fs).sin(), fc, 2.0, fs);
+            if i > 4410 {
+                peak = peak.max(y.abs());
+            }
+        }
+        peak
+    };
+    assert!((gain(0.45 * fs, 1000.0) - 1.0).abs() < 0.01);
+    assert!(gain(200.0, 4000.0) < 0.01);
+    assert!((gain(1000.0, 1000.0) - 0.5).abs() < 0.01);   // -6db at the corner with no resonance
+}
 
-    pub fn lowpass(&self) -> Filter {
-        Filter::lowpass(self.len as usize, self.fs, self.fc) 
+#[test]
+fn test_filter_state() {
+    // the longest design fits a voices state, and running it through matches a direct convolution
+    let h: Vec<f32> = (0..MAX_FILTER_TAPS + 10).map(|i| ((i * 31) % 17) as f32 / 17.0 - 0.5).collect();
+    let f = Filter::from_coeffs(h);
+    let mut state = FilterState::new();
+    assert!(matches!(f.engine, FilterEngine::Partitioned(_)));
+    assert!(state.fits(&f));
+    let input: Vec<f32> = (0..3000).map(|i| ((i * 7) % 13) as f32 / 13.0 - 0.5).collect();
+    for (n, x) in input.iter().enumerate() {
+        let expected: f32 = (0..f.coeffs.len().min(n + 1)).map(|k| f.coeffs[k] * input[n - k]).sum();
+        assert!((f.tick(&mut state, *x) - expected).abs() < 1e-3);
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_response() {
+    // two tap average, flat at dc, nothing at nyquist, half a sample of delay
+    let f = Filter::from_coeffs(vec![0.5, 0.5]);
+    assert!((f.response(0.0, 1000.0).norm() - 1.0).abs() < 1e-6);
+    assert!(f.response(500.0, 1000.0).norm() < 1e-6);
+    assert!((f.response(250.0, 1000.0).norm() - 0.5f32.sqrt()).abs() < 1e-6);
+    assert!((f.response(250.0, 1000.0).arg() + PI / 4.0).abs() < 1e-6);
+    assert!((f.magnitude_db(250.0, 1000.0) + 3.0103).abs() < 1e-3);
+}
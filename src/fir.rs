@@ -0,0 +1,457 @@
+use std::f64::consts::PI;
+use rustfft::{FftPlanner, num_complex::Complex};
+
+// fir design. everything comes out odd length and symmetric (type I) unless you ask for minimum phase,
+// that way highpass and bandstop are possible and the delay is a whole number of samples
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseKind {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Bandstop,
+}
+
+impl ResponseKind {
+    pub const ALL: [ResponseKind; 4] = [ResponseKind::Lowpass, ResponseKind::Highpass, ResponseKind::Bandpass, ResponseKind::Bandstop];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResponseKind::Lowpass => "lp",
+            ResponseKind::Highpass => "hp",
+            ResponseKind::Bandpass => "bp",
+            ResponseKind::Bandstop => "bs",
+        }
+    }
+}
+
+// symmetric windows, ie over n/(len-1) not n/len like the analyzer ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FirWindow {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    Kaiser,     // uses FirSpec::beta
+}
+
+impl FirWindow {
+    pub fn coeff(&self, n: usize, len: usize, beta: f32) -> f64 {
+        if len < 2 {
+            return 1.0;
+        }
+        let x = 2.0 * PI * n as f64 / (len - 1) as f64;
+        match self {
+            FirWindow::Rectangular => 1.0,
+            FirWindow::Hann => 0.5 - 0.5 * x.cos(),
+            FirWindow::Hamming => 0.54 - 0.46 * x.cos(),
+            FirWindow::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos(),
+            FirWindow::Kaiser => {
+                let r = 2.0 * n as f64 / (len - 1) as f64 - 1.0;
+                bessel_i0(beta as f64 * (1.0 - r * r).max(0.0).sqrt()) / bessel_i0(beta as f64)
+            },
+        }
+    }
+
+    pub const ALL: [FirWindow; 5] = [FirWindow::Rectangular, FirWindow::Hann, FirWindow::Hamming, FirWindow::Blackman, FirWindow::Kaiser];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FirWindow::Rectangular => "rect",
+            FirWindow::Hann => "hann",
+            FirWindow::Hamming => "hamm",
+            FirWindow::Blackman => "black",
+            FirWindow::Kaiser => "kaiser",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FirMethod {
+    WindowedSinc,
+    Equiripple,     // parks mcclellan
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FirPhase {
+    Linear,
+    Minimum,
+}
+
+// modified bessel function of the first kind, order 0, for the kaiser window
+pub fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)) * (x / (2.0 * k));
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FirSpec {
+    pub kind: ResponseKind,
+    pub f1: f32,            // cutoff, or lower band edge
+    pub f2: f32,            // upper band edge for bandpass and bandstop
+    pub len: usize,         // rounded up to odd
+    pub method: FirMethod,
+    pub window: FirWindow,
+    pub beta: f32,
+    pub transition: f32,    // hz, how wide the gap between pass and stop is for equiripple
+    pub phase: FirPhase,
+}
+
+impl FirSpec {
+    pub fn taps(&self) -> usize {
+        self.len.max(3) | 1
+    }
+
+    pub fn coeffs(&self, fs: f32) -> Vec<f32> {
+        let h = match self.method {
+            FirMethod::WindowedSinc => self.windowed_sinc(fs),
+            FirMethod::Equiripple => self.equiripple(fs),
+        };
+        let h = match self.phase {
+            FirPhase::Linear => h,
+            FirPhase::Minimum => minimum_phase(&h),
+        };
+        h.iter().map(|x| *x as f32).collect()
+    }
+
+    // normalized band edges, clamped so they stay in order and under nyquist
+    fn edges(&self, fs: f32) -> (f64, f64) {
+        let f1 = (self.f1 / fs).max(0.0).min(0.5) as f64;
+        let f2 = (self.f2 / fs).max(0.0).min(0.5) as f64;
+        (f1.min(f2), f1.max(f2))
+    }
+
+    // ideal response truncated and windowed, then scaled for unity gain in the middle of the passband
+    fn windowed_sinc(&self, fs: f32) -> Vec<f64> {
+        let n = self.taps();
+        let (f1, f2) = self.edges(fs);
+        let mid = (n - 1) as f64 / 2.0;
+        // ideal lowpass at fc, 2fc sinc(2fc m)
+        let lp = |fc: f64, m: f64| if m == 0.0 { 2.0 * fc } else { (2.0 * PI * fc * m).sin() / (PI * m) };
+        let delta = |m: f64| if m == 0.0 { 1.0 } else { 0.0 };
+        let mut h: Vec<f64> = (0..n).map(|i| {
+            let m = i as f64 - mid;
+            let ideal = match self.kind {
+                ResponseKind::Lowpass => lp(f1, m),
+                ResponseKind::Highpass => delta(m) - lp(f1, m),
+                ResponseKind::Bandpass => lp(f2, m) - lp(f1, m),
+                ResponseKind::Bandstop => delta(m) - lp(f2, m) + lp(f1, m),
+            };
+            ideal * self.window.coeff(i, n, self.beta)
+        }).collect();
+
+        let reference = match self.kind {
+            ResponseKind::Lowpass | ResponseKind::Bandstop => 0.0,
+            ResponseKind::Highpass => 0.5,
+            ResponseKind::Bandpass => (f1 + f2) / 2.0,
+        };
+        let gain = gain_at(&h, reference);
+        if gain > 1e-9 {
+            h.iter_mut().for_each(|x| *x /= gain);
+        }
+        h
+    }
+
+    // (lo, hi, desired) bands, normalized. transition is split either side of each edge
+    fn bands(&self, fs: f32) -> Vec<(f64, f64, f64)> {
+        let (f1, f2) = self.edges(fs);
+        let tw = (self.transition / fs) as f64 / 2.0;
+        let bands = match self.kind {
+            ResponseKind::Lowpass => vec![(0.0, f1 - tw, 1.0), (f1 + tw, 0.5, 0.0)],
+            ResponseKind::Highpass => vec![(0.0, f1 - tw, 0.0), (f1 + tw, 0.5, 1.0)],
+            ResponseKind::Bandpass => vec![(0.0, f1 - tw, 0.0), (f1 + tw, f2 - tw, 1.0), (f2 + tw, 0.5, 0.0)],
+            ResponseKind::Bandstop => vec![(0.0, f1 - tw, 1.0), (f1 + tw, f2 - tw, 0.0), (f2 + tw, 0.5, 1.0)],
+        };
+        // drop any band the transition ate
+        bands.into_iter()
+            .map(|(lo, hi, d)| (lo.max(0.0), hi.min(0.5), d))
+            .filter(|(lo, hi, _)| hi > lo)
+            .collect()
+    }
+
+    // remez exchange on A(w) = sum a_k cos(kw), equal weights
+    fn equiripple(&self, fs: f32) -> Vec<f64> {
+        let n = self.taps();
+        let l = (n - 1) / 2;
+        let r = l + 1;
+        let bands = self.bands(fs);
+        if bands.is_empty() {
+            return self.windowed_sinc(fs);
+        }
+
+        // dense grid over the bands only
+        let total: f64 = bands.iter().map(|(lo, hi, _)| hi - lo).sum();
+        let grid_len = (16 * r).max(bands.len() * 4);
+        let mut grid: Vec<(f64, f64)> = Vec::with_capacity(grid_len + bands.len());
+        for (lo, hi, d) in bands.iter() {
+            let pts = (((hi - lo) / total) * grid_len as f64).ceil().max(2.0) as usize;
+            for i in 0..pts {
+                grid.push((lo + (hi - lo) * i as f64 / (pts - 1) as f64, *d));
+            }
+        }
+        if grid.len() < r + 1 {
+            return self.windowed_sinc(fs);
+        }
+        let x_of = |f: f64| (2.0 * PI * f).cos();
+
+        let mut ext: Vec<usize> = (0..=r).map(|i| i * (grid.len() - 1) / r).collect();
+        let mut interp = Interpolator::new(&[], &[]);
+        for _ in 0..50 {
+            let xs: Vec<f64> = ext.iter().map(|i| x_of(grid[*i].0)).collect();
+            let ds: Vec<f64> = ext.iter().map(|i| grid[*i].1).collect();
+            let b = barycentric_weights(&xs);
+            let num: f64 = (0..=r).map(|i| b[i] * ds[i]).sum();
+            let den: f64 = (0..=r).map(|i| b[i] * if i % 2 == 0 { 1.0 } else { -1.0 }).sum();
+            let delta = num / den;
+            let cs: Vec<f64> = (0..r).map(|i| ds[i] - if i % 2 == 0 { delta } else { -delta }).collect();
+            interp = Interpolator::new(&xs[..r], &cs);
+
+            let err: Vec<f64> = grid.iter().map(|(f, d)| d - interp.eval(x_of(*f))).collect();
+
+            // local extrema of the error, then thin them out til the signs alternate. compared signed,
+            // a small bump next to a big one of the other sign still counts
+            let mut cands: Vec<usize> = Vec::new();
+            for i in 0..grid.len() {
+                let s = err[i].signum();
+                let left = i == 0 || s * err[i] >= s * err[i - 1] || grid[i - 1].1 != grid[i].1;
+                let right = i == grid.len() - 1 || s * err[i] > s * err[i + 1] || grid[i + 1].1 != grid[i].1;
+                if left && right {
+                    cands.push(i);
+                }
+            }
+            let mut alt: Vec<usize> = Vec::new();
+            for i in cands {
+                match alt.last() {
+                    Some(&j) if err[j].signum() == err[i].signum() => {
+                        if err[i].abs() > err[j].abs() {
+                            *alt.last_mut().unwrap() = i;
+                        }
+                    },
+                    _ => alt.push(i),
+                }
+            }
+            while alt.len() > r + 1 {
+                if err[alt[0]].abs() < err[alt[alt.len() - 1]].abs() {
+                    alt.remove(0);
+                } else {
+                    alt.pop();
+                }
+            }
+            if alt.len() < r + 1 {
+                break;
+            }
+
+            let max_err = alt.iter().map(|i| err[*i].abs()).fold(0.0, f64::max);
+            let done = alt == ext || (max_err - delta.abs()) <= 1e-6 * max_err.max(1e-12);
+            ext = alt;
+            if done {
+                break;
+            }
+        }
+
+        // sample A at the dft frequencies and take the cosine series back out
+        let a: Vec<f64> = (0..=l).map(|k| interp.eval(x_of(k as f64 / n as f64))).collect();
+        (0..n).map(|i| {
+            let m = i as f64 - l as f64;
+            let mut acc = a[0];
+            for k in 1..=l {
+                acc += 2.0 * a[k] * (2.0 * PI * k as f64 * m / n as f64).cos();
+            }
+            acc / n as f64
+        }).collect()
+    }
+}
+
+// |H(f)| with f as a fraction of the sample rate
+pub fn gain_at(h: &[f64], f: f64) -> f64 {
+    let w = -2.0 * PI * f;
+    let (re, im) = h.iter().enumerate().fold((0.0, 0.0), |(re, im), (n, x)| {
+        (re + x * (w * n as f64).cos(), im + x * (w * n as f64).sin())
+    });
+    (re * re + im * im).sqrt()
+}
+
+fn barycentric_weights(xs: &[f64]) -> Vec<f64> {
+    // the factor of 2 keeps the products from underflowing for long filters
+    (0..xs.len()).map(|i| {
+        1.0 / (0..xs.len()).filter(|j| *j != i).map(|j| 2.0 * (xs[i] - xs[j])).product::<f64>()
+    }).collect()
+}
+
+struct Interpolator {
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    ws: Vec<f64>,
+}
+
+impl Interpolator {
+    fn new(xs: &[f64], ys: &[f64]) -> Interpolator {
+        Interpolator { xs: xs.to_vec(), ys: ys.to_vec(), ws: barycentric_weights(xs) }
+    }
+
+    fn eval(&self, x: f64) -> f64 {
+        let mut num = 0.0;
+        let mut den = 0.0;
+        for i in 0..self.xs.len() {
+            let d = x - self.xs[i];
+            if d.abs() < 1e-12 {
+                return self.ys[i];
+            }
+            num += self.ws[i] / d * self.ys[i];
+            den += self.ws[i] / d;
+        }
+        if den == 0.0 { 0.0 } else { num / den }
+    }
+}
+
+// same magnitude, all the delay squashed to the front. homomorphic method: fold the real
+// cepstrum of log|H| onto the causal side and exponentiate back
+pub fn minimum_phase(h: &[f64]) -> Vec<f64> {
+    let n = (h.len() * 16).next_power_of_two();
+    let mut planner = FftPlanner::<f64>::new();
+    let fft = planner.plan_fft_forward(n);
+    let ifft = planner.plan_fft_inverse(n);
+
+    let mut buf: Vec<Complex<f64>> = (0..n).map(|i| Complex::new(*h.get(i).unwrap_or(&0.0), 0.0)).collect();
+    fft.process(&mut buf);
+    // floor it so the stopband nulls dont go to -inf
+    buf.iter_mut().for_each(|c| *c = Complex::new(c.norm().max(1e-10).ln(), 0.0));
+    ifft.process(&mut buf);
+    for i in 0..n {
+        let fold = if i == 0 || i == n / 2 { 1.0 } else if i < n / 2 { 2.0 } else { 0.0 };
+        buf[i] = buf[i] / n as f64 * fold;
+    }
+    fft.process(&mut buf);
+    buf.iter_mut().for_each(|c| *c = c.exp());
+    ifft.process(&mut buf);
+    buf.iter().take(h.len()).map(|c| c.re / n as f64).collect()
+}
+
+// kaisers empirical formulas: beta for a stopband attenuation in dB, and the length to get it
+// with a transition band tw wide (as a fraction of the sample rate)
+#[cfg(test)]
+fn kaiser_beta(atten_db: f32) -> f32 {
+    if atten_db > 50.0 {
+        0.1102 * (atten_db - 8.7)
+    } else if atten_db > 21.0 {
+        0.5842 * (atten_db - 21.0).powf(0.4) + 0.07886 * (atten_db - 21.0)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+fn kaiser_len(atten_db: f32, tw: f32) -> usize {
+    (((atten_db - 8.0) / (2.285 * 2.0 * std::f32::consts::PI * tw)).ceil() as usize + 1) | 1
+}
+
+#[cfg(test)]
+fn lowpass_spec(len: usize, fc: f32) -> FirSpec {
+    FirSpec {
+        kind: ResponseKind::Lowpass,
+        f1: fc,
+        f2: fc,
+        len,
+        method: FirMethod::WindowedSinc,
+        window: FirWindow::Blackman,
+        beta: 8.0,
+        transition: fc * 0.5,
+        phase: FirPhase::Linear,
+    }
+}
+
+#[cfg(test)]
+fn response_db(h: &[f32], f: f64) -> f64 {
+    let h: Vec<f64> = h.iter().map(|x| *x as f64).collect();
+    20.0 * gain_at(&h, f).max(1e-12).log10()
+}
+
+// worst case over a band, (min, max) dB
+#[cfg(test)]
+fn band_db(h: &[f32], lo: f64, hi: f64) -> (f64, f64) {
+    (0..=200).map(|i| response_db(h, lo + (hi - lo) * i as f64 / 200.0))
+        .fold((f64::MAX, f64::MIN), |(a, b), x| (a.min(x), b.max(x)))
+}
+
+#[test]
+fn test_windowed_sinc() {
+    // kaiser for 60dB with a 100hz transition at 8k
+    let fs = 8000.0;
+    let atten = 60.0;
+    let mut spec = lowpass_spec(kaiser_len(atten, 100.0 / fs), 1000.0);
+    spec.window = FirWindow::Kaiser;
+    spec.beta = kaiser_beta(atten);
+    let h = spec.coeffs(fs);
+    assert_eq!(h.len() % 2, 1);
+    for i in 0..h.len() {
+        assert!((h[i] - h[h.len() - 1 - i]).abs() < 1e-6);
+    }
+    let (lo, hi) = band_db(&h, 0.0, 950.0 / 8000.0);
+    assert!(lo > -0.02 && hi < 0.02, "passband {} {}", lo, hi);
+    assert!(band_db(&h, 1050.0 / 8000.0, 0.5).1 < -59.0);
+    assert!((response_db(&h, 1000.0 / 8000.0) + 6.02).abs() < 0.1);
+
+    spec.kind = ResponseKind::Highpass;
+    let h = spec.coeffs(fs);
+    assert!(band_db(&h, 0.0, 950.0 / 8000.0).1 < -59.0);
+    assert!(band_db(&h, 1050.0 / 8000.0, 0.5).0 > -0.02);
+
+    spec.kind = ResponseKind::Bandpass;
+    spec.f2 = 2000.0;
+    let h = spec.coeffs(fs);
+    assert!(band_db(&h, 0.0, 950.0 / 8000.0).1 < -59.0);
+    assert!(band_db(&h, 1050.0 / 8000.0, 1950.0 / 8000.0).0 > -0.02);
+    assert!(band_db(&h, 2050.0 / 8000.0, 0.5).1 < -59.0);
+
+    spec.kind = ResponseKind::Bandstop;
+    let h = spec.coeffs(fs);
+    assert!(band_db(&h, 0.0, 950.0 / 8000.0).0 > -0.02);
+    assert!(band_db(&h, 1050.0 / 8000.0, 1950.0 / 8000.0).1 < -59.0);
+    assert!(band_db(&h, 2050.0 / 8000.0, 0.5).0 > -0.02);
+}
+
+#[test]
+fn test_equiripple() {
+    let fs = 1000.0;
+    let mut spec = lowpass_spec(101, 100.0);
+    spec.method = FirMethod::Equiripple;
+    spec.transition = 40.0;
+    let h = spec.coeffs(fs);
+    assert_eq!(h.len(), 101);
+    // pm for this length and transition should do better than 60dB, ripple the same either side
+    let (plo, phi) = band_db(&h, 0.0, 0.08);
+    let (_, stop) = band_db(&h, 0.12, 0.5);
+    assert!(plo > -0.01 && phi < 0.01, "passband {} {}", plo, phi);
+    assert!(stop < -60.0, "stopband {}", stop);
+
+    spec.kind = ResponseKind::Bandpass;
+    spec.f2 = 250.0;
+    let h = spec.coeffs(fs);
+    assert!(band_db(&h, 0.0, 0.08).1 < -50.0);
+    assert!(band_db(&h, 0.12, 0.23).0 > -0.05);
+    assert!(band_db(&h, 0.27, 0.5).1 < -50.0);
+}
+
+#[test]
+fn test_minimum_phase() {
+    let mut spec = lowpass_spec(63, 8000.0);
+    let linear = spec.coeffs(44100.0);
+    spec.phase = FirPhase::Minimum;
+    let min = spec.coeffs(44100.0);
+    assert_eq!(min.len(), linear.len());
+    for f in [0.0, 0.05, 0.1, 0.15, 0.18] {
+        assert!((response_db(&min, f) - response_db(&linear, f)).abs() < 0.1, "at {}", f);
+    }
+    assert!(band_db(&min, 0.25, 0.5).1 < -70.0);
+    // energy up front instead of in the middle
+    let centre = |h: &[f32]| h.iter().enumerate().map(|(i, x)| i as f32 * x * x).sum::<f32>() / h.iter().map(|x| x * x).sum::<f32>();
+    assert!(centre(&min) < 10.0);
+    assert!((centre(&linear) - 31.0).abs() < 0.01);
+}
@@ -0,0 +1,115 @@
+use crate::envelope::*;
+use crate::breakpoints::*;
+use crate::filter::*;
+use crate::unison::*;
+
+// everything that makes up the sound, as opposed to what the panels are showing
+#[derive(Clone, Copy, PartialEq)]
+pub struct Patch {
+    pub envelope: Envelope,
+    pub shape: BreakpointEnvelope,
+    pub pitch_env: PitchEnvelope,
+    pub filter: FilterPlanner,
+    pub layers: [Layer; MAX_LAYERS],
+    pub amplitude: f32,
+    pub voices: f32,
+    pub detune: f32,
+    pub reverb_mix: f32,
+}
+
+// how long something has to sit still before its a step, so a drag or a knob twist is one
+pub const SETTLE: f64 = 0.3;
+const MAX_STEPS: usize = 200;
+
+// undo by snapshots. whoever owns it hands over where things are every frame and it works out
+// the steps itself, so nothing that changes a value has to know about it
+pub struct History<T> {
+    undo: Vec<T>,
+    redo: Vec<T>,
+    committed: T,               // top of the undo stack, what undo goes back from
+    seen: Option<(T, f64)>,     // uncommitted change and when it last moved
+}
+
+impl<T: Clone + PartialEq> History<T> {
+    pub fn new(initial: T) -> History<T> {
+        History { undo: Vec::new(), redo: Vec::new(), committed: initial, seen: None }
+    }
+
+    // busy is something still being held, dont cut a drag in half even if it pauses
+    pub fn track(&mut self, current: &T, t: f64, busy: bool) {
+        if *current == self.committed {
+            self.seen = None;
+            return;
+        }
+        match &self.seen {
+            Some((s, _)) if s == current => {},
+            _ => self.seen = Some((current.clone(), t)),
+        }
+        let moved = self.seen.as_ref().map_or(t, |(_, moved)| *moved);
+        if !busy && t - moved >= SETTLE {
+            self.commit(current);
+        }
+    }
+
+    fn commit(&mut self, current: &T) {
+        let before = std::mem::replace(&mut self.committed, current.clone());
+        self.undo.push(before);
+        if self.undo.len() > MAX_STEPS {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        self.seen = None;
+    }
+
+    // what to go back to, if theres anything. a change that hasnt settled yet counts as a step
+    pub fn undo(&mut self, current: &T) -> Option<T> {
+        if *current != self.committed {
+            self.commit(current);
+        }
+        let prev = self.undo.pop()?;
+        self.redo.push(std::mem::replace(&mut self.committed, prev.clone()));
+        Some(prev)
+    }
+
+    pub fn redo(&mut self, current: &T) -> Option<T> {
+        // changing something after undoing throws the redos away, same as anywhere else
+        if *current != self.committed {
+            self.commit(current);
+            return None;
+        }
+        let next = self.redo.pop()?;
+        self.undo.push(std::mem::replace(&mut self.committed, next.clone()));
+        Some(next)
+    }
+}
+
+#[test]
+fn test_history() {
+    let mut h = History::new(0);
+    // a drag through a few values with pauses is one step once let go and settled
+    h.track(&1, 0.0, true);
+    h.track(&2, 0.1, true);
+    h.track(&3, 1.0, true);
+    h.track(&3, 1.1, false);
+    h.track(&3, 1.4, false);
+    h.track(&5, 2.0, false);
+    h.track(&5, 2.5, false);
+    assert_eq!(h.undo(&5), Some(3));
+    assert_eq!(h.undo(&3), Some(0));
+    assert_eq!(h.undo(&0), None);
+    assert_eq!(h.redo(&0), Some(3));
+    assert_eq!(h.redo(&3), Some(5));
+    assert_eq!(h.redo(&5), None);
+
+    // undo straight after a change, before its settled, still goes back past it
+    h.track(&7, 3.0, false);
+    assert_eq!(h.undo(&7), Some(5));
+    assert_eq!(h.redo(&5), Some(7));
+
+    // a new change after an undo loses the redo
+    assert_eq!(h.undo(&7), Some(5));
+    h.track(&9, 4.0, false);
+    h.track(&9, 5.0, false);
+    assert_eq!(h.redo(&9), None);
+    assert_eq!(h.undo(&9), Some(5));
+}
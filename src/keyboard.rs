@@ -1,106 +1,171 @@
 use crate::krenderer::*;
 use crate::kinput::*;
 use crate::kmath::*;
+use crate::tuning::*;
+use crate::keymap::*;
+use crate::widgets::*;
+use crate::midi::*;
+use crate::sequencer::SEQUENCER_ID_BIT;
+use crate::arpeggiator::ARP_ID_BIT;
 use glutin::event::VirtualKeyCode;
 
-const keys: [VirtualKeyCode; 23] = [
-    VirtualKeyCode::Tab,    // A
-    VirtualKeyCode::Key1,   // Bb
-    VirtualKeyCode::Q,      // B
-    VirtualKeyCode::W,      // C
-    VirtualKeyCode::Key3,   // Db
-    VirtualKeyCode::E,      // D
-    VirtualKeyCode::Key4,   // Eb
-    VirtualKeyCode::R,      // E
-    VirtualKeyCode::T,      // F
-    VirtualKeyCode::Key6,   // Gb
-    VirtualKeyCode::Y,      // G
-    VirtualKeyCode::Key7,   // Ab
-    VirtualKeyCode::U,      // A
-    VirtualKeyCode::Key8,   // Bb
-    VirtualKeyCode::I,      // B
-    VirtualKeyCode::O,      // C
-    VirtualKeyCode::Key0,   // Db
-    VirtualKeyCode::P,      // D
-    VirtualKeyCode::Minus,       // Eb
-    VirtualKeyCode::LBracket,   // E
-    VirtualKeyCode::RBracket,   // F
-    VirtualKeyCode::Back,  // Gb
-    VirtualKeyCode::Backslash,  // G
-];
-const white_keys: [VirtualKeyCode; 14] = [
-    VirtualKeyCode::Tab,    // A
-    VirtualKeyCode::Q,      // B
-    VirtualKeyCode::W,      // C
-    VirtualKeyCode::E,      // D
-    VirtualKeyCode::R,      // E
-    VirtualKeyCode::T,      // F
-    VirtualKeyCode::Y,      // G
-    VirtualKeyCode::U,      // A
-    VirtualKeyCode::I,      // B
-    VirtualKeyCode::O,      // C
-    VirtualKeyCode::P,      // D
-    VirtualKeyCode::LBracket,   // E
-    VirtualKeyCode::RBracket,   // F
-    VirtualKeyCode::Backslash,  // G
-];
-const black_keys: [VirtualKeyCode; 9] = [
-    VirtualKeyCode::Key1,   // Bb
-    VirtualKeyCode::Key3,   // Db
-    VirtualKeyCode::Key4,   // Eb
-    VirtualKeyCode::Key6,   // Gb
-    VirtualKeyCode::Key7,   // Ab
-    VirtualKeyCode::Key8,   // Bb
-    VirtualKeyCode::Key0,   // Db
-    VirtualKeyCode::Minus,       // Eb
-    VirtualKeyCode::Back,       // Gb
-];
+// how much of the piano is on screen at once, scrolls an octave a notch
+const VIEW_OCTAVES: i32 = 4;
 
 pub struct Keyboard {
+    pub tuning: Tuning,
+    pub keymap: Keymap,
     current_octave: i32,
-    held_keys: Vec<u32>,
-    counters: Vec<u32>,
+    transpose: i32,
+    held_keys: Vec<(VirtualKeyCode, i32, u32)>,  // key, the note it played and the uid that went out with it
+    mouse_note: Option<(i32, u32)>,
+    mouse_playing: bool,    // the press started on the keys, dragging in from elsewhere doesnt play
+    midi_notes: Vec<(u8, u8, u32)>,     // channel note uid
+    midi_events: Vec<KeyboardEvent>,    // from midi since last frame
+    view_lo: i32,   // leftmost drawn key, always a C
+    midi_pedals: (bool, bool),
+    pub sustain: bool,      // from either the pedal keys or a controller
+    pub sostenuto: bool,
+    counter: u32,
+    tap: Option<VirtualKeyCode>,    // modifier octave key pressed with nothing else since
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct KeyboardEvent {
     pub uid: u32,
     pub freq: f32,
+    pub velocity: f32,  // 0 to 1, 0 for releases
     pub pressed: bool, // else released
 }
 
+// midi note numbers, C is 0
+fn is_black(note: i32) -> bool {
+    matches!(note.rem_euclid(12), 1 | 3 | 6 | 8 | 10)
+}
+
 impl Keyboard {
     pub fn new() -> Keyboard {
         Keyboard {
+            tuning: Tuning::new(),
+            keymap: Keymap::new(),
             current_octave: 0,
+            transpose: 0,
             held_keys: Vec::new(),
-            counters: vec![0; keys.len()],
+            mouse_note: None,
+            mouse_playing: false,
+            midi_notes: Vec::new(),
+            midi_events: Vec::new(),
+            view_lo: 48,
+            midi_pedals: (false, false),
+            sustain: false,
+            sostenuto: false,
+            counter: 0,
+            tap: None,
         }
     }
 
     // lets plot fft
 
-    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, rect: Rect) -> Vec<KeyboardEvent> {
-        let mut events = Vec::new();
+    fn release_all(&mut self, events: &mut Vec<KeyboardEvent>) {
+        for (_, _, uid) in self.held_keys.drain(..) {
+            events.push(KeyboardEvent { uid, freq: 0.0, velocity: 0.0, pressed: false });
+        }
+    }
+
+    // None if the tuning leaves it unmapped
+    fn press(&mut self, note: i32, velocity: f32) -> Option<KeyboardEvent> {
+        let freq = self.tuning.freq(note)?;
+        self.counter += 1;
+        // the sequencer and arp have the top two bits to themselves
+        let uid = khash(self.counter).wrapping_mul(khash(note as u32)) & !(SEQUENCER_ID_BIT | ARP_ID_BIT);
+        Some(KeyboardEvent { uid, freq, velocity, pressed: true })
+    }
 
-        // if they switch octave unhold those keys hey
-        if inputs.key_rising(VirtualKeyCode::LShift) {
-            self.current_octave += 1;
+    // notes from a controller come out of frame with everything else so they get recorded and arped the same
+    pub fn midi(&mut self, messages: &[MidiMessage]) {
+        for m in messages {
+            match *m {
+                MidiMessage::NoteOn { channel, note, velocity } => {
+                    if let Some(e) = self.press(note as i32, velocity as f32 / 127.0) {
+                        self.midi_notes.push((channel, note, e.uid));
+                        self.midi_events.push(e);
+                    }
+                },
+                MidiMessage::NoteOff { channel, note } => {
+                    if let Some(i) = self.midi_notes.iter().position(|(c, n, _)| *c == channel && *n == note) {
+                        let (_, _, uid) = self.midi_notes.remove(i);
+                        self.midi_events.push(KeyboardEvent { uid, freq: 0.0, velocity: 0.0, pressed: false });
+                    }
+                },
+                // pedals are on at half way and up
+                MidiMessage::ControlChange { cc: 64, value, .. } => self.midi_pedals.0 = value >= 64,
+                MidiMessage::ControlChange { cc: 66, value, .. } => self.midi_pedals.1 = value >= 64,
+                _ => {},
+            }
         }
-        if inputs.key_rising(VirtualKeyCode::LControl) {
-            self.current_octave -= 1;
+    }
+
+    // (note, rect, black) for whats on screen. blacks come first since theyre on top
+    fn key_rects(&self, piano: Rect) -> Vec<(i32, Rect, bool)> {
+        let hi = self.view_lo + 12 * VIEW_OCTAVES;
+        let whites = (self.view_lo..=hi).filter(|n| !is_black(*n)).count();
+        let spaces = piano.split_lrn(whites as i32);
+        let mut blacks = Vec::new();
+        let mut keys = Vec::new();
+        let mut slot = 0;
+        for n in self.view_lo..=hi {
+            if is_black(n) {
+                // straddles the white one before it and the next
+                let prev = spaces[slot - 1];
+                blacks.push((n, Rect::new(prev.x + prev.w * 0.75, prev.y, prev.w * 0.5, prev.h * 0.6), true));
+            } else {
+                keys.push((n, spaces[slot].dilate_pc(-0.05), false));
+                slot += 1;
+            }
         }
+        blacks.extend(keys);
+        blacks
+    }
 
-        let base_freq = 440. * 2.0f32.powf(self.current_octave as f32);
-        for i in 0..keys.len() {
-            if inputs.key_rising(keys[i as usize]) {
-                self.held_keys.push(i as u32);
-                self.counters[i as usize] += 1;
-                events.push(KeyboardEvent { 
-                    uid: khash(self.counters[i as usize]) * khash(i as u32),
-                    freq: base_freq * 2.0f32.powf(i as f32/12.0),
-                    pressed: true,
-                });
+    // sounding is notes playing from elsewhere, like the sequencer, to light up as well
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect, sounding: &[i32]) -> Vec<KeyboardEvent> {
+        let inputs = ui.inputs;
+        let mut events = std::mem::take(&mut self.midi_events);
+
+        // octave and transpose keys. modifiers only go on a tap since theyre shortcut keys as
+        // well, ctrl z shouldnt also drop an octave
+        let rising: Vec<VirtualKeyCode> = inputs.curr_keys.iter().filter(|k| !inputs.prev_keys.contains(k)).copied().collect();
+        let pressed = |k: VirtualKeyCode| {
+            if is_modifier(k) {
+                inputs.key_falling(k) && self.tap == Some(k)
+            } else {
+                inputs.key_rising(k)
+            }
+        };
+        let octave_up = pressed(self.keymap.octave_up);
+        let octave_down = pressed(self.keymap.octave_down);
+        let transpose_up = pressed(self.keymap.transpose_up);
+        let transpose_down = pressed(self.keymap.transpose_down);
+        if let Some(k) = rising.iter().find(|k| is_modifier(**k)) {
+            self.tap = Some(*k);
+        } else if !rising.is_empty() {
+            self.tap = None;
+        }
+        let octaves = octave_up as i32 - octave_down as i32;
+        self.current_octave += octaves;
+        self.transpose += transpose_up as i32 - transpose_down as i32;
+
+        // first key is A440 at octave 0, the tuning decides what that means from there. an
+        // octave is however many keys the scale takes to repeat
+        let notes = self.keymap.layout.notes();
+        let period = self.tuning.period_keys();
+        let base_note = 69 + period * self.current_octave + self.transpose;
+        for (k, semitone) in notes.iter() {
+            if inputs.key_rising(*k) && !inputs.ctrl() {
+                if let Some(e) = self.press(base_note + semitone, 1.0) {
+                    self.held_keys.push((*k, base_note + semitone, e.uid));
+                    events.push(e);
+                }
             }
         }
 
@@ -110,72 +175,197 @@ impl Keyboard {
         // also can probably clean up and put on github before bad things happen
 
         // can you convolve the frequency response with something to shift it
-        
-
-
-        // kill everything that the keys not held for
-        // might not need to do the octave resettings
-        // layout: white keys are easy
-        // what if we assumed all whtie and all black and then had false
-
-        let released: Vec<u32> = self.held_keys
-            .iter()
-            .filter(|k| !inputs.key_held(keys[**k as usize]))
-            .copied()
-            .collect();
-        for k in &released {
-            events.push(KeyboardEvent {
-                uid: khash(self.counters[*k as usize]) * khash(*k),
-                freq: 0.0,
-                pressed: false,
-            });
+
+        for (k, _, uid) in self.held_keys.iter() {
+            if !inputs.key_held(*k) {
+                events.push(KeyboardEvent { uid: *uid, freq: 0.0, velocity: 0.0, pressed: false });
+            }
         }
-        self.held_keys.retain(|k| inputs.key_held(keys[*k as usize]));
+        self.held_keys.retain(|(k, _, _)| inputs.key_held(*k));
+        self.sustain = self.midi_pedals.0 || inputs.key_held(self.keymap.sustain);
+        self.sostenuto = self.midi_pedals.1 || inputs.key_held(self.keymap.sostenuto);
 
-        kc.set_depth(1.5);
-        kc.set_colour(Vec4::new(0.0, 0.0, 0.0, 1.0));
-        kc.rect(rect);
-        kc.set_depth(1.6);
-        let spaces = rect.split_lrn(white_keys.len() as i32 + 1);
+        ui.kc.set_depth(1.5);
+        ui.kc.set_colour(Vec4::new(0.0, 0.0, 0.0, 1.0));
+        ui.kc.rect(rect);
+        ui.push_id("keyboard");
+
+        let (controls, piano) = rect.split_lr(0.08);
+
+        // the view follows the octave keys, and the wheel moves it on its own
+        let max_lo = 127 - 12 * VIEW_OCTAVES;
+        let scroll = if piano.contains(inputs.mouse_pos) { inputs.scroll.round() as i32 } else { 0 };
+        let lo = (self.view_lo + period * octaves + 12 * scroll + 6).div_euclid(12) * 12;
+        self.view_lo = lo.max(0).min(max_lo / 12 * 12);
 
-        // Octave up&down buttons
-        let (oct_up, oct_down) = spaces[0].split_ud(0.5);
-        kc.set_colour(if inputs.key_held(VirtualKeyCode::LShift) {
+        // mouse, the key under it plays and dragging across slides from key to key. further down
+        // the key is harder, like hitting it nearer the front
+        let keys = self.key_rects(piano);
+        let lmb_down = inputs.lmb == KeyStatus::JustPressed || inputs.lmb == KeyStatus::Pressed;
+        if inputs.lmb == KeyStatus::JustPressed {
+            self.mouse_playing = piano.contains(inputs.mouse_pos) && !ui.popup_open();
+        }
+        let under = if lmb_down && self.mouse_playing {
+            keys.iter().find(|(_, r, _)| r.contains(inputs.mouse_pos))
+        } else {
+            None
+        };
+        if self.mouse_note.map(|(n, _)| n) != under.map(|(n, _, _)| *n) {
+            if let Some((_, uid)) = self.mouse_note.take() {
+                events.push(KeyboardEvent { uid, freq: 0.0, velocity: 0.0, pressed: false });
+            }
+            if let Some((n, r, _)) = under {
+                let velocity = lerp(0.2, 1.0, unlerp(inputs.mouse_pos.y, r.top(), r.bot()).max(0.0).min(1.0));
+                if let Some(e) = self.press(*n, velocity) {
+                    self.mouse_note = Some((*n, e.uid));
+                    events.push(e);
+                }
+            }
+        }
+
+        // layout, then octave up and down showing where its shifted to
+        let (layout_button, rest) = controls.split_ud(0.25);
+        let (octave_rects, pedal_rects) = rest.split_ud(0.7);
+        if ui.button(self.keymap.layout.name(), layout_button) {
+            // the same keys are about to mean something else
+            self.release_all(&mut events);
+            self.keymap.layout = self.keymap.layout.next();
+        }
+        let (oct_up, oct_down) = octave_rects.split_ud(0.5);
+        let kc = &mut *ui.kc;
+        kc.set_depth(1.6);
+        kc.set_colour(if inputs.key_held(self.keymap.octave_up) {
             Vec4::new(1.0, 0.9, 0.9, 1.0)
         } else {
             Vec4::new(1.0, 0.5, 0.5, 1.0)
         });
         kc.rect(oct_up.dilate_pc(-0.05));
-        kc.set_colour(if inputs.key_held(VirtualKeyCode::LControl) {
+        kc.set_colour(if inputs.key_held(self.keymap.octave_down) {
             Vec4::new(0.9, 0.9, 1.0, 1.0)
         } else {
             Vec4::new(0.5, 0.5, 1.0, 1.0)
         });
         kc.rect(oct_down.dilate_pc(-0.05));
+        kc.set_depth(1.65);
+        kc.set_colour(Vec4::new(0.0, 0.0, 0.0, 1.0));
+        kc.text_center(format!("{:+}", self.current_octave).as_bytes(), oct_up.dilate_pc(-0.25));
+        kc.text_center(format!("{:+}st", self.transpose).as_bytes(), oct_down.dilate_pc(-0.25));
 
-        // White keys
-        for i in 1..spaces.len() {
-            kc.set_colour(if inputs.key_held(white_keys[i-1 as usize]) {
-                Vec4::new(0.8, 0.8, 0.8, 1.0)
-            } else {
-                Vec4::new(1.0, 1.0, 1.0, 1.0)
-            });
-            kc.rect(spaces[i as usize].dilate_pc(-0.05));
+        // pedals, lit when down
+        let (sus, sos) = pedal_rects.split_lr(0.5);
+        for (down, label, r) in [(self.sustain, "sus", sus), (self.sostenuto, "sos", sos)] {
+            kc.set_depth(1.6);
+            kc.set_colour(if down { Vec4::new(0.9, 0.8, 0.3, 1.0) } else { Vec4::new(0.3, 0.3, 0.3, 1.0) });
+            kc.rect(r.dilate_pc(-0.1));
+            kc.set_depth(1.65);
+            kc.set_colour(Vec4::new(0.0, 0.0, 0.0, 1.0));
+            kc.text_center(label.as_bytes(), r.dilate_pc(-0.25));
         }
 
-        // black keys have fun
-        kc.set_depth(1.7);
-        let br = |r: Rect| Rect::new(r.x + r.w*0.75, r.y, r.w * 0.5, r.h * 0.5);
-        for (i, bki) in [0, 2, 3, 5, 6, 7, 9, 10, 12].iter().enumerate() {
-            let bk_rect = br(spaces[(1 + bki) as usize]);
-            kc.set_colour(if inputs.key_held(black_keys[i]) {
-                Vec4::new(0.2, 0.2, 0.2, 1.0)
+        // keys, labelled with whatever plays them. played here is blue, anywhere else orange
+        let key_for = |n: i32| notes.iter().find(|(_, s)| base_note + *s == n).map(|(k, _)| *k);
+        let played = |n: i32| self.held_keys.iter().any(|(_, note, _)| *note == n) ||
+            self.mouse_note.map_or(false, |(note, _)| note == n);
+        let elsewhere = |n: i32| sounding.contains(&n) || self.midi_notes.iter().any(|(_, note, _)| *note as i32 == n);
+        for (n, r, black) in keys.iter().rev() {
+            let (n, r) = (*n, *r);
+            let (depth, colour, text) = if *black {
+                let colour = if played(n) {
+                    Vec4::new(0.2, 0.3, 0.6, 1.0)
+                } else if elsewhere(n) {
+                    Vec4::new(0.6, 0.3, 0.1, 1.0)
+                } else {
+                    Vec4::new(0.0, 0.0, 0.0, 1.0)
+                };
+                (1.7, colour, Vec4::new(0.8, 0.8, 0.8, 1.0))
             } else {
-                Vec4::new(0.0, 0.0, 0.0, 1.0)
-            });
-            kc.rect(bk_rect);
+                let colour = if played(n) {
+                    Vec4::new(0.7, 0.8, 1.0, 1.0)
+                } else if elsewhere(n) {
+                    Vec4::new(1.0, 0.8, 0.6, 1.0)
+                } else {
+                    Vec4::new(1.0, 1.0, 1.0, 1.0)
+                };
+                (1.6, colour, Vec4::new(0.3, 0.3, 0.3, 1.0))
+            };
+            kc.set_depth(depth);
+            kc.set_colour(colour);
+            kc.rect(r);
+            kc.set_depth(depth + 0.05);
+            kc.set_colour(text);
+            if let Some(k) = key_for(n) {
+                let label = if *black { r.child(0.1, 0.7, 0.8, 0.25) } else { r.child(0.1, 0.85, 0.8, 0.1) };
+                kc.text_center(key_label(k).as_bytes(), label);
+            }
+            // Cs say which octave they are so theres something to go on when scrolled
+            if n % 12 == 0 {
+                kc.text_center(format!("c{}", n / 12 - 1).as_bytes(), r.child(0.1, 0.7, 0.8, 0.1));
+            }
         }
 
+        ui.pop_id();
         events
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_mouse_keys() {
+    let mut kb = Keyboard::new();
+    let mut state = UiState::new();
+    let mut kc = KRCanvas::new();
+    let rect = Rect::new(0.0, 0.0, 1.0, 0.2);
+    let piano = rect.split_lr(0.08).1;
+    let keys = kb.key_rects(piano);
+    let at = |note: i32, down: f32| {
+        let (_, r, _) = keys.iter().find(|(n, _, _)| *n == note).unwrap();
+        Vec2::new(r.x + r.w / 2.0, r.y + r.h * down)
+    };
+    let mut frame = |kb: &mut Keyboard, mouse: Vec2, lmb: KeyStatus| {
+        let inputs = test_inputs(mouse, lmb);
+        let mut ui = Ui::new(&inputs, &mut kc, &mut state);
+        kb.frame(&mut ui, rect, &[])
+    };
+
+    // A440 near the top is soft
+    let e = frame(&mut kb, at(69, 0.1), KeyStatus::JustPressed);
+    assert_eq!(e.len(), 1);
+    assert!(e[0].pressed && (e[0].freq - 440.0).abs() < 0.01 && e[0].velocity < 0.5);
+    // staying on it does nothing, dragging onto the black key above lets go and plays that
+    assert!(frame(&mut kb, at(69, 0.2), KeyStatus::Pressed).is_empty());
+    let e = frame(&mut kb, at(70, 0.5), KeyStatus::Pressed);
+    assert_eq!(e.len(), 2);
+    assert!(!e[0].pressed && e[1].pressed && e[1].freq > 440.0);
+    let e = frame(&mut kb, at(70, 0.5), KeyStatus::JustReleased);
+    assert!(e.len() == 1 && !e[0].pressed);
+
+    // dragging in from outside the keys doesnt play
+    frame(&mut kb, Vec2::new(0.5, 0.5), KeyStatus::JustPressed);
+    assert!(frame(&mut kb, at(69, 0.5), KeyStatus::Pressed).is_empty());
+    frame(&mut kb, at(69, 0.5), KeyStatus::JustReleased);
+
+    // midi comes out next frame, offs match their ons
+    kb.midi(&[MidiMessage::NoteOn { channel: 0, note: 60, velocity: 127 }]);
+    let e = frame(&mut kb, Vec2::new(0.5, 0.5), KeyStatus::Released);
+    assert!(e.len() == 1 && e[0].pressed && e[0].velocity == 1.0);
+    kb.midi(&[MidiMessage::NoteOff { channel: 1, note: 60 }, MidiMessage::NoteOff { channel: 0, note: 60 }]);
+    let off = frame(&mut kb, Vec2::new(0.5, 0.5), KeyStatus::Released);
+    assert!(off.len() == 1 && off[0].uid == e[0].uid && !off[0].pressed);
+}
+
+#[test]
+fn test_uid_space() {
+    let mut kb = Keyboard::new();
+    let mut state = UiState::new();
+    let mut kc = KRCanvas::new();
+    let inputs = test_inputs(Vec2::new(0.5, 0.5), KeyStatus::Released);
+    for round in 0..8 {
+        for note in 0..128 {
+            kb.midi(&[MidiMessage::NoteOn { channel: 0, note, velocity: 100 }, MidiMessage::NoteOff { channel: 0, note }]);
+            let mut ui = Ui::new(&inputs, &mut kc, &mut state);
+            let e = kb.frame(&mut ui, Rect::new(0.0, 0.0, 1.0, 0.2), &[]);
+            // a note off that matched an arp or sequencer id would cut off one of their voices
+            assert!(e.len() == 2 && !e[1].pressed, "round {} note {}", round, note);
+            assert_eq!(e[1].uid & (SEQUENCER_ID_BIT | ARP_ID_BIT), 0);
+        }
+    }
+}
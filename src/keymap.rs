@@ -0,0 +1,171 @@
+use glutin::event::VirtualKeyCode;
+use glutin::event::VirtualKeyCode::*;
+
+// which computer keys play which notes. winit hands over keys by what the layout says is printed
+// on them, so each layout lists its own codes for the same physical rows. notes are semitones
+// from the A the keyboard is sitting on
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeymapLayout {
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+    Tracker,    // two rows a piano each, zxcv from C and qwer from the C above, like trackers do
+}
+
+impl KeymapLayout {
+    pub const ALL: [KeymapLayout; 5] = [KeymapLayout::Qwerty, KeymapLayout::Azerty, KeymapLayout::Qwertz, KeymapLayout::Dvorak, KeymapLayout::Tracker];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeymapLayout::Qwerty => "qwerty",
+            KeymapLayout::Azerty => "azerty",
+            KeymapLayout::Qwertz => "qwertz",
+            KeymapLayout::Dvorak => "dvorak",
+            KeymapLayout::Tracker => "tracker",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<KeymapLayout> {
+        KeymapLayout::ALL.iter().copied().find(|l| l.name() == s)
+    }
+
+    pub fn next(&self) -> KeymapLayout {
+        let i = KeymapLayout::ALL.iter().position(|l| l == self).unwrap_or(0);
+        KeymapLayout::ALL[(i + 1) % KeymapLayout::ALL.len()]
+    }
+
+    // (key, semitones from A). the piano ones start at tab for A with the black keys up on the
+    // number row, letter row white and number row black
+    pub fn notes(&self) -> Vec<(VirtualKeyCode, i32)> {
+        let chromatic = |keys: &[VirtualKeyCode], from: i32| keys.iter().enumerate().map(|(i, k)| (*k, from + i as i32)).collect::<Vec<_>>();
+        match self {
+            KeymapLayout::Qwerty => chromatic(&[Tab, Key1, Q, W, Key3, E, Key4, R, T, Key6, Y, Key7, U, Key8, I, O, Key0, P, Minus, LBracket, RBracket, Back, Backslash], 0),
+            // the number row is the digits on windows, on x11 it depends how the keymap names the unshifted symbols
+            KeymapLayout::Azerty => chromatic(&[Tab, Key1, A, Z, Key3, E, Key4, R, T, Key6, Y, Key7, U, Key8, I, O, Key0, P], 0),
+            // past p its umlauts, which dont come through as anything
+            KeymapLayout::Qwertz => chromatic(&[Tab, Key1, Q, W, Key3, E, Key4, R, T, Key6, Z, Key7, U, Key8, I, O, Key0, P], 0),
+            KeymapLayout::Dvorak => chromatic(&[Tab, Key1, Apostrophe, Comma, Key3, Period, Key4, P, Y, Key6, F, Key7, G, Key8, C, R, Key0, L, LBracket, Slash, Equals, Back, Backslash], 0),
+            KeymapLayout::Tracker => {
+                // C is 3 above A, so the bottom row starts 9 under it
+                let mut notes = chromatic(&[Z, S, X, D, C, V, G, B, H, N, J, M, Comma, L, Period, Semicolon, Slash], -9);
+                notes.extend(chromatic(&[Q, Key2, W, Key3, E, R, Key5, T, Key6, Y, Key7, U, I, Key9, O, Key0, P, LBracket, Equals, RBracket], 3));
+                notes
+            },
+        }
+    }
+}
+
+// what to draw on a key, and what to call it in the config file
+const KEY_NAMES: [(VirtualKeyCode, &str, &str); 61] = [
+    (A, "A", "a"), (B, "B", "b"), (C, "C", "c"), (D, "D", "d"), (E, "E", "e"), (F, "F", "f"), (G, "G", "g"),
+    (H, "H", "h"), (I, "I", "i"), (J, "J", "j"), (K, "K", "k"), (L, "L", "l"), (M, "M", "m"), (N, "N", "n"),
+    (O, "O", "o"), (P, "P", "p"), (Q, "Q", "q"), (R, "R", "r"), (S, "S", "s"), (T, "T", "t"), (U, "U", "u"),
+    (V, "V", "v"), (W, "W", "w"), (X, "X", "x"), (Y, "Y", "y"), (Z, "Z", "z"),
+    (Key0, "Key0", "0"), (Key1, "Key1", "1"), (Key2, "Key2", "2"), (Key3, "Key3", "3"), (Key4, "Key4", "4"),
+    (Key5, "Key5", "5"), (Key6, "Key6", "6"), (Key7, "Key7", "7"), (Key8, "Key8", "8"), (Key9, "Key9", "9"),
+    (Tab, "Tab", "tab"), (Back, "Back", "bk"), (Minus, "Minus", "-"), (Equals, "Equals", "="),
+    (LBracket, "LBracket", "["), (RBracket, "RBracket", "]"), (Backslash, "Backslash", "\\"),
+    (Semicolon, "Semicolon", ";"), (Apostrophe, "Apostrophe", "'"), (Comma, "Comma", ","),
+    (Period, "Period", "."), (Slash, "Slash", "/"), (Grave, "Grave", "`"),
+    (LShift, "LShift", "shft"), (RShift, "RShift", "shft"), (LControl, "LControl", "ctrl"), (RControl, "RControl", "ctrl"),
+    (LAlt, "LAlt", "alt"), (RAlt, "RAlt", "alt"),
+    (Left, "Left", "<"), (Right, "Right", ">"), (Up, "Up", "^"), (Down, "Down", "v"),
+    (PageUp, "PageUp", "pgup"), (PageDown, "PageDown", "pgdn"),
+];
+
+pub fn key_label(k: VirtualKeyCode) -> &'static str {
+    KEY_NAMES.iter().find(|(key, _, _)| *key == k).map_or("?", |(_, _, label)| label)
+}
+
+pub fn key_name(k: VirtualKeyCode) -> &'static str {
+    KEY_NAMES.iter().find(|(key, _, _)| *key == k).map_or("?", |(_, name, _)| name)
+}
+
+pub fn key_from_name(s: &str) -> Option<VirtualKeyCode> {
+    KEY_NAMES.iter().find(|(_, name, _)| *name == s).map(|(key, _, _)| *key)
+}
+
+// modifiers double as shortcut keys, so when one is an octave key it only counts as a tap
+pub fn is_modifier(k: VirtualKeyCode) -> bool {
+    matches!(k, LShift | RShift | LControl | RControl | LAlt | RAlt)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keymap {
+    pub layout: KeymapLayout,
+    pub octave_up: VirtualKeyCode,
+    pub octave_down: VirtualKeyCode,
+    pub transpose_up: VirtualKeyCode,
+    pub transpose_down: VirtualKeyCode,
+    pub sustain: VirtualKeyCode,
+    pub sostenuto: VirtualKeyCode,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            layout: KeymapLayout::Qwerty,
+            octave_up: LShift,
+            octave_down: LControl,
+            transpose_up: Right,
+            transpose_down: Left,
+            sustain: Down,
+            sostenuto: Up,
+        }
+    }
+
+    // config file names for the remappable ones
+    pub fn set_action(&mut self, action: &str, k: VirtualKeyCode) -> bool {
+        match action {
+            "octave_up" => self.octave_up = k,
+            "octave_down" => self.octave_down = k,
+            "transpose_up" => self.transpose_up = k,
+            "transpose_down" => self.transpose_down = k,
+            "sustain" => self.sustain = k,
+            "sostenuto" => self.sostenuto = k,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn actions(&self) -> [(&'static str, VirtualKeyCode); 6] {
+        [
+            ("octave_up", self.octave_up),
+            ("octave_down", self.octave_down),
+            ("transpose_up", self.transpose_up),
+            ("transpose_down", self.transpose_down),
+            ("sustain", self.sustain),
+            ("sostenuto", self.sostenuto),
+        ]
+    }
+}
+
+#[test]
+fn test_keymaps() {
+    for layout in KeymapLayout::ALL {
+        let notes = layout.notes();
+        // every key has a label and a name that reads back, and no key plays two notes
+        for (i, (k, _)) in notes.iter().enumerate() {
+            assert_ne!(key_label(*k), "?", "{:?} {:?}", layout, k);
+            assert_eq!(key_from_name(key_name(*k)), Some(*k));
+            assert!(notes[i + 1..].iter().all(|(other, _)| other != k), "{:?} {:?} twice", layout, k);
+        }
+        assert_eq!(KeymapLayout::from_name(layout.name()), Some(layout));
+    }
+    // the piano layouts agree on where the notes are, just not what the keys are called
+    let qwerty = KeymapLayout::Qwerty.notes();
+    for layout in [KeymapLayout::Azerty, KeymapLayout::Qwertz, KeymapLayout::Dvorak] {
+        for (i, (_, n)) in layout.notes().iter().enumerate() {
+            assert_eq!(*n, qwerty[i].1);
+        }
+    }
+    // tracker, z is C below and q is the C above
+    let tracker = KeymapLayout::Tracker.notes();
+    assert!(tracker.contains(&(Z, -9)) && tracker.contains(&(Q, 3)) && tracker.contains(&(Comma, 3)));
+    let mut keymap = Keymap::new();
+    assert!(keymap.set_action("transpose_up", PageUp));
+    assert!(!keymap.set_action("nope", PageUp));
+    assert_eq!(keymap.transpose_up, PageUp);
+}
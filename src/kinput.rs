@@ -13,6 +13,12 @@ use glutin::event::WindowEvent::KeyboardInput;
 use glutin::event::WindowEvent::MouseInput;
 use glutin::event::WindowEvent::CursorMoved;
 use glutin::event::WindowEvent::Resized;
+use glutin::event::WindowEvent::ScaleFactorChanged;
+use glutin::event::WindowEvent::MouseWheel;
+use glutin::event::WindowEvent::ReceivedCharacter;
+use glutin::event::WindowEvent::Touch;
+use glutin::event::TouchPhase;
+use glutin::event::MouseScrollDelta;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub enum KeyStatus {
@@ -27,6 +33,7 @@ pub enum KeyStatus {
 #[derive(Clone)]
 pub struct FrameInputState {
     pub screen_rect: Rect,
+    pub px: f32,        // one logical pixel in screen units, for sizes that shouldnt scale with the window
     pub mouse_pos: Vec2,
     pub mouse_delta: Vec2,
     
@@ -36,6 +43,8 @@ pub struct FrameInputState {
     pub lmb: KeyStatus,
     pub rmb: KeyStatus,
     pub mmb: KeyStatus,
+    pub scroll: f32,    // wheel notches this frame, up is positive
+    pub text: String,   // typed characters this frame, including \r \x08 \x1b for enter backspace escape
     pub t: f64,
     pub dt: f64,
     pub frame: u32,
@@ -52,26 +61,49 @@ impl FrameInputState {
     pub fn key_falling(&self, keycode: VirtualKeyCode) -> bool {
         !self.curr_keys.contains(&keycode) && self.prev_keys.contains(&keycode)
     }
+    pub fn ctrl(&self) -> bool {
+        self.key_held(VirtualKeyCode::LControl) || self.key_held(VirtualKeyCode::RControl)
+    }
+    // same frame but the mouse buttons are up, for when something on top has the mouse
+    pub fn without_mouse(&self) -> FrameInputState {
+        let mut inputs = self.clone();
+        inputs.lmb = KeyStatus::Released;
+        inputs.rmb = KeyStatus::Released;
+        inputs.mmb = KeyStatus::Released;
+        inputs.scroll = 0.0;
+        inputs
+    }
+    // same frame but no key presses or releases, for when a text field has the keyboard
+    pub fn without_keys(&self) -> FrameInputState {
+        let mut inputs = self.clone();
+        inputs.prev_keys = inputs.curr_keys.clone();
+        inputs
+    }
 }
 
 // Its basically just a state machine to go from events to polling behaviour
 pub struct EventAggregator {
     xres: f32,
     yres: f32,
+    scale: f32,     // physical pixels per logical pixel, 2 on a lot of laptops and 4k screens
     t_last: Instant,
     instant_mouse_pos: Vec2,
+    touch: Option<u64>,     // the finger being the mouse, the rest are ignored
     current: FrameInputState,
 }
 
 impl EventAggregator {
-    pub fn new(xres: f32, yres: f32) -> EventAggregator {
+    pub fn new(xres: f32, yres: f32, scale: f32) -> EventAggregator {
         EventAggregator { 
             xres, 
             yres, 
+            scale,
             t_last: Instant::now(),
             instant_mouse_pos: Vec2::new(0.0, 0.0),
+            touch: None,
             current: FrameInputState { 
                 screen_rect: Rect::new(0.0, 0.0, xres/yres, 1.0, ), 
+                px: scale / yres,
                 mouse_pos: Vec2::new(0.0, 0.0), 
                 mouse_delta: Vec2::new(0.0, 0.0), 
                 curr_keys: HashSet::new(),
@@ -79,6 +111,8 @@ impl EventAggregator {
                 lmb: KeyStatus::Released, 
                 rmb: KeyStatus::Released, 
                 mmb: KeyStatus::Released, 
+                scroll: 0.0,
+                text: String::new(),
                 t: 0.0,
                 dt: 0.0,
                 frame: 0,
@@ -126,6 +160,38 @@ impl EventAggregator {
                 },
 
 
+                MouseWheel { delta, .. } => {
+                    self.current.scroll += match delta {
+                        MouseScrollDelta::LineDelta(_, y) => *y,
+                        // trackpads, call about 50 pixels a notch
+                        MouseScrollDelta::PixelDelta(p) => p.y as f32 / 50.0,
+                    };
+                },
+
+                // first finger down drives the left button, good enough for playing the keys
+                Touch(touch) => {
+                    let pos = Vec2::new(touch.location.x as f32 / self.yres, touch.location.y as f32 / self.yres);
+                    match touch.phase {
+                        TouchPhase::Started if self.touch.is_none() => {
+                            self.touch = Some(touch.id);
+                            self.instant_mouse_pos = pos;
+                            self.current.lmb = KeyStatus::JustPressed;
+                        },
+                        TouchPhase::Moved if self.touch == Some(touch.id) => {
+                            self.instant_mouse_pos = pos;
+                        },
+                        TouchPhase::Ended | TouchPhase::Cancelled if self.touch == Some(touch.id) => {
+                            self.touch = None;
+                            self.current.lmb = KeyStatus::JustReleased;
+                        },
+                        _ => {},
+                    }
+                },
+
+                ReceivedCharacter(c) => {
+                    self.current.text.push(*c);
+                },
+
                 // Mouse motion
                 CursorMoved {
                     position: pos,
@@ -139,6 +205,16 @@ impl EventAggregator {
                     self.xres = physical_size.width as f32;
                     self.yres = physical_size.height as f32;
                     self.current.screen_rect = Rect::new(0.0, 0.0, self.xres / self.yres, 1.0);
+                    self.current.px = self.scale / self.yres;
+                },
+
+                // dragged onto another monitor or the os setting changed
+                ScaleFactorChanged { scale_factor, new_inner_size } => {
+                    self.scale = *scale_factor as f32;
+                    self.xres = new_inner_size.width as f32;
+                    self.yres = new_inner_size.height as f32;
+                    self.current.screen_rect = Rect::new(0.0, 0.0, self.xres / self.yres, 1.0);
+                    self.current.px = self.scale / self.yres;
                 },
 
 
@@ -156,6 +232,8 @@ impl EventAggregator {
                 self.current.mouse_delta = self.instant_mouse_pos - self.current.mouse_pos;
                 self.current.mouse_pos = self.instant_mouse_pos;
                 let state = self.current.clone();
+                self.current.scroll = 0.0;
+                self.current.text.clear();
                 self.current.prev_keys = self.current.curr_keys.clone();
                 self.current.seed = khash(self.current.seed * 196513497);
                 self.current.lmb = match self.current.lmb {KeyStatus::JustPressed | KeyStatus::Pressed => KeyStatus::Pressed, KeyStatus::JustReleased | KeyStatus::Released => KeyStatus::Released};
@@ -18,8 +18,12 @@ pub fn remap(x: f32, from_low: f32, from_high: f32, to_low: f32, to_high: f32) -
     lerp(to_low, to_high, unlerp(x, from_low, from_high))
 }
 
+// de casteljau, lerp the lerps of the lerps
 pub fn cubic_bezier(start: Vec2, c1: Vec2, c2: Vec2, end: Vec2, t: f32) -> Vec2 {
-    start.lerp(c1.lerp(c2.lerp(end, t), t), t)
+    let a = start.lerp(c1, t);
+    let b = c1.lerp(c2, t);
+    let c = c2.lerp(end, t);
+    a.lerp(b, t).lerp(b.lerp(c, t), t)
 }
 
 /***************************************************
@@ -27,9 +31,9 @@ pub fn cubic_bezier(start: Vec2, c1: Vec2, c2: Vec2, end: Vec2, t: f32) -> Vec2
  ***************************************************/
 
 pub fn khash(mut state: u32) -> u32 {
-    state = (state ^ 2747636419) * 2654435769;
-    state = (state ^ (state >> 16)) * 2654435769;
-    state = (state ^ (state >> 16)) * 2654435769;
+    state = (state ^ 2747636419).wrapping_mul(2654435769);
+    state = (state ^ (state >> 16)).wrapping_mul(2654435769);
+    state = (state ^ (state >> 16)).wrapping_mul(2654435769);
     state
 }
 
@@ -574,4 +578,14 @@ pub fn test_lerp() {
     let r2 = Rect::new(1.0, 1.0, 2.0, 1.0);
     assert_eq!(r1.lerp(r2, 0.5), Rect::new(0.5, 0.5, 1.5, 1.0));
     assert_eq!(r1.lerp(r2, 0.3), Rect::new(0.3, 0.3, 1.3, 1.0));
-}
\ No newline at end of file
+}
+#[test]
+pub fn test_bezier() {
+    let (a, b, c, d) = (Vec2::new(0.0, 0.0), Vec2::new(0.0, 1.0), Vec2::new(1.0, 1.0), Vec2::new(1.0, 0.0));
+    assert_eq!(cubic_bezier(a, b, c, d, 0.0), a);
+    assert_eq!(cubic_bezier(a, b, c, d, 1.0), d);
+    assert_eq!(cubic_bezier(a, b, c, d, 0.5), Vec2::new(0.5, 0.75));
+    // evenly spaced controls on a line is just the line
+    let p = cubic_bezier(a, Vec2::new(1.0, 1.0), Vec2::new(2.0, 2.0), Vec2::new(3.0, 3.0), 0.25);
+    assert!((p.x - 0.75).abs() < 1e-6 && (p.y - 0.75).abs() < 1e-6);
+}
@@ -54,9 +54,14 @@ impl KRenderer {
     }
 
     pub fn send(&self, gl: &glow::Context, buf: &[u8]) {
+        self.send_with(gl, buf, self.atlas);
+    }
+
+    // same as send but sampling some other texture instead of the atlas
+    pub fn send_with(&self, gl: &glow::Context, buf: &[u8], texture: NativeTexture) {
         unsafe {
             gl.use_program(Some(self.shader));
-            gl.bind_texture(glow::TEXTURE_2D, Some(self.atlas));
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
             gl.bind_vertex_array(Some(self.vao));
             gl.bind_buffer(glow::ARRAY_BUFFER, Some(self.vbo));
             gl.buffer_data_u8_slice(glow::ARRAY_BUFFER, buf, glow::DYNAMIC_DRAW);
@@ -65,6 +70,29 @@ impl KRenderer {
         }
     }
 
+    // blank texture that wraps horizontally, so you can write columns into it as a ring
+    // and scroll it by offsetting uvs instead of uploading the whole thing again
+    pub fn stream_texture(&self, gl: &glow::Context, w: usize, h: usize) -> NativeTexture {
+        unsafe {
+            let texture = gl.create_texture().unwrap();
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_image_2d(glow::TEXTURE_2D, 0, glow::RGBA as i32, w as i32, h as i32, 0, RGBA, glow::UNSIGNED_BYTE, Some(&vec![0u8; w * h * 4]));
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::LINEAR as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::REPEAT as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+            texture
+        }
+    }
+
+    // one column of rgba, top to bottom
+    pub fn upload_column(&self, gl: &glow::Context, texture: NativeTexture, x: usize, column: &[u8]) {
+        unsafe {
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            gl.tex_sub_image_2d(glow::TEXTURE_2D, 0, x as i32, 0, 1, (column.len() / 4) as i32, RGBA, glow::UNSIGNED_BYTE, PixelUnpackData::Slice(column));
+        }
+    }
+
     pub fn destroy(&self, gl: &glow::Context) {
         unsafe {
             gl.delete_buffer(self.vbo);
@@ -150,6 +178,24 @@ impl KRCanvas {
         self.triangle(r.bl(), r.tr(), r.br());
     }
 
+    // rect showing the uv region of whatever texture this canvas gets sent with
+    pub fn rect_uv(&mut self, r: Rect, uv: Rect) {
+        self.uv_clip = uv;
+        self.rect(r);
+        self.uv_clip = Rect::new(0.0, 0.0, 1.0/20.0, 1.0/20.0);
+    }
+
+    // quad of width w along a->b
+    pub fn line(&mut self, a: Vec2, b: Vec2, w: f32) {
+        // no direction to be wide in, normalize would give nans
+        if a == b {
+            return;
+        }
+        let n = Vec2::new(a.y - b.y, b.x - a.x).normalize() * (w / 2.0);
+        self.triangle(a + n, b + n, a - n);
+        self.triangle(a - n, b + n, b - n);
+    }
+
     pub fn poly(&mut self, center: Vec2, radius: f32, n_sides: i32) {
         for i in 0..n_sides {
             let theta_1 = i as f32 * 2.0 * std::f32::consts::PI / n_sides as f32;
@@ -0,0 +1,287 @@
+use crate::krenderer::*;
+use crate::kinput::*;
+use crate::kmath::*;
+
+// where the panels go. a tree of splits, drag the gap between two panels to move the split,
+// middle drag a panel onto another to swap them round. minimum sizes are in logical pixels so
+// a small window squeezes the splits before it squeezes the panels
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanelId {
+    Envelope,
+    Mids,
+    Filter,
+    Tuning,
+    Unison,
+    Fft,
+    Roll,
+    Shape,
+    Spectrogram,
+    Scope,
+    Sequencer,
+    Arp,
+    Looper,
+    Bend,
+    Keyboard,
+}
+
+pub const NUM_PANELS: usize = 15;
+
+impl PanelId {
+    // logical pixels, w then h
+    pub fn min_size(&self) -> (f32, f32) {
+        match self {
+            PanelId::Envelope | PanelId::Mids | PanelId::Filter => (180.0, 160.0),
+            PanelId::Tuning | PanelId::Unison | PanelId::Fft => (140.0, 160.0),
+            PanelId::Roll | PanelId::Sequencer => (240.0, 120.0),
+            PanelId::Shape | PanelId::Looper => (160.0, 120.0),
+            PanelId::Spectrogram | PanelId::Scope | PanelId::Arp => (120.0, 100.0),
+            PanelId::Bend => (16.0, 80.0),
+            PanelId::Keyboard => (320.0, 80.0),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SplitDir {
+    LeftRight,
+    UpDown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Panel(PanelId),
+    Split { dir: SplitDir, frac: f32, a: Box<Node>, b: Box<Node> },
+}
+
+fn lr(frac: f32, a: Node, b: Node) -> Node {
+    Node::Split { dir: SplitDir::LeftRight, frac, a: Box::new(a), b: Box::new(b) }
+}
+
+fn ud(frac: f32, a: Node, b: Node) -> Node {
+    Node::Split { dir: SplitDir::UpDown, frac, a: Box::new(a), b: Box::new(b) }
+}
+
+impl Node {
+    // logical pixels
+    fn min_size(&self) -> (f32, f32) {
+        match self {
+            Node::Panel(id) => id.min_size(),
+            Node::Split { dir, a, b, .. } => {
+                let (a, b) = (a.min_size(), b.min_size());
+                match dir {
+                    SplitDir::LeftRight => (a.0 + b.0, a.1.max(b.1)),
+                    SplitDir::UpDown => (a.0.max(b.0), a.1 + b.1),
+                }
+            },
+        }
+    }
+
+    // how far along the split can actually go given whats on each side. if theres not room for
+    // both it shares out by how much each wants
+    fn clamped_frac(&self, r: Rect, px: f32) -> f32 {
+        match self {
+            Node::Panel(_) => 1.0,
+            Node::Split { dir, frac, a, b } => {
+                let pick = |s: (f32, f32)| if *dir == SplitDir::LeftRight { s.0 } else { s.1 };
+                let (min_a, min_b) = (pick(a.min_size()) * px, pick(b.min_size()) * px);
+                let total = if *dir == SplitDir::LeftRight { r.w } else { r.h };
+                if min_a + min_b >= total {
+                    min_a / (min_a + min_b)
+                } else {
+                    frac.max(min_a / total).min(1.0 - min_b / total)
+                }
+            },
+        }
+    }
+}
+
+pub struct Layout {
+    pub root: Node,
+    drag: Option<usize>,            // split being dragged, by its order in the tree
+    dock: Option<PanelId>,          // panel being carried to swap with another
+}
+
+// how wide the gap between panels is, where the splits get grabbed
+const GAP: f32 = 4.0;
+
+impl Layout {
+    // the layout there was before you could change it
+    pub fn new() -> Layout {
+        let tops = lr(1.0 / 6.0, Node::Panel(PanelId::Envelope),
+            lr(1.0 / 5.0, Node::Panel(PanelId::Mids),
+            lr(1.0 / 4.0, Node::Panel(PanelId::Filter),
+            lr(1.0 / 3.0, Node::Panel(PanelId::Tuning),
+            lr(1.0 / 2.0, Node::Panel(PanelId::Unison), Node::Panel(PanelId::Fft))))));
+        let views = lr(0.5,
+            lr(0.65, Node::Panel(PanelId::Roll), Node::Panel(PanelId::Shape)),
+            lr(0.5, Node::Panel(PanelId::Spectrogram), Node::Panel(PanelId::Scope)));
+        let bottom = ud(0.5,
+            lr(0.6, Node::Panel(PanelId::Sequencer), lr(0.4, Node::Panel(PanelId::Arp), Node::Panel(PanelId::Looper))),
+            lr(0.03, Node::Panel(PanelId::Bend), Node::Panel(PanelId::Keyboard)));
+        Layout { root: ud(0.65, ud(0.7, tops, views), bottom), drag: None, dock: None }
+    }
+
+    // every panels rect, indexed by PanelId, and the splits in tree order with the rect they split
+    pub fn rects(&self, r: Rect, px: f32) -> ([Rect; NUM_PANELS], Vec<(Rect, SplitDir, f32)>) {
+        fn walk(n: &Node, r: Rect, px: f32, panels: &mut [Rect; NUM_PANELS], splits: &mut Vec<(Rect, SplitDir, f32)>) {
+            match n {
+                Node::Panel(id) => panels[*id as usize] = r,
+                Node::Split { dir, a, b, .. } => {
+                    let frac = n.clamped_frac(r, px);
+                    splits.push((r, *dir, frac));
+                    let (ra, rb) = if *dir == SplitDir::LeftRight { r.split_lr(frac) } else { r.split_ud(frac) };
+                    walk(a, ra, px, panels, splits);
+                    walk(b, rb, px, panels, splits);
+                },
+            }
+        }
+        let mut panels = [Rect::new(0.0, 0.0, 0.0, 0.0); NUM_PANELS];
+        let mut splits = Vec::new();
+        walk(&self.root, r, px, &mut panels, &mut splits);
+        (panels, splits)
+    }
+
+    fn split_mut(&mut self, i: usize) -> Option<&mut f32> {
+        fn walk<'a>(n: &'a mut Node, i: &mut usize) -> Option<&'a mut f32> {
+            match n {
+                Node::Panel(_) => None,
+                Node::Split { frac, a, b, .. } => {
+                    if *i == 0 {
+                        return Some(frac);
+                    }
+                    *i -= 1;
+                    walk(a, i).or_else(move || walk(b, i))
+                },
+            }
+        }
+        let mut i = i;
+        walk(&mut self.root, &mut i)
+    }
+
+    pub fn swap(&mut self, x: PanelId, y: PanelId) {
+        fn walk(n: &mut Node, x: PanelId, y: PanelId) {
+            match n {
+                Node::Panel(id) if *id == x => *id = y,
+                Node::Panel(id) if *id == y => *id = x,
+                Node::Panel(_) => {},
+                Node::Split { a, b, .. } => {
+                    walk(a, x, y);
+                    walk(b, x, y);
+                },
+            }
+        }
+        walk(&mut self.root, x, y);
+    }
+
+    // the rect each panel gets this frame, inset so the gaps belong to the layout. true while
+    // its got the mouse, the panels shouldnt see the buttons then
+    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas) -> ([Rect; NUM_PANELS], bool) {
+        let px = inputs.px;
+        let (mut panels, splits) = self.rects(inputs.screen_rect, px);
+        let mouse = inputs.mouse_pos;
+        let gap = GAP * px;
+        // the strip along the middle of a split
+        let divider = |(r, dir, frac): (Rect, SplitDir, f32)| match dir {
+            SplitDir::LeftRight => Rect::new(r.x + r.w * frac - gap, r.y, 2.0 * gap, r.h),
+            SplitDir::UpDown => Rect::new(r.x, r.y + r.h * frac - gap, r.w, 2.0 * gap),
+        };
+
+        if inputs.lmb == KeyStatus::JustPressed {
+            // innermost first, where two gaps cross the smaller split is more likely what was meant
+            self.drag = splits.iter().rposition(|s| divider(*s).contains(mouse));
+        }
+        if inputs.lmb != KeyStatus::Pressed && inputs.lmb != KeyStatus::JustPressed {
+            self.drag = None;
+        }
+        if let Some(i) = self.drag {
+            let (r, dir, _) = splits[i];
+            let frac = match dir {
+                SplitDir::LeftRight => unlerp(mouse.x, r.left(), r.right()),
+                SplitDir::UpDown => unlerp(mouse.y, r.top(), r.bot()),
+            };
+            if let Some(f) = self.split_mut(i) {
+                // the minimums clamp it when its laid out, keep the stored one sane as well
+                *f = frac.max(0.0).min(1.0);
+            }
+        }
+
+        let over = (0..NUM_PANELS).find(|i| panels[*i].contains(mouse));
+        let id_at = |i: usize| all_panels()[i];
+        if inputs.mmb == KeyStatus::JustPressed {
+            self.dock = over.map(id_at);
+        }
+        let mut dropped = None;
+        if inputs.mmb == KeyStatus::JustReleased || inputs.mmb == KeyStatus::Released {
+            if let (Some(from), Some(to)) = (self.dock.take(), over.map(id_at)) {
+                dropped = Some((from, to));
+            }
+        }
+
+        // highlight whats being grabbed or could be
+        kc.set_depth(3.5);
+        let hovered = if self.drag.is_some() { self.drag } else { splits.iter().rposition(|s| divider(*s).contains(mouse)) };
+        if let Some(i) = hovered {
+            kc.set_colour(Vec4::new(1.0, 0.9, 0.3, if self.drag.is_some() { 0.8 } else { 0.4 }));
+            kc.rect(divider(splits[i]));
+        }
+        if let Some(from) = self.dock {
+            kc.set_colour(Vec4::new(1.0, 0.9, 0.3, 0.15));
+            kc.rect(panels[from as usize]);
+            if let Some(to) = over {
+                kc.set_colour(Vec4::new(0.3, 0.9, 1.0, 0.25));
+                kc.rect(panels[to]);
+            }
+        }
+
+        let busy = self.drag.is_some() || self.dock.is_some() || dropped.is_some();
+        if let Some((from, to)) = dropped {
+            self.swap(from, to);
+            panels = self.rects(inputs.screen_rect, px).0;
+        }
+        for p in panels.iter_mut() {
+            *p = p.dilate(-gap / 2.0);
+        }
+        (panels, busy)
+    }
+}
+
+pub fn all_panels() -> [PanelId; NUM_PANELS] {
+    [
+        PanelId::Envelope, PanelId::Mids, PanelId::Filter, PanelId::Tuning, PanelId::Unison, PanelId::Fft,
+        PanelId::Roll, PanelId::Shape, PanelId::Spectrogram, PanelId::Scope,
+        PanelId::Sequencer, PanelId::Arp, PanelId::Looper, PanelId::Bend, PanelId::Keyboard,
+    ]
+}
+
+#[test]
+fn test_layout() {
+    let mut layout = Layout::new();
+    let screen = Rect::new(0.0, 0.0, 1.6, 1.0);
+    // big window, nothing is squeezed so its the old fixed splits
+    let (panels, splits) = layout.rects(screen, 1.0 / 2000.0);
+    assert_eq!(splits.len(), NUM_PANELS - 1);
+    let top = screen.split_ud(0.65).0.split_ud(0.7).0;
+    let env = top.split_lrn(6)[0];
+    assert!((panels[PanelId::Envelope as usize].w - env.w).abs() < 1e-5);
+    assert!((panels[PanelId::Keyboard as usize].bot() - 1.0).abs() < 1e-5);
+    // the panels tile the screen
+    let area: f32 = panels.iter().map(|r| r.w * r.h).sum();
+    assert!((area - screen.w * screen.h).abs() < 1e-4);
+
+    // tiny window, every panel gets its share of what it wants rather than some getting nothing
+    let (panels, _) = layout.rects(Rect::new(0.0, 0.0, 1.0, 1.0), 1.0 / 300.0);
+    for p in all_panels() {
+        assert!(panels[p as usize].w > 0.005 && panels[p as usize].h > 0.005, "{:?}", p);
+    }
+
+    // the bend wheel wants 16px, a split pushed past that stops there
+    *layout.split_mut(splits.len() - 1).unwrap() = 0.0;
+    let (panels, _) = layout.rects(screen, 1.0 / 1000.0);
+    assert!((panels[PanelId::Bend as usize].w - 0.016).abs() < 1e-5);
+
+    layout.swap(PanelId::Roll, PanelId::Scope);
+    let (swapped, _) = layout.rects(screen, 1.0 / 1000.0);
+    assert_eq!(swapped[PanelId::Roll as usize], panels[PanelId::Scope as usize]);
+    assert_eq!(swapped[PanelId::Scope as usize], panels[PanelId::Roll as usize]);
+}
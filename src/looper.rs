@@ -0,0 +1,383 @@
+use crate::krenderer::*;
+use crate::kinput::*;
+use crate::kmath::*;
+use crate::transport::*;
+use glutin::event::VirtualKeyCode;
+
+pub const LOOP_TRACKS: usize = 4;
+const UNDO_DEPTH: usize = 2;
+const MAX_LOOP_SECONDS: f32 = 30.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopCommand {
+    Pedal,      // one button looper: empty -> record -> play -> overdub -> play ...
+    Record,
+    Overdub,
+    Play,
+    Stop,
+    Undo,
+    Clear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoopState {
+    Empty,
+    Recording,
+    Playing,
+    Overdubbing,
+    Stopped,
+}
+
+impl LoopState {
+    // audio thread only, the gui shows whatever comes back in the analysis frames
+    pub fn after(&self, cmd: LoopCommand) -> LoopState {
+        match (self, cmd) {
+            (_, LoopCommand::Clear) => LoopState::Empty,
+            (LoopState::Empty, LoopCommand::Pedal | LoopCommand::Record | LoopCommand::Overdub) => LoopState::Recording,
+            (LoopState::Empty, _) => LoopState::Empty,
+            (LoopState::Recording, LoopCommand::Pedal | LoopCommand::Play | LoopCommand::Record) => LoopState::Playing,
+            (LoopState::Recording, LoopCommand::Overdub) => LoopState::Overdubbing,
+            (LoopState::Recording, LoopCommand::Stop) => LoopState::Stopped,
+            (LoopState::Recording, LoopCommand::Undo) => LoopState::Empty,
+            (LoopState::Playing | LoopState::Stopped, LoopCommand::Pedal | LoopCommand::Overdub | LoopCommand::Record) => LoopState::Overdubbing,
+            (LoopState::Overdubbing, LoopCommand::Pedal | LoopCommand::Play | LoopCommand::Overdub | LoopCommand::Record) => LoopState::Playing,
+            (_, LoopCommand::Play) => LoopState::Playing,
+            (_, LoopCommand::Stop) => LoopState::Stopped,
+            (s, LoopCommand::Undo) => *s,
+        }
+    }
+}
+
+// all the memory is got up front on the gui side, nothing here allocates once its on the audio thread.
+// the takes are a ring, overdubbing copies into the next one and undo steps back round
+pub struct LoopTrack {
+    pub state: LoopState,
+    bufs: Vec<Vec<f32>>,    // UNDO_DEPTH + 1, each as long as a loop can get
+    current: usize,
+    undos: usize,           // how many takes behind current are still good
+    len: usize,
+    head: usize,
+    wait: bool,             // armed, recording starts on the next bar line
+    closing: Option<(LoopState, usize)>,    // stop was pressed, finish the bar out to this length then go to that
+}
+
+impl LoopTrack {
+    pub fn new(max_len: usize) -> LoopTrack {
+        LoopTrack {
+            state: LoopState::Empty,
+            bufs: (0..UNDO_DEPTH + 1).map(|_| vec![0.0; max_len]).collect(),
+            current: 0,
+            undos: 0,
+            len: 0,
+            head: 0,
+            wait: false,
+            closing: None,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.bufs[self.current].len()
+    }
+
+    // bar is the bar length in samples if quantizing to a running transport
+    pub fn command(&mut self, cmd: LoopCommand, bar: Option<usize>) {
+        let next = self.state.after(cmd);
+        match (self.state, next) {
+            (_, LoopState::Empty) => {
+                self.len = 0;
+                self.head = 0;
+                self.undos = 0;
+                self.wait = false;
+                self.closing = None;
+            },
+            (LoopState::Empty, LoopState::Recording) => {
+                self.len = 0;
+                self.head = 0;
+                self.undos = 0;
+                self.wait = bar.is_some();
+            },
+            (LoopState::Recording, _) => {
+                // started on a bar line so going up to the next one keeps it in time
+                if let Some(bar) = bar.filter(|b| *b > 0) {
+                    let target = (((self.len + bar - 1) / bar).max(1) * bar).min(self.capacity());
+                    if target > self.len {
+                        self.closing = Some((next, target));
+                        return;
+                    }
+                }
+                self.close(next);
+                return;
+            },
+            (_, LoopState::Overdubbing) => self.push_undo(),
+            (_, LoopState::Stopped) => {
+                self.head = 0;
+            },
+            _ => {},
+        }
+        // nothing to go back to means you keep the first take, clear is for that
+        if cmd == LoopCommand::Undo && self.undos > 0 {
+            self.current = (self.current + UNDO_DEPTH) % (UNDO_DEPTH + 1);
+            self.undos -= 1;
+        }
+        self.state = next;
+    }
+
+    fn close(&mut self, next: LoopState) {
+        self.head = 0;
+        self.wait = false;
+        self.closing = None;
+        self.state = if self.len == 0 { LoopState::Empty } else { next };
+    }
+
+    fn push_undo(&mut self) {
+        let next = (self.current + 1) % (UNDO_DEPTH + 1);
+        // take it out so both can be borrowed, an empty vec doesnt allocate
+        let src = std::mem::take(&mut self.bufs[self.current]);
+        self.bufs[next][..self.len].copy_from_slice(&src[..self.len]);
+        self.bufs[self.current] = src;
+        self.current = next;
+        self.undos = (self.undos + 1).min(UNDO_DEPTH);
+    }
+
+    pub fn tick(&mut self, input: f32, bar_line: bool) -> f32 {
+        match self.state {
+            LoopState::Recording => {
+                if self.wait && !bar_line {
+                    return 0.0;
+                }
+                self.wait = false;
+                self.bufs[self.current][self.len] = input;
+                self.len += 1;
+                // full just closes the take like the pedal was hit
+                let done = self.closing.map_or(false, |(_, target)| self.len >= target) || self.len == self.capacity();
+                if done {
+                    self.close(self.closing.map_or(LoopState::Playing, |(s, _)| s));
+                }
+                0.0
+            },
+            LoopState::Playing => {
+                let out = self.bufs[self.current][self.head];
+                self.head = (self.head + 1) % self.len;
+                out
+            },
+            LoopState::Overdubbing => {
+                let buf = &mut self.bufs[self.current];
+                let out = buf[self.head];
+                buf[self.head] += input;
+                self.head = (self.head + 1) % self.len;
+                out
+            },
+            LoopState::Empty | LoopState::Stopped => 0.0,
+        }
+    }
+}
+
+// audio thread side, sits after the mixer. made on the gui side and moved over
+pub struct Looper {
+    pub tracks: Vec<LoopTrack>,
+    pub quantize: bool,
+    clock: Clock,   // its own copy of the transport so the bar lines land where the sequencers do
+}
+
+impl Looper {
+    pub fn new(sample_rate: f32) -> Looper {
+        let max_len = (sample_rate * MAX_LOOP_SECONDS) as usize;
+        Looper {
+            tracks: (0..LOOP_TRACKS).map(|_| LoopTrack::new(max_len)).collect(),
+            quantize: true,
+            clock: Clock::new(sample_rate),
+        }
+    }
+
+    pub fn set_transport(&mut self, t: Transport) {
+        self.clock.set_transport(t);
+    }
+
+    pub fn command(&mut self, track: usize, cmd: LoopCommand) {
+        // no bar lines to snap to with the transport stopped
+        let t = self.clock.transport;
+        let bar = self.clock.step_len() * t.steps_per_bar() as f64;
+        let bar = if self.quantize && t.playing { Some(bar.round() as usize) } else { None };
+        if let Some(t) = self.tracks.get_mut(track) {
+            t.command(cmd, bar);
+        }
+    }
+
+    pub fn states(&self) -> [LoopState; LOOP_TRACKS] {
+        let mut states = [LoopState::Empty; LOOP_TRACKS];
+        for (s, t) in states.iter_mut().zip(self.tracks.iter()) {
+            *s = t.state;
+        }
+        states
+    }
+
+    // takes the dry mixer output, gives back dry + loops
+    pub fn tick(&mut self, input: f32) -> f32 {
+        let steps_per_bar = self.clock.transport.steps_per_bar() as u64;
+        let bar_line = matches!(self.clock.tick(), Some(n) if n % steps_per_bar == 0);
+        let mut acc = input;
+        for t in self.tracks.iter_mut() {
+            acc += t.tick(input, bar_line);
+        }
+        acc
+    }
+}
+
+#[test]
+fn test_loop_track() {
+    let mut t = LoopTrack::new(16);
+    // armed til the bar line, then 5 samples goes on to the end of the bar of 4 before playing
+    t.command(LoopCommand::Pedal, Some(4));
+    t.tick(9.0, false);
+    for i in 0..5 {
+        t.tick(i as f32, i == 0);
+    }
+    t.command(LoopCommand::Pedal, Some(4));
+    assert_eq!(t.state, LoopState::Recording);
+    for i in 5..8 {
+        t.tick(i as f32, false);
+    }
+    assert_eq!(t.state, LoopState::Playing);
+    assert_eq!(t.len, 8);
+    let played: Vec<f32> = (0..10).map(|_| t.tick(0.0, false)).collect();
+    assert_eq!(played, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 0.0, 1.0]);
+
+    // overdub then undo it
+    t.command(LoopCommand::Pedal, Some(4));
+    assert_eq!(t.state, LoopState::Overdubbing);
+    for _ in 0..8 {
+        t.tick(10.0, false);
+    }
+    t.command(LoopCommand::Pedal, Some(4));
+    let played: Vec<f32> = (0..8).map(|_| t.tick(0.0, false)).collect();
+    assert_eq!(played, vec![12.0, 13.0, 14.0, 15.0, 16.0, 17.0, 10.0, 11.0]);
+    t.command(LoopCommand::Undo, Some(4));
+    let played: Vec<f32> = (0..2).map(|_| t.tick(0.0, false)).collect();
+    assert_eq!(played, vec![2.0, 3.0]);
+    // undos run out rather than going round the ring
+    for _ in 0..UNDO_DEPTH + 2 {
+        t.command(LoopCommand::Pedal, None);
+        t.tick(1.0, false);
+        t.command(LoopCommand::Pedal, None);
+    }
+    for _ in 0..UNDO_DEPTH + 2 {
+        t.command(LoopCommand::Undo, None);
+    }
+    assert_eq!(t.undos, 0);
+
+    t.command(LoopCommand::Clear, Some(4));
+    assert_eq!(t.state, LoopState::Empty);
+    assert_eq!(t.tick(1.0, false), 0.0);
+
+    // ending a take with nothing in it goes back to empty
+    t.command(LoopCommand::Record, None);
+    t.command(LoopCommand::Play, None);
+    assert_eq!(t.state, LoopState::Empty);
+
+    // running out of room closes the take instead of growing
+    t.command(LoopCommand::Record, None);
+    for i in 0..20 {
+        t.tick(i as f32, false);
+    }
+    assert_eq!(t.state, LoopState::Playing);
+    assert_eq!(t.len, 16);
+}
+
+// gui side, the states come back from the audio thread
+pub struct LooperPanel {
+    pub states: [LoopState; LOOP_TRACKS],
+    pub selected: usize,
+    pub quantize: bool,
+}
+
+impl LooperPanel {
+    pub fn new() -> LooperPanel {
+        LooperPanel { states: [LoopState::Empty; LOOP_TRACKS], selected: 0, quantize: true }
+    }
+
+    // F1-F4 is the pedal for each track, F5 stop/play, F6 undo, F7 clear the selected one
+    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, rect: Rect) -> Vec<(usize, LoopCommand)> {
+        let mut commands = Vec::new();
+        let clicked = |r: Rect| r.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::JustPressed;
+
+        let pedals = [VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4];
+        for i in 0..LOOP_TRACKS {
+            if inputs.key_rising(pedals[i]) {
+                self.selected = i;
+                commands.push((i, LoopCommand::Pedal));
+            }
+        }
+        if inputs.key_rising(VirtualKeyCode::F5) {
+            let cmd = if self.states[self.selected] == LoopState::Stopped { LoopCommand::Play } else { LoopCommand::Stop };
+            commands.push((self.selected, cmd));
+        }
+        if inputs.key_rising(VirtualKeyCode::F6) {
+            commands.push((self.selected, LoopCommand::Undo));
+        }
+        if inputs.key_rising(VirtualKeyCode::F7) {
+            commands.push((self.selected, LoopCommand::Clear));
+        }
+
+        kc.set_depth(1.1);
+        kc.set_colour(Vec4::new(0.6, 0.5, 0.3, 1.0));
+        kc.rect(rect);
+
+        let (title, rows) = rect.split_ud(0.15);
+        let (title_text, quantize_rect) = title.split_lr(0.6);
+        kc.set_depth(1.2);
+        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        kc.text_center("looper".as_bytes(), title_text);
+        if clicked(quantize_rect) {
+            self.quantize = !self.quantize;
+        }
+        kc.set_colour(if self.quantize { Vec4::new(0.4, 0.9, 0.4, 1.0) } else { Vec4::new(0.3, 0.3, 0.3, 1.0) });
+        kc.rect(quantize_rect.dilate_pc(-0.1));
+        kc.set_depth(1.3);
+        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        kc.text_center("bar".as_bytes(), quantize_rect.dilate_pc(-0.2));
+
+        let buttons = [
+            ("rec", LoopCommand::Record),
+            ("dub", LoopCommand::Overdub),
+            ("play", LoopCommand::Play),
+            ("stop", LoopCommand::Stop),
+            ("undo", LoopCommand::Undo),
+            ("clr", LoopCommand::Clear),
+        ];
+        for i in 0..LOOP_TRACKS {
+            let row = rows.grid_child(0, i as i32, 1, LOOP_TRACKS as i32).dilate_pc(-0.05);
+            let cells = row.split_lrn(buttons.len() as i32 + 1);
+
+            if clicked(row) {
+                self.selected = i;
+            }
+            kc.set_depth(1.2);
+            kc.set_colour(match self.states[i] {
+                LoopState::Empty => Vec4::new(0.2, 0.2, 0.2, 1.0),
+                LoopState::Recording => Vec4::new(0.9, 0.2, 0.2, 1.0),
+                LoopState::Overdubbing => Vec4::new(0.9, 0.6, 0.2, 1.0),
+                LoopState::Playing => Vec4::new(0.3, 0.8, 0.3, 1.0),
+                LoopState::Stopped => Vec4::new(0.3, 0.3, 0.6, 1.0),
+            });
+            kc.rect(cells[0].dilate_pc(-0.1));
+            kc.set_depth(1.3);
+            kc.set_colour(if self.selected == i { Vec4::new(1.0, 1.0, 0.6, 1.0) } else { Vec4::new(1.0, 1.0, 1.0, 1.0) });
+            kc.text_center(format!("{}", i + 1).as_bytes(), cells[0].dilate_pc(-0.2));
+
+            for (j, (label, cmd)) in buttons.iter().enumerate() {
+                let r = cells[j + 1].dilate_pc(-0.1);
+                if clicked(r) {
+                    commands.push((i, *cmd));
+                }
+                kc.set_depth(1.2);
+                kc.set_colour(Vec4::new(0.15, 0.15, 0.15, 1.0));
+                kc.rect(r);
+                kc.set_depth(1.3);
+                kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+                kc.text_center(label.as_bytes(), r.dilate_pc(-0.1));
+            }
+        }
+
+        commands
+    }
+}
@@ -51,18 +51,46 @@ mod krenderer;
 mod application;
 mod synth;
 mod filter;
+mod fir;
+mod convolver;
 mod sound;
 mod keyboard;
+mod widgets;
+mod units;
+mod midi;
+mod config;
+mod history;
+mod layout;
+mod keymap;
+mod bendwheel;
 mod fftviewer;
 mod envelope;
+mod breakpoints;
+mod transport;
+mod sequencer;
+mod arpeggiator;
+mod looper;
+mod recorder;
+mod pianoroll;
+mod tuning;
+mod unison;
+mod spectrogram;
+mod oscilloscope;
 use crate::kmath::*;
 use crate::synth::*;
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 
 fn main() -> anyhow::Result<()> {
+    let args = clap::Command::new("reeser")
+        .arg(clap::Arg::new("scl").long("scl").takes_value(true).help("scala scale file"))
+        .arg(clap::Arg::new("kbm").long("kbm").takes_value(true).help("scala keyboard mapping file"))
+        .arg(clap::Arg::new("ir").long("ir").takes_value(true).help("wav impulse response for the reverb"))
+        .arg(clap::Arg::new("midi").long("midi").takes_value(true).help("alsa raw midi device for controllers, like hw:1,0,0"))
+        .get_matches();
+
     let event_loop = glutin::event_loop::EventLoop::new();
-    let mut application = Application::new(&event_loop);
+    let mut application = Application::new(&event_loop, &args)?;
     
     event_loop.run(move |event, _, control_flow| {
         application.handle_event(&event);
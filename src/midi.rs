@@ -0,0 +1,141 @@
+use crate::kmath::*;
+
+// midi in from a hardware controller. raw midi off alsa, polled once a frame from the gui
+// side so its frame rate latency, fine for knobs and pedals
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MidiMessage {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    ControlChange { channel: u8, cc: u8, value: u8 },
+    PitchBend { channel: u8, value: f32 },  // -1..1
+}
+
+// bytes to messages. handles running status, skips realtime bytes and sysex
+pub struct MidiParser {
+    status: u8,
+    data: [u8; 2],
+    n: usize,
+}
+
+impl MidiParser {
+    pub fn new() -> MidiParser {
+        MidiParser { status: 0, data: [0; 2], n: 0 }
+    }
+
+    pub fn push(&mut self, b: u8) -> Option<MidiMessage> {
+        if b >= 0xf8 {
+            // clock and friends can turn up anywhere, even mid message
+            return None;
+        }
+        if b & 0x80 != 0 {
+            // system common and sysex cancel running status, nothing in them we want
+            self.status = if b < 0xf0 { b } else { 0 };
+            self.n = 0;
+            return None;
+        }
+        if self.status == 0 {
+            return None;
+        }
+        self.data[self.n] = b;
+        self.n += 1;
+        let kind = self.status & 0xf0;
+        let channel = self.status & 0x0f;
+        let len = if kind == 0xc0 || kind == 0xd0 { 1 } else { 2 };
+        if self.n < len {
+            return None;
+        }
+        self.n = 0;
+        let [d0, d1] = self.data;
+        match kind {
+            // note on with no velocity is how half the world sends note off
+            0x90 if d1 > 0 => Some(MidiMessage::NoteOn { channel, note: d0, velocity: d1 }),
+            0x90 | 0x80 => Some(MidiMessage::NoteOff { channel, note: d0 }),
+            0xb0 => Some(MidiMessage::ControlChange { channel, cc: d0, value: d1 }),
+            0xe0 => {
+                let v = ((d1 as i32) << 7 | d0 as i32) - 8192;
+                Some(MidiMessage::PitchBend { channel, value: (v as f32 / 8192.0).max(-1.0) })
+            },
+            _ => None,
+        }
+    }
+}
+
+pub struct MidiInput {
+    #[cfg(target_os = "linux")]
+    rawmidi: alsa::rawmidi::Rawmidi,
+    parser: MidiParser,
+}
+
+impl MidiInput {
+    // alsa device name, like hw:1,0,0. amidi -l lists them
+    #[cfg(target_os = "linux")]
+    pub fn open(device: &str) -> anyhow::Result<MidiInput> {
+        let rawmidi = alsa::rawmidi::Rawmidi::new(device, alsa::Direction::Capture, true)?;
+        Ok(MidiInput { rawmidi, parser: MidiParser::new() })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn open(_device: &str) -> anyhow::Result<MidiInput> {
+        Err(anyhow::Error::msg("midi in is only done for alsa so far"))
+    }
+
+    // whatever came in since last time, never blocks
+    pub fn poll(&mut self) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+        #[cfg(target_os = "linux")]
+        {
+            use std::io::Read;
+            let mut buf = [0u8; 256];
+            // nonblocking so this stops with EAGAIN once its drained
+            while let Ok(n) = self.rawmidi.io().read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                messages.extend(buf[..n].iter().filter_map(|b| self.parser.push(*b)));
+            }
+        }
+        messages
+    }
+}
+
+// a cc driving a control. lo and hi are where 0 and 127 land along the control, so a knob can
+// cover just part of it or run backwards
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiBinding {
+    pub channel: u8,
+    pub cc: u8,
+    pub lo: f32,
+    pub hi: f32,
+}
+
+impl MidiBinding {
+    pub fn new(channel: u8, cc: u8) -> MidiBinding {
+        MidiBinding { channel, cc, lo: 0.0, hi: 1.0 }
+    }
+
+    // 0..1 along the control
+    pub fn t(&self, value: u8) -> f32 {
+        lerp(self.lo, self.hi, value as f32 / 127.0)
+    }
+}
+
+#[test]
+fn test_midi_parse() {
+    let mut p = MidiParser::new();
+    let mut parse = |bytes: &[u8]| bytes.iter().filter_map(|b| p.push(*b)).collect::<Vec<_>>();
+    assert_eq!(parse(&[0x91, 60, 100]), vec![MidiMessage::NoteOn { channel: 1, note: 60, velocity: 100 }]);
+    // running status, and a velocity 0 note on is an off
+    assert_eq!(parse(&[62, 90, 60, 0]), vec![
+        MidiMessage::NoteOn { channel: 1, note: 62, velocity: 90 },
+        MidiMessage::NoteOff { channel: 1, note: 60 },
+    ]);
+    // clock in the middle of a cc doesnt break it
+    assert_eq!(parse(&[0xb0, 74, 0xf8, 127]), vec![MidiMessage::ControlChange { channel: 0, cc: 74, value: 127 }]);
+    // sysex is skipped and data after it without a status goes nowhere
+    assert_eq!(parse(&[0xf0, 0x7e, 0x01, 0xf7, 5, 6]), vec![]);
+    // program change is one data byte, so the next status isnt eaten
+    assert_eq!(parse(&[0xc0, 3, 0x80, 60, 64]), vec![MidiMessage::NoteOff { channel: 0, note: 60 }]);
+    assert_eq!(parse(&[0xe0, 0, 0x40]), vec![MidiMessage::PitchBend { channel: 0, value: 0.0 }]);
+    assert_eq!(parse(&[0xe0, 0, 0]), vec![MidiMessage::PitchBend { channel: 0, value: -1.0 }]);
+}
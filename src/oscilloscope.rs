@@ -0,0 +1,169 @@
+use crate::kmath::*;
+use crate::widgets::*;
+use crate::units::*;
+
+// most detuned voices the scope keeps separate traces for, same as the voices slider goes up to
+pub const SCOPE_VOICES: usize = 9;
+
+pub struct Oscilloscope {
+    samples: Vec<f32>,
+    voices: Vec<[f32; SCOPE_VOICES]>,
+    head: usize,
+    sample_rate: f32,
+
+    pub fundamental: Option<f32>,   // last note played, trigger locks to its period
+    pub trigger: bool,
+    pub trigger_level: f32,
+    pub periods: f32,               // how many periods of the fundamental across, or tens of ms if theres none
+    pub gain: f32,
+    pub show_voices: bool,
+}
+
+impl Oscilloscope {
+    pub fn new(len: usize, sample_rate: f32) -> Oscilloscope {
+        Oscilloscope {
+            samples: vec![0.0; len],
+            voices: vec![[0.0; SCOPE_VOICES]; len],
+            head: 0,
+            sample_rate,
+            fundamental: None,
+            trigger: true,
+            trigger_level: 0.0,
+            periods: 3.0,
+            gain: 2.0,
+            show_voices: false,
+        }
+    }
+
+    pub fn tick(&mut self, x: f32, voices: &[f32]) {
+        self.samples[self.head] = x;
+        for (dst, src) in self.voices[self.head].iter_mut().zip(voices) {
+            *dst = *src;
+        }
+        self.head = (self.head + 1) % self.samples.len();
+    }
+
+    // i samples back from the newest
+    fn sample(&self, back: usize) -> f32 {
+        let n = self.samples.len();
+        self.samples[(self.head + n - 1 - back % n) % n]
+    }
+
+    // how many samples the display spans
+    pub fn span(&self) -> usize {
+        let seconds = match self.fundamental {
+            Some(f) => self.periods / f,
+            None => self.periods * 0.01,
+        };
+        ((seconds * self.sample_rate) as usize).max(2).min(self.samples.len() / 2)
+    }
+
+    // how far back the display starts. a rising edge through the trigger level, looked for in the
+    // period just before the newest span so the same edge of the waveform lands at the left each frame
+    pub fn trigger_point(&self) -> usize {
+        let span = self.span();
+        if !self.trigger {
+            return span;
+        }
+        let search = match self.fundamental {
+            Some(f) => (self.sample_rate / f) as usize + 1,
+            None => span,
+        }.min(self.samples.len() - span - 1);
+        for back in span..span + search {
+            if self.sample(back + 1) < self.trigger_level && self.sample(back) >= self.trigger_level {
+                return back;
+            }
+        }
+        // nothing crossed, free run
+        span
+    }
+
+    pub fn frame(&mut self, ui: &mut Ui, r: Rect) {
+        ui.push_id("scope");
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.3, 0.4, 0.3, 1.0));
+        ui.kc.rect(r);
+
+        let (controls, plot) = r.split_lr(0.3);
+        let (buttons, sliders) = controls.split_ud(0.2);
+        let (trig_rect, voices_rect) = buttons.split_lr(0.5);
+        ui.toggle("trig", trig_rect, &mut self.trigger);
+        ui.toggle("vox", voices_rect, &mut self.show_voices);
+        let sliders = sliders.split_lrn(3);
+        ui.slider("time", sliders[0], 1.0, 16.0, &mut self.periods, SliderMapping::Stepped(1.0), Unit::None);
+        ui.slider("gain", sliders[1], 0.5, 20.0, &mut self.gain, SliderMapping::Exp, Unit::Gain);
+        ui.slider("lvl", sliders[2], -0.5, 0.5, &mut self.trigger_level, SliderMapping::Linear, Unit::None);
+
+        let plot = plot.dilate_pc(-0.02);
+        ui.kc.set_depth(1.2);
+        ui.kc.set_colour(Vec4::new(0.05, 0.1, 0.05, 1.0));
+        ui.kc.rect(plot);
+        ui.kc.set_depth(1.25);
+        ui.kc.set_colour(Vec4::new(0.2, 0.3, 0.2, 1.0));
+        ui.kc.rect(Rect::new(plot.x, plot.centroid().y, plot.w, 0.001));
+        let level_y = plot.centroid().y - self.trigger_level * self.gain * plot.h / 2.0;
+        if self.trigger && (plot.top()..plot.bot()).contains(&level_y) {
+            ui.kc.set_colour(Vec4::new(0.4, 0.3, 0.1, 1.0));
+            ui.kc.rect(Rect::new(plot.x, level_y, plot.w * 0.05, 0.001));
+        }
+
+        let span = self.span();
+        let start = self.trigger_point();
+        // at least 2 so the x scale below doesnt divide by 0
+        let points = span.min(400).max(2);
+        let to_screen = |i: usize, v: f32| Vec2::new(
+            plot.x + plot.w * i as f32 / (points - 1) as f32,
+            plot.centroid().y - (v * self.gain).max(-1.0).min(1.0) * plot.h / 2.0,
+        );
+        let back = |i: usize| start - i * span / points;
+
+        // voices underneath in their own colours so you can see them cancel out
+        if self.show_voices {
+            let n = self.samples.len();
+            for v in 0..SCOPE_VOICES {
+                let t = v as f32 / SCOPE_VOICES as f32;
+                ui.kc.set_depth(1.3);
+                ui.kc.set_colour(Vec4::new(0.3 + 0.7 * t, 0.5, 1.0 - 0.7 * t, 0.6));
+                let voice = |b: usize| self.voices[(self.head + n - 1 - b) % n][v];
+                if (0..points).all(|i| voice(back(i)) == 0.0) {
+                    continue;
+                }
+                for i in 1..points {
+                    ui.kc.line(to_screen(i - 1, voice(back(i - 1))), to_screen(i, voice(back(i))), 0.0015);
+                }
+            }
+        }
+
+        ui.kc.set_depth(1.4);
+        ui.kc.set_colour(Vec4::new(0.6, 1.0, 0.6, 1.0));
+        for i in 1..points {
+            ui.kc.line(to_screen(i - 1, self.sample(back(i - 1))), to_screen(i, self.sample(back(i))), 0.003);
+        }
+
+        ui.kc.set_depth(1.5);
+        ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        let label = match self.fundamental {
+            Some(f) => format!("{:.0} periods of {:.1}hz", self.periods, f),
+            None => format!("{:.0}ms", self.periods * 10.0),
+        };
+        ui.kc.text_left(label.as_bytes(), plot.child(0.02, 0.02, 1.0, 0.08));
+        ui.pop_id();
+    }
+}
+
+#[test]
+fn test_scope_trigger() {
+    // sine at 100hz, 1000 samples a second, trigger should always land on the rising zero crossing
+    let mut s = Oscilloscope::new(1000, 1000.0);
+    s.fundamental = Some(100.0);
+    s.periods = 2.0;
+    for offset in 0..10 {
+        for i in 0..337 + offset {
+            let x = (2.0 * std::f32::consts::PI * i as f32 / 10.0 + 0.3).sin();
+            s.tick(x, &[x]);
+        }
+        let t = s.trigger_point();
+        assert!(t >= s.span() && t <= s.span() + 11);
+        assert!(s.sample(t + 1) < 0.0 && s.sample(t) >= 0.0);
+    }
+}
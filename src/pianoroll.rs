@@ -0,0 +1,202 @@
+use crate::kinput::*;
+use crate::kmath::*;
+use crate::sound::*;
+use crate::recorder::*;
+use crate::widgets::*;
+use glutin::event::VirtualKeyCode;
+
+pub enum PianoRollAction {
+    Capture,
+    ExportMidi,
+    ExportWav,
+}
+
+// editor for clips pulled out of the input recorder
+pub struct PianoRoll {
+    pub clip: Option<Clip>,
+    pub capture_seconds: f32,
+    selected: Option<usize>,
+    drag_from: Option<(Vec2, ClipNote)>,
+}
+
+impl PianoRoll {
+    pub fn new() -> PianoRoll {
+        PianoRoll {
+            clip: None,
+            capture_seconds: 60.0,
+            selected: None,
+            drag_from: None,
+        }
+    }
+
+    // a fresh clip, the old selection and drag point into notes that arent there anymore
+    pub fn set_clip(&mut self, clip: Clip) {
+        self.clip = Some(clip);
+        self.selected = None;
+        self.drag_from = None;
+    }
+
+    // grid is the quantize step in seconds
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect, grid: f64) -> Vec<PianoRollAction> {
+        let mut actions = Vec::new();
+        let inputs = &ui.raw_inputs();
+        let typing = ui.typing();
+        let kc = &mut *ui.kc;
+        let clicked = |r: Rect| r.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::JustPressed;
+
+        kc.set_depth(1.1);
+        kc.set_colour(Vec4::new(0.3, 0.4, 0.4, 1.0));
+        kc.rect(rect);
+
+        let (controls, roll) = rect.split_lr(0.15);
+        let buttons = ["capture", "quant", "del", "midi", "wav"];
+        for (i, label) in buttons.iter().enumerate() {
+            let r = controls.grid_child(0, i as i32, 1, buttons.len() as i32 + 1).dilate_pc(-0.1);
+            kc.set_depth(1.2);
+            kc.set_colour(Vec4::new(0.15, 0.15, 0.15, 1.0));
+            kc.rect(r);
+            kc.set_depth(1.3);
+            kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+            kc.text_center(label.as_bytes(), r.dilate_pc(-0.1));
+            if !clicked(r) {
+                continue;
+            }
+            match i {
+                0 => actions.push(PianoRollAction::Capture),
+                1 => if let Some(clip) = self.clip.as_mut() { clip.quantize(grid) },
+                2 => self.delete_selected(),
+                3 => actions.push(PianoRollAction::ExportMidi),
+                _ => actions.push(PianoRollAction::ExportWav),
+            }
+        }
+        let length_rect = controls.grid_child(0, buttons.len() as i32, 1, buttons.len() as i32 + 1).dilate_pc(-0.1);
+        kc.set_depth(1.3);
+        kc.text_center(format!("{}s", self.capture_seconds as i32).as_bytes(), length_rect);
+        if clicked(length_rect) {
+            // cycles through how far back to capture
+            self.capture_seconds = match self.capture_seconds as i32 { 15 => 60.0, 60 => 300.0, _ => 15.0 };
+        }
+        // delete in a text box is for the text
+        if inputs.key_rising(VirtualKeyCode::Delete) && !typing {
+            self.delete_selected();
+        }
+
+        kc.set_depth(1.2);
+        kc.set_colour(Vec4::new(0.1, 0.1, 0.1, 1.0));
+        kc.rect(roll);
+
+        let clip = match self.clip.as_mut() {
+            Some(clip) if clip.notes.len() > 0 => clip,
+            _ => {
+                kc.set_depth(1.3);
+                kc.set_colour(Vec4::new(0.6, 0.6, 0.6, 1.0));
+                kc.text_center("nothing captured".as_bytes(), roll.dilate_pc(-0.3));
+                return actions;
+            },
+        };
+
+        let lo = clip.notes.iter().map(|n| n.note()).min().unwrap() - 2;
+        let hi = clip.notes.iter().map(|n| n.note()).max().unwrap() + 2;
+        let rows = (hi - lo + 1) as f32;
+        let len = clip.len.max(grid) as f32 * 1.1;
+        let note_rect = |n: &ClipNote| Rect::new(
+            roll.x + roll.w * n.start as f32 / len,
+            roll.y + roll.h * (hi - n.note()) as f32 / rows,
+            roll.w * n.len as f32 / len,
+            roll.h / rows,
+        );
+
+        // black key rows and grid lines
+        kc.set_depth(1.25);
+        for note in lo..=hi {
+            if [1, 3, 6, 8, 10].contains(&(69 + note).rem_euclid(12)) {
+                kc.set_colour(Vec4::new(0.15, 0.15, 0.15, 1.0));
+                kc.rect(Rect::new(roll.x, roll.y + roll.h * (hi - note) as f32 / rows, roll.w, roll.h / rows));
+            }
+        }
+        kc.set_colour(Vec4::new(0.25, 0.25, 0.25, 1.0));
+        let mut t = 0.0;
+        while t < len as f64 && grid > 0.0 {
+            kc.rect(Rect::new(roll.x + roll.w * t as f32 / len, roll.y, 0.001, roll.h));
+            t += grid;
+        }
+
+        // click to select, drag to move in time and pitch
+        if inputs.lmb == KeyStatus::JustPressed && roll.contains(inputs.mouse_pos) {
+            self.selected = clip.notes.iter().position(|n| note_rect(n).contains(inputs.mouse_pos));
+            self.drag_from = self.selected.map(|i| (inputs.mouse_pos, clip.notes[i]));
+        }
+        if self.selected.map_or(false, |i| i >= clip.notes.len()) {
+            self.selected = None;
+        }
+        if inputs.lmb != KeyStatus::Pressed && inputs.lmb != KeyStatus::JustPressed {
+            self.drag_from = None;
+        }
+        if let (Some(i), Some((from, orig))) = (self.selected, self.drag_from) {
+            let d = inputs.mouse_pos - from;
+            let n = &mut clip.notes[i];
+            n.start = (orig.start + (d.x / roll.w * len) as f64).max(0.0);
+            let semis = -(d.y / (roll.h / rows)).round();
+            n.freq = orig.freq * 2.0f32.powf(semis / 12.0);
+            clip.len = clip.notes.iter().map(|n| n.start + n.len).fold(0.0, f64::max);
+        }
+
+        kc.set_depth(1.3);
+        for (i, n) in clip.notes.iter().enumerate() {
+            kc.set_colour(if self.selected == Some(i) {
+                Vec4::new(1.0, 0.9, 0.4, 1.0)
+            } else {
+                Vec4::new(0.4, 0.8, 0.6, 1.0)
+            });
+            kc.rect(note_rect(n));
+        }
+        kc.set_depth(1.4);
+        kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        for note in lo..=hi {
+            if (69 + note).rem_euclid(12) == 0 {
+                kc.text_left(note_name(note).as_bytes(), Rect::new(roll.x, roll.y + roll.h * (hi - note) as f32 / rows, roll.w, roll.h / rows));
+            }
+        }
+
+        actions
+    }
+
+    fn delete_selected(&mut self) {
+        if let (Some(clip), Some(i)) = (self.clip.as_mut(), self.selected.take()) {
+            if i >= clip.notes.len() {
+                return;
+            }
+            clip.notes.remove(i);
+            clip.len = clip.notes.iter().map(|n| n.start + n.len).fold(0.0, f64::max);
+        }
+        self.drag_from = None;
+    }
+}
+
+#[test]
+fn test_recapture_delete() {
+    use crate::keyboard::KeyboardEvent;
+    let mut r = InputRecorder::new(60.0);
+    for (i, t) in [0.0, 1.0, 2.0].iter().enumerate() {
+        r.push(*t, KeyboardEvent { uid: i as u32, freq: 440.0, velocity: 1.0, pressed: true });
+        r.push(*t + 0.5, KeyboardEvent { uid: i as u32, freq: 440.0, velocity: 0.0, pressed: false });
+    }
+    let mut pr = PianoRoll::new();
+    pr.set_clip(r.capture(3.0, 60.0));
+    assert_eq!(pr.clip.as_ref().unwrap().notes.len(), 3);
+    pr.selected = Some(2);
+
+    // only the last note is in the last second, the old selection is past the end
+    pr.set_clip(r.capture(3.0, 1.0));
+    assert_eq!(pr.clip.as_ref().unwrap().notes.len(), 1);
+    pr.delete_selected();
+    assert_eq!(pr.clip.as_ref().unwrap().notes.len(), 1);
+
+    // and a stale index that got in some other way is ignored too
+    pr.selected = Some(5);
+    pr.delete_selected();
+    assert_eq!(pr.clip.as_ref().unwrap().notes.len(), 1);
+    pr.selected = Some(0);
+    pr.delete_selected();
+    assert_eq!(pr.clip.as_ref().unwrap().notes.len(), 0);
+}
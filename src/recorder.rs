@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::io::Write;
+
+use crate::keyboard::*;
+use crate::sound::*;
+use crate::filter::*;
+
+// always on, remembers everything you play so you can grab it after the fact
+pub struct InputRecorder {
+    events: VecDeque<(f64, KeyboardEvent)>,
+    pub max_seconds: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipNote {
+    pub start: f64,     // seconds from the start of the clip
+    pub len: f64,
+    pub freq: f32,
+    pub velocity: f32,
+}
+
+impl ClipNote {
+    // nearest semitone from A440, what the piano roll and midi go by
+    pub fn note(&self) -> i32 {
+        (12.0 * (self.freq / 440.0).log2()).round() as i32
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip {
+    pub notes: Vec<ClipNote>,
+    pub len: f64,
+}
+
+impl InputRecorder {
+    pub fn new(max_seconds: f64) -> InputRecorder {
+        InputRecorder { events: VecDeque::new(), max_seconds }
+    }
+
+    pub fn push(&mut self, t: f64, e: KeyboardEvent) {
+        self.events.push_back((t, e));
+        while let Some((t_front, _)) = self.events.front() {
+            if t - t_front > self.max_seconds {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    // last `seconds` worth up to now, anything still held gets cut off at now
+    pub fn capture(&self, now: f64, seconds: f64) -> Clip {
+        let from = now - seconds;
+        let mut notes = Vec::new();
+        let mut open: Vec<(u32, f64, f32, f32)> = Vec::new();
+        for (t, e) in self.events.iter() {
+            if e.pressed {
+                open.push((e.uid, *t, e.freq, e.velocity));
+            } else if let Some(i) = open.iter().position(|(uid, _, _, _)| *uid == e.uid) {
+                let (_, start, freq, velocity) = open.remove(i);
+                if *t >= from {
+                    notes.push(ClipNote { start, len: t - start, freq, velocity });
+                }
+            }
+        }
+        for (_, start, freq, velocity) in open {
+            notes.push(ClipNote { start, len: now - start, freq, velocity });
+        }
+
+        // start the clip at the first note rather than the window edge
+        let first = notes.iter().map(|n| n.start).fold(f64::INFINITY, f64::min).max(from);
+        for n in notes.iter_mut() {
+            if n.start < first {
+                n.len -= first - n.start;
+                n.start = first;
+            }
+            n.start -= first;
+        }
+        notes.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+        let len = notes.iter().map(|n| n.start + n.len).fold(0.0, f64::max);
+        Clip { notes, len }
+    }
+}
+
+impl Clip {
+    pub fn quantize(&mut self, grid: f64) {
+        for n in self.notes.iter_mut() {
+            let end = ((n.start + n.len) / grid).round() * grid;
+            n.start = (n.start / grid).round() * grid;
+            n.len = (end - n.start).max(grid);
+        }
+        self.len = self.notes.iter().map(|n| n.start + n.len).fold(0.0, f64::max);
+    }
+
+    // plays the notes through a fresh mixer with whatever the patch is now
+    pub fn render(&self, sound: Sound, filter: &Filter, sample_rate: f32) -> Vec<f32> {
+        let mut mixer = Mixer::new(sample_rate);
+        mixer.set_filter(Box::new(filter.clone()));
+        let tail = sound.envelope.r as f64 + 0.1;
+        let total = ((self.len + tail) * sample_rate as f64) as usize;
+        let mut on: Vec<(usize, usize)> = self.notes.iter().enumerate()
+            .map(|(i, n)| ((n.start * sample_rate as f64) as usize, i))
+            .collect();
+        let mut off: Vec<(usize, usize)> = self.notes.iter().enumerate()
+            .map(|(i, n)| (((n.start + n.len) * sample_rate as f64) as usize, i))
+            .collect();
+        on.sort();
+        off.sort();
+        let (mut on_i, mut off_i) = (0, 0);
+        let mut out = Vec::with_capacity(total);
+        for samp in 0..total {
+            while off_i < off.len() && off[off_i].0 <= samp {
+                mixer.stop_sound(off[off_i].1 as u32);
+                off_i += 1;
+            }
+            while on_i < on.len() && on[on_i].0 <= samp {
+                let n = self.notes[on[on_i].1];
+                let mut s = sound;
+                s.freq = n.freq;
+                s.amplitude *= n.velocity;
+                mixer.add_sound(s, on[on_i].1 as u32);
+                on_i += 1;
+            }
+            out.push(mixer.tick());
+        }
+        out
+    }
+
+    pub fn write_wav(&self, path: &str, sound: Sound, filter: &Filter, sample_rate: f32) -> anyhow::Result<()> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: sample_rate as u32,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        for s in self.render(sound, filter, sample_rate) {
+            writer.write_sample(s)?;
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    // standard midi file, format 0, one track
+    pub fn midi_bytes(&self, bpm: f32) -> Vec<u8> {
+        let ppq: u16 = 480;
+        let ticks_per_second = ppq as f64 * bpm as f64 / 60.0;
+        let to_ticks = |t: f64| (t * ticks_per_second).round() as u32;
+
+        // (tick, is_on, note, velocity). offs sort before ons at the same tick
+        let mut events: Vec<(u32, bool, u8, u8)> = Vec::new();
+        for n in self.notes.iter() {
+            let key = (69 + n.note()).max(0).min(127) as u8;
+            let vel = ((n.velocity * 127.0) as u8).max(1).min(127);
+            events.push((to_ticks(n.start), true, key, vel));
+            events.push((to_ticks(n.start + n.len), false, key, 0));
+        }
+        events.sort_by_key(|e| (e.0, e.1));
+
+        let mut track = Vec::new();
+        let tempo = (60_000_000.0 / bpm) as u32;
+        track.extend(&[0x00, 0xFF, 0x51, 0x03]);
+        track.extend(&tempo.to_be_bytes()[1..]);
+        let mut last = 0;
+        for (tick, on, key, vel) in events {
+            write_vlq(&mut track, tick - last);
+            last = tick;
+            track.extend(&[if on { 0x90 } else { 0x80 }, key, vel]);
+        }
+        track.extend(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let mut bytes = Vec::new();
+        bytes.extend(b"MThd");
+        bytes.extend(&6u32.to_be_bytes());
+        bytes.extend(&0u16.to_be_bytes());
+        bytes.extend(&1u16.to_be_bytes());
+        bytes.extend(&ppq.to_be_bytes());
+        bytes.extend(b"MTrk");
+        bytes.extend(&(track.len() as u32).to_be_bytes());
+        bytes.extend(track);
+        bytes
+    }
+
+    pub fn write_midi(&self, path: &str, bpm: f32) -> anyhow::Result<()> {
+        let mut f = std::fs::File::create(path)?;
+        f.write_all(&self.midi_bytes(bpm))?;
+        Ok(())
+    }
+}
+
+pub fn write_vlq(buf: &mut Vec<u8>, mut x: u32) {
+    let mut stack = vec![(x & 0x7F) as u8];
+    x >>= 7;
+    while x > 0 {
+        stack.push((x & 0x7F) as u8 | 0x80);
+        x >>= 7;
+    }
+    buf.extend(stack.iter().rev());
+}
+
+#[test]
+fn test_vlq() {
+    let enc = |x| { let mut v = Vec::new(); write_vlq(&mut v, x); v };
+    assert_eq!(enc(0), vec![0x00]);
+    assert_eq!(enc(0x7F), vec![0x7F]);
+    assert_eq!(enc(0x80), vec![0x81, 0x00]);
+    assert_eq!(enc(0x3FFF), vec![0xFF, 0x7F]);
+    assert_eq!(enc(0x0FFFFFFF), vec![0xFF, 0xFF, 0xFF, 0x7F]);
+}
+
+#[test]
+fn test_capture() {
+    let mut r = InputRecorder::new(60.0);
+    r.push(10.0, KeyboardEvent { uid: 1, freq: 440.0, velocity: 1.0, pressed: true });
+    r.push(10.5, KeyboardEvent { uid: 2, freq: 880.0, velocity: 0.5, pressed: true });
+    r.push(11.0, KeyboardEvent { uid: 1, freq: 0.0, velocity: 0.0, pressed: false });
+    let clip = r.capture(12.0, 30.0);
+    assert_eq!(clip.notes.len(), 2);
+    assert_eq!(clip.notes[0], ClipNote { start: 0.0, len: 1.0, freq: 440.0, velocity: 1.0 });
+    assert_eq!(clip.notes[1], ClipNote { start: 0.5, len: 1.5, freq: 880.0, velocity: 0.5 });
+    assert_eq!(clip.notes[1].note(), 12);
+    assert_eq!(clip.len, 2.0);
+
+    // stuff older than the buffer gets dropped
+    r.push(200.0, KeyboardEvent { uid: 3, freq: 440.0, velocity: 1.0, pressed: true });
+    assert_eq!(r.capture(201.0, 300.0).notes.len(), 1);
+}
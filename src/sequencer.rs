@@ -0,0 +1,375 @@
+use crate::kinput::*;
+use crate::kmath::*;
+use crate::sound::*;
+use crate::widgets::*;
+use crate::units::*;
+use crate::transport::*;
+use crate::tuning::*;
+use glutin::event::VirtualKeyCode;
+
+// ids for sequencer voices have the top bit set, the keyboard masks it off its ones so they cant collide
+pub const SEQUENCER_ID_BIT: u32 = 0x8000_0000;
+pub const MAX_STEPS: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub on: bool,
+    pub note: i32,      // semitones from A440
+    pub velocity: f32,
+    pub gate: f32,      // fraction of the step
+    pub slide: bool,    // glide into the next step instead of retriggering
+    pub accent: bool,
+}
+
+impl Step {
+    pub fn new() -> Step {
+        Step { on: false, note: -12, velocity: 0.8, gate: 0.5, slide: false, accent: false }
+    }
+}
+
+// fixed size so it goes over to the audio thread without allocating
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pattern {
+    steps: [Step; MAX_STEPS],
+    len: usize,
+}
+
+impl Pattern {
+    pub fn new(len: usize) -> Pattern {
+        let len = len.min(MAX_STEPS);
+        let mut steps = [Step::new(); MAX_STEPS];
+        // something to start with
+        for (i, note) in [(0, -12), (3, -12), (6, 0), (8, -12), (10, -9), (11, -7), (14, -12)] {
+            if i < len {
+                steps[i].on = true;
+                steps[i].note = note;
+            }
+        }
+        Pattern { steps, len }
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps[..self.len]
+    }
+
+    pub fn steps_mut(&mut self) -> &mut [Step] {
+        &mut self.steps[..self.len]
+    }
+
+    // new steps come in blank
+    pub fn set_len(&mut self, len: usize) {
+        let len = len.min(MAX_STEPS);
+        if len > self.len {
+            self.steps[self.len..len].iter_mut().for_each(|s| *s = Step::new());
+        }
+        self.len = len;
+    }
+
+    // worked out gui side where the tuning lives. None is a key the tuning leaves out, it rests
+    pub fn freqs(&self, tuning: &Tuning) -> [Option<f32>; MAX_STEPS] {
+        let mut freqs = [None; MAX_STEPS];
+        for (f, step) in freqs.iter_mut().zip(self.steps().iter()) {
+            *f = tuning.freq(69 + step.note);
+        }
+        freqs
+    }
+}
+
+// audio thread side
+pub struct SequencePlayer {
+    pub clock: Clock,
+    pattern: Pattern,
+    freqs: [Option<f32>; MAX_STEPS],
+    sound: Sound,
+    voice: Option<u32>,
+    gate_left: Option<u64>,     // samples until release, None is hold (sliding or nothing playing)
+    sliding: bool,              // last step slid so the next one glides
+    counter: u32,
+}
+
+impl SequencePlayer {
+    pub fn new(sample_rate: f32) -> SequencePlayer {
+        let pattern = Pattern::new(16);
+        SequencePlayer {
+            clock: Clock::new(sample_rate),
+            freqs: pattern.freqs(&Tuning::new()),
+            pattern,
+            sound: Sound::new(),
+            voice: None,
+            gate_left: None,
+            sliding: false,
+            counter: 0,
+        }
+    }
+
+    pub fn set_transport(&mut self, t: Transport) {
+        self.clock.set_transport(t);
+    }
+
+    pub fn set_pattern(&mut self, pattern: Pattern, freqs: [Option<f32>; MAX_STEPS], sound: Sound) {
+        self.pattern = pattern;
+        self.freqs = freqs;
+        self.sound = sound;
+    }
+
+    fn release(&mut self, mixer: &mut Mixer) {
+        if let Some(id) = self.voice.take() {
+            mixer.stop_sound(id);
+        }
+        self.gate_left = None;
+        self.sliding = false;
+    }
+
+    // call once per sample before the mixer ticks
+    pub fn tick(&mut self, mixer: &mut Mixer) {
+        if !self.clock.transport.playing {
+            self.release(mixer);
+            return;
+        }
+
+        if let Some(left) = self.gate_left {
+            if left == 0 {
+                self.release(mixer);
+            } else {
+                self.gate_left = Some(left - 1);
+            }
+        }
+
+        let n = match self.clock.tick() {
+            Some(n) => n,
+            None => return,
+        };
+        if self.pattern.len == 0 {
+            return;
+        }
+        let i = n as usize % self.pattern.len;
+        let step = self.pattern.steps[i];
+        let freq = match self.freqs[i] {
+            Some(f) if step.on => f,
+            _ => {
+                self.release(mixer);
+                return;
+            },
+        };
+
+        let step_len = self.clock.step_len();
+        match (self.voice, self.sliding) {
+            (Some(id), true) => {
+                mixer.glide_sound(id, freq, (step_len * 0.25) as u32);
+            },
+            _ => {
+                self.release(mixer);
+                let mut s = self.sound;
+                s.freq = freq;
+                s.amplitude *= step.velocity * if step.accent { 1.5 } else { 1.0 };
+                self.counter = self.counter.wrapping_add(1);
+                let id = SEQUENCER_ID_BIT | (self.counter & !SEQUENCER_ID_BIT);
+                mixer.add_sound(s, id);
+                self.voice = Some(id);
+            },
+        }
+        self.sliding = step.slide;
+        self.gate_left = if step.slide { None } else { Some((step.gate * step_len as f32) as u64) };
+    }
+}
+
+// gui side
+pub struct Sequencer {
+    pub pattern: Pattern,
+    pub transport: Transport,
+    clock: Clock,   // just for the playhead
+}
+
+impl Sequencer {
+    pub fn new(sample_rate: f32) -> Sequencer {
+        Sequencer {
+            pattern: Pattern::new(16),
+            transport: Transport::new(),
+            clock: Clock::new(sample_rate),
+        }
+    }
+
+    // whats playing going by the playhead, as a midi note. the audio thread has the real clock
+    // but this is close enough to light a key up
+    pub fn sounding(&self) -> Option<i32> {
+        if !self.transport.playing || self.pattern.len == 0 {
+            return None;
+        }
+        let pos = self.clock.pos();
+        let step = self.pattern.steps[pos as usize % self.pattern.len];
+        if step.on && (step.slide || (pos.fract() as f32) < step.gate) {
+            Some(69 + step.note)
+        } else {
+            None
+        }
+    }
+
+    // returns modification
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect) -> bool {
+        // the step grid is hand rolled, the rest goes through ui
+        let inputs = ui.raw_inputs();
+        ui.push_id("sequencer");
+        let mut changed = false;
+        let clicked = |r: Rect| r.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::JustPressed;
+        let dragging = |r: Rect| r.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::Pressed;
+
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.5, 0.4, 0.6, 1.0));
+        ui.kc.rect(rect);
+
+        let (controls, grid) = rect.split_lr(0.25);
+        let (title, controls) = controls.split_ud(0.15);
+        let (title_text, play_rect) = title.split_lr(0.5);
+        ui.kc.set_depth(1.2);
+        ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        ui.kc.text_center("seq".as_bytes(), title_text);
+
+        let label = if self.transport.playing { "stop" } else { "play" };
+        if ui.toggle(label, play_rect, &mut self.transport.playing) {
+            changed = true;
+        } else if inputs.key_rising(VirtualKeyCode::Space) {
+            self.transport.playing = !self.transport.playing;
+            changed = true;
+        }
+
+        let slider_rects = controls.split_lrn(4);
+        let mut beats_per_bar = self.transport.beats_per_bar as f32;
+        let mut steps_per_beat = self.transport.steps_per_beat as f32;
+        changed |= ui.slider("bpm", slider_rects[0], 40.0, 240.0, &mut self.transport.bpm, SliderMapping::Linear, Unit::Bpm) |
+            ui.slider("swing", slider_rects[1], 0.0, 1.0, &mut self.transport.swing, SliderMapping::Linear, Unit::Percent) |
+            ui.slider("beats", slider_rects[2], 1.0, 8.0, &mut beats_per_bar, SliderMapping::Stepped(1.0), Unit::None) |
+            ui.slider("div", slider_rects[3], 1.0, 4.0, &mut steps_per_beat, SliderMapping::Stepped(1.0), Unit::None);
+        self.transport.beats_per_bar = beats_per_bar.round() as u32;
+        self.transport.steps_per_beat = steps_per_beat.round() as u32;
+
+        // pattern follows the time signature, 1 bar
+        let len = (self.transport.steps_per_bar() as usize).min(MAX_STEPS);
+        if len != self.pattern.len {
+            self.pattern.set_len(len);
+            changed = true;
+        }
+
+        self.clock.set_transport(self.transport);
+        self.clock.advance_seconds(inputs.dt);
+        let playhead = if self.transport.playing { Some(self.clock.pos() as usize % len) } else { None };
+
+        // row layout
+        let rows = [0.15, 0.2, 0.25, 0.25, 0.075, 0.075];
+        let labels = ["on", "note", "vel", "gate", "sl", "acc"];
+        let row_rects = |r: Rect| {
+            let mut y = 0.0;
+            rows.iter().map(|h| { let c = r.child(0.0, y, 1.0, *h); y += h; c }).collect::<Vec<Rect>>()
+        };
+
+        let columns = grid.split_lrn(len as i32 + 1);
+        ui.kc.set_depth(1.2);
+        ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        for (label, r) in labels.iter().zip(row_rects(columns[0])) {
+            ui.kc.text_center(label.as_bytes(), r.dilate_pc(-0.2));
+        }
+
+        for i in 0..len {
+            let col = columns[i + 1];
+            let step = &mut self.pattern.steps_mut()[i];
+            let r = row_rects(col);
+
+            ui.kc.set_depth(1.2);
+            ui.kc.set_colour(if playhead == Some(i) {
+                Vec4::new(0.8, 0.7, 0.3, 1.0)
+            } else if i as u32 % self.transport.steps_per_beat == 0 {
+                Vec4::new(0.35, 0.3, 0.45, 1.0)
+            } else {
+                Vec4::new(0.25, 0.2, 0.3, 1.0)
+            });
+            ui.kc.rect(col.dilate_pc(-0.05));
+
+            ui.kc.set_depth(1.3);
+            if clicked(r[0]) {
+                step.on = !step.on;
+                changed = true;
+            }
+            ui.kc.set_colour(if step.on { Vec4::new(0.9, 0.5, 0.2, 1.0) } else { Vec4::new(0.1, 0.1, 0.1, 1.0) });
+            ui.kc.rect(r[0].dilate_pc(-0.15));
+
+            // top half up, bottom half down
+            let (up, down) = r[1].split_ud(0.5);
+            if clicked(up) {
+                step.note += 1;
+                changed = true;
+            }
+            if clicked(down) {
+                step.note -= 1;
+                changed = true;
+            }
+            ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+            ui.kc.text_center(note_name(step.note).as_bytes(), r[1].dilate_pc(-0.1));
+
+            for (val, bar) in [(&mut step.velocity, r[2]), (&mut step.gate, r[3])] {
+                let bar = bar.dilate_pc(-0.1);
+                if dragging(bar) {
+                    *val = unlerp(inputs.mouse_pos.y, bar.bot(), bar.top()).max(0.0).min(1.0);
+                    changed = true;
+                }
+                ui.kc.set_colour(Vec4::new(0.1, 0.1, 0.1, 1.0));
+                ui.kc.rect(bar);
+                ui.kc.set_depth(1.4);
+                ui.kc.set_colour(Vec4::new(0.7, 0.7, 0.9, 1.0));
+                ui.kc.rect(bar.child(0.0, 1.0 - *val, 1.0, *val));
+                ui.kc.set_depth(1.3);
+            }
+
+            for (flag, r, colour) in [(&mut step.slide, r[4], Vec4::new(0.3, 0.8, 0.9, 1.0)), (&mut step.accent, r[5], Vec4::new(0.9, 0.3, 0.3, 1.0))] {
+                if clicked(r) {
+                    *flag = !*flag;
+                    changed = true;
+                }
+                ui.kc.set_colour(if *flag { colour } else { Vec4::new(0.1, 0.1, 0.1, 1.0) });
+                ui.kc.rect(r.dilate_pc(-0.15));
+            }
+        }
+
+        ui.pop_id();
+        changed
+    }
+}
+
+#[test]
+fn test_sequence_player() {
+    // 250 samples a step
+    let mut p = SequencePlayer::new(1000.0);
+    p.set_transport(Transport { bpm: 60.0, swing: 0.0, beats_per_bar: 1, beat_unit: 4, steps_per_beat: 4, playing: true });
+    let mut pattern = Pattern::new(4);
+    let steps = pattern.steps_mut();
+    steps[0] = Step { on: true, note: 0, velocity: 1.0, gate: 0.5, slide: false, accent: false };
+    steps[1] = Step { slide: true, ..steps[0] };
+    steps[2] = Step { note: 12, ..steps[0] };
+    steps[3] = Step::new();
+    p.set_pattern(pattern, pattern.freqs(&Tuning::new()), Sound::new());
+
+    let mut m = Mixer::new(1000.0);
+    let mut at = Vec::new();
+    for _ in 0..1000 {
+        p.tick(&mut m);
+        m.tick();
+        at.push(m.voices());
+    }
+    let held = |n: usize| at[n].iter().filter(|v| !v.2).copied().collect::<Vec<_>>();
+
+    // half a step gate lets go right on time
+    assert_eq!(held(120).len(), 1);
+    assert_eq!(held(120)[0].1, 440.0);
+    assert!(held(130).is_empty());
+
+    // the slide step holds on and the next one glides up the same voice instead of starting another
+    let sliding = held(260);
+    assert_eq!(sliding.len(), 1);
+    assert_ne!(sliding[0].0, held(120)[0].0);
+    let mid = held(520);
+    assert_eq!(mid.len(), 1);
+    assert_eq!(mid[0].0, sliding[0].0);
+    assert!(mid[0].1 > 440.0 && mid[0].1 < 880.0, "{}", mid[0].1);
+    assert_eq!(held(600), vec![(sliding[0].0, 880.0, false)]);
+    // and that one does have a gate
+    assert!(held(630).is_empty());
+    assert!(held(900).is_empty());
+}
@@ -3,6 +3,14 @@ use std::f32::consts::PI;
 use crate::kmath::*;
 use crate::filter::*;
 use crate::envelope::*;
+use crate::breakpoints::*;
+use crate::transport::*;
+use crate::sequencer::*;
+use crate::arpeggiator::*;
+use crate::looper::*;
+use crate::unison::*;
+use crate::oscilloscope::*;
+use crate::convolver::*;
 
 #[derive(Clone, Copy)]
 pub struct Sound {
@@ -17,10 +25,12 @@ pub struct Sound {
     pub voices: u32,
 
     pub envelope: Envelope,
+    pub shape: BreakpointEnvelope,
+    pub pitch_env: PitchEnvelope,
     
     pub amplitude: f32,
 
-    pub filter: FilterPlanner,
+    pub layers: [Layer; MAX_LAYERS],
 }
 
 impl Sound {
@@ -30,8 +40,10 @@ impl Sound {
             voices: 2,
             detune: 20.0,
             envelope: Envelope::new(),
+            shape: BreakpointEnvelope::new(),
+            pitch_env: PitchEnvelope::new(),
             amplitude: 0.2,
-            filter: FilterPlanner::new(),
+            layers: interval_layers(&[0.0]),
         }
     }
 
@@ -41,18 +53,6 @@ impl Sound {
         f(&mut s);
         s
     }
-
-    pub fn play(&self, sample_rate: f32, id: u32) -> PlayingSound {
-        PlayingSound {
-            sample_rate,
-            sample_count: 0,
-            sample_released: None,
-            sound: self.clone(),
-            counters: vec![0; self.voices as usize],
-            filter: self.filter.lowpass(),
-            id,
-        }
-    }
 }
 
 // specify adsr in terms of samples
@@ -73,6 +73,17 @@ pub fn detune_interval(cents: f32) -> f32 {
 
 // maybe even detune should be as odd but ignore one of the - voices
 
+// semitones from A440, same as the keyboard
+pub fn note_freq(note: i32) -> f32 {
+    440.0 * 2.0f32.powf(note as f32 / 12.0)
+}
+
+pub fn note_name(note: i32) -> String {
+    let names = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+    let midi = 69 + note;
+    format!("{}{}", names[midi.rem_euclid(12) as usize], midi.div_euclid(12) - 1)
+}
+
 pub fn detune_voice_n(freq: f32, cents: f32, n: i32, k: i32) -> f32 {
     let detune_interval = detune_interval(cents);
     // let detune_gap = (detune_interval * 2.0) / (k - 1) as f32;
@@ -98,55 +109,163 @@ fn test_detune() {
     assert_eq!(detune_voice_n(1000.0, 1200.0, 3, 4), 2000.0);
 }
 
+// most detuned copies in a stack, the voices slider stops here
+pub const MAX_UNISON: usize = 9;
+// voices the mixer has, all made up front. past this the quietest gets taken over
+pub const MAX_CHANNELS: usize = 32;
+
+// one voice out of the mixers pool. nothing in here is reallocated once its made, starting a
+// note just resets it
 #[derive(Clone)]
 pub struct PlayingSound {
+    active: bool,
     id: u32,
     sample_rate: f32,
     sample_count: u32,
-    sample_released: Option<u32>,
+    env: EnvelopeState,
+    shape: BreakpointState,
     sound: Sound,
-    filter: Filter,
-    counters: Vec<u32>,
+    filter: FilterState,
+    svf: StateVariable,     // only for the shape sweeping a filter
+    phases: Vec<f32>,      // 0..1 saw per voice, layer major, MAX_UNISON a layer
+    voice_out: Vec<f32>,    // what each detuned voice put in last tick, before the filter, for the scope
+
+    // portamento for sequencer slides, multiply freq by ratio each sample til we get there
+    glide_to: f32,
+    glide_ratio: f32,
+    glide_left: u32,
+
+    // pedals. let go of with a pedal down just keeps ringing til the pedal comes up
+    key_down: bool,
+    caught: bool,   // was held when sostenuto went down so it stays up til sostenuto does
 }
 
 impl PlayingSound {
-    pub fn tick(&mut self) -> f32 {
+    pub fn new(sample_rate: f32) -> PlayingSound {
+        PlayingSound {
+            active: false,
+            id: 0,
+            sample_rate,
+            sample_count: 0,
+            env: EnvelopeState::new(),
+            shape: BreakpointState::new(),
+            svf: StateVariable::new(),
+            sound: Sound::new(),
+            phases: vec![0.0; MAX_UNISON * MAX_LAYERS],
+            voice_out: vec![0.0; MAX_UNISON],
+            filter: FilterState::new(),
+            glide_to: 0.0,
+            glide_ratio: 1.0,
+            glide_left: 0,
+            key_down: false,
+            caught: false,
+        }
+    }
+
+    // a new note from silence
+    fn start(&mut self, sound: Sound, id: u32) {
+        self.env = EnvelopeState::new();
+        self.phases.iter_mut().for_each(|p| *p = 0.0);
+        self.filter.reset();
+        self.caught = false;
+        self.key_down = true;
+        self.active = true;
+        self.restart(sound, id);
+    }
+
+    // picks the envelope up from where it is and keeps the oscillators going, no click
+    fn restart(&mut self, sound: Sound, id: u32) {
+        if sound.voices != self.sound.voices {
+            self.phases.iter_mut().for_each(|p| *p = 0.0);
+        }
+        self.env.retrigger();
+        self.shape = BreakpointState::new();
+        self.svf = StateVariable::new();
+        self.sample_count = 0;
+        self.glide_to = sound.freq;
+        self.glide_ratio = 1.0;
+        self.glide_left = 0;
+        self.sound = sound;
+        self.id = id;
+    }
+
+    // bend is the ratio from the wheel, same for everything playing
+    pub fn tick(&mut self, bend: f32, filter: &Filter) -> f32 {
         self.sample_count += 1; // warn overflow
 
-        let env_amp = self.sound.envelope.amplitude(self.sample_count, self.sample_rate as u32, self.sample_released);
+        let mut env_amp = self.env.tick(&self.sound.envelope, self.sample_rate);
+
+        // the breakpoint shape goes wherever its pointed, nowhere is nothing to work out
+        let shape = if self.sound.shape.target == ShapeTarget::Off { 0.0 } else { self.shape.tick(&self.sound.shape, self.sample_rate) };
+        let amount = self.sound.shape.amount;
+        let note_time = self.sample_count as f32 / self.sample_rate;
+        let mut pitch = bend * detune_interval(100.0 * self.sound.pitch_env.offset(note_time));
+        let mut detune = self.sound.detune;
+        let mut cutoff = None;
+        match self.sound.shape.target {
+            ShapeTarget::Off => {},
+            ShapeTarget::Amplitude => env_amp *= lerp(1.0, shape, amount.abs()),
+            ShapeTarget::Pitch => pitch *= detune_interval(2400.0 * amount * shape),
+            ShapeTarget::Detune => detune += 100.0 * amount * shape,
+            ShapeTarget::Filter => {
+                // negative amount opens as the shape falls instead
+                let shape = if amount < 0.0 { 1.0 - shape } else { shape };
+                // all the way open is as high as the svf goes, where it leaves everything alone
+                cutoff = Some(0.45 * self.sample_rate * 2.0f32.powf(-8.0 * amount.abs() * (1.0 - shape)));
+            },
+        }
+
+        if self.glide_left > 0 {
+            self.glide_left -= 1;
+            self.sound.freq = if self.glide_left == 0 { self.glide_to } else { self.sound.freq * self.glide_ratio };
+        }
 
-        // yea aint sound that good, env the filter maybe
-        // let t = (self.sample_count as f32 / (self.sample_rate * 0.5)).min(1.0);
-        // let pitch_bend_envelope = lerp(1.5, 1.0, t);
-        
+        // each layer is the whole detuned stack again at some interval
         let mut acc = 0.0;
-        for i in 0..self.sound.voices {
-            let k = self.sound.voices;
-            let f = if k == 1 {
-                self.sound.freq
-            } else {
-                detune_voice_n(self.sound.freq, self.sound.detune, i as i32, k as i32)
-                // let detune_interval = 2.0f32.powf(self.sound.detune / 1200.0);
-                // self.sound.freq * detune_interval.powf((k as f32/2.0 - i as f32)/k as f32)
-                // self.sound.freq - detune_freq + 2.0 * i as f32 * detune_freq / (k - 1) as f32
-            };
-            let this_period = (44100.0 / f) as u32;
-
-            // lol can end up with period of zero, woops
-            if this_period != 0 {
-                self.counters[i as usize] = (self.counters[i as usize] + 1) % this_period;  // mind this division by 0 hey
+        let mut total_level = 0.0;
+        let k = self.sound.voices.max(1).min(MAX_UNISON as u32);
+        self.voice_out.iter_mut().for_each(|v| *v = 0.0);
+        for (l, layer) in self.sound.layers.iter().enumerate() {
+            if layer.level == 0.0 {
+                continue;
             }
-
-            acc += self.counters[i as usize] as f32 / this_period as f32;
+            let layer_freq = self.sound.freq * pitch * detune_interval(layer.cents);
+            let mut layer_acc = 0.0;
+            for i in 0..k {
+                let f = if k == 1 {
+                    layer_freq
+                } else {
+                    detune_voice_n(layer_freq, detune, i as i32, k as i32)
+                    // let detune_interval = 2.0f32.powf(self.sound.detune / 1200.0);
+                    // self.sound.freq * detune_interval.powf((k as f32/2.0 - i as f32)/k as f32)
+                    // self.sound.freq - detune_freq + 2.0 * i as f32 * detune_freq / (k - 1) as f32
+                };
+                // phase not a whole number period, so bends and pitch envelopes slide instead of stepping
+                let c = l * MAX_UNISON + i as usize;
+                self.phases[c] = (self.phases[c] + f / self.sample_rate).fract();
+
+                layer_acc += self.phases[c];
+                self.voice_out[i as usize] += layer.level * (self.phases[c] - 0.5) * 2.0 / k as f32;
+            }
+            layer_acc /= k as f32;
+            layer_acc -= 0.5;
+            layer_acc *= 2.0;
+            acc += layer.level * layer_acc;
+            total_level += layer.level;
         }
-        acc /= self.sound.voices as f32;
-        acc -= 0.5;
-        acc *= 2.0;
+        // stacking shouldnt make it louder than one layer flat out
+        acc /= total_level.max(1.0);
+        let voice_scale = self.sound.amplitude * env_amp / total_level.max(1.0);
+        self.voice_out.iter_mut().for_each(|v| *v *= voice_scale);
 
 
 
         let samp = self.sound.amplitude * env_amp * acc;
-        let samp = self.filter.tick(samp);
+        let samp = filter.tick(&mut self.filter, samp);
+        let samp = match cutoff {
+            Some(fc) => self.svf.tick(samp, fc, 0.5, self.sample_rate),
+            None => samp,
+        };
         // if self.sample_count % 2 == 0 {
         //     return 0.0;
         // } else {
@@ -158,73 +277,228 @@ impl PlayingSound {
         
     }
 
-    pub fn finished(&self) -> bool {
-        if let Some(released) = self.sample_released {
-            if (self.sample_count - released) as f32 > self.sound.envelope.r * self.sample_rate {
-                return true;
-            }
+    pub fn glide(&mut self, freq: f32, samples: u32) {
+        if samples == 0 {
+            self.sound.freq = freq;
+            self.glide_left = 0;
+            return;
         }
-        false
+        self.glide_to = freq;
+        self.glide_ratio = (freq / self.sound.freq).powf(1.0 / samples as f32);
+        self.glide_left = samples;
+    }
+
+    pub fn finished(&self) -> bool {
+        // a full depth amp shape thats run out at zero is silent for good too
+        let shape = self.sound.shape;
+        let shape_done = shape.target == ShapeTarget::Amplitude && shape.amount.abs() >= 1.0
+            && self.shape.finished(&shape) && self.shape.value == 0.0;
+        !self.active || self.env.finished(&self.sound.envelope, self.sample_rate) || shape_done
     }
 }
 
 pub struct Mixer {
-    sample_rate: f32,
     channels: Vec<PlayingSound>,
+    filter: Box<Filter>,    // designed on the gui side, every voice runs it with its own state
+    bend: f32,      // pitch wheel as a ratio
+    sustain: bool,
+    sostenuto: bool,
 }
 
 impl Mixer {
+    // allocates the whole voice pool, so make it before it goes to the audio thread
     pub fn new(sample_rate: f32) -> Mixer {
         Mixer {
-            sample_rate,
-            channels: Vec::new(),
+            channels: (0..MAX_CHANNELS).map(|_| PlayingSound::new(sample_rate)).collect(),
+            filter: Box::new(Filter::new()),
+            bend: 1.0,
+            sustain: false,
+            sostenuto: false,
         }
     }
 
+    // hands back the old one so whoever sent this can free it off the audio thread
+    pub fn set_filter(&mut self, filter: Box<Filter>) -> Box<Filter> {
+        debug_assert!(self.channels[0].filter.fits(&filter));
+        for ch in self.channels.iter_mut() {
+            ch.filter.reset();
+        }
+        std::mem::replace(&mut self.filter, filter)
+    }
+
     pub fn add_sound(&mut self, sound: Sound, id: u32) {
-        // try to put it in one with same id. but this restarts. this fixed weird releasy things
-        // do with no restart for when synth params change obviously
-        // this is probably why weird popping actually
-        // fix make unique id per press
+        // same id is the same press with new settings, keep it going
+        if let Some(ch) = self.channels.iter_mut().find(|ch| ch.active && ch.id == id) {
+            ch.restart(sound, id);
+            return;
+        }
+
+        // hitting a note again thats only still going because of a pedal restrikes it rather than
+        // stacking another voice on top, same as a piano string
+        let ringing = self.channels.iter_mut().find(|ch| {
+            !ch.finished() && !ch.key_down && !ch.env.is_released() && (ch.sound.freq - sound.freq).abs() < 0.01
+        });
+        if let Some(ch) = ringing {
+            ch.restart(sound, id);
+            ch.key_down = true;
+            return;
+        }
+
+        // a free one, or take over whichever is quietest
+        let i = match self.channels.iter().position(|ch| ch.finished()) {
+            Some(i) => i,
+            None => (0..self.channels.len())
+                .min_by(|a, b| self.channels[*a].env.level.total_cmp(&self.channels[*b].env.level))
+                .unwrap_or(0),
+        };
+        self.channels[i].start(sound, id);
+    }
+
+    pub fn stop_sound(&mut self, id: u32) {
         for i in 0..self.channels.len() {
-            if self.channels[i].id == id {
-                self.channels[i] = sound.play(self.sample_rate, id);
-                return;
+            if self.channels[i].active && self.channels[i].id == id {
+                self.channels[i].key_down = false;
+                if !self.sustain && !self.channels[i].caught {
+                    self.channels[i].env.release();
+                    self.channels[i].shape.release();
+                }
             }
         }
-        
+    }
 
-        for i in 0..self.channels.len() {
-            if self.channels[i].finished() {
-                self.channels[i] = sound.play(self.sample_rate, id);
-                return;
+    // anything let go of while a pedal was holding it, now nothing is
+    fn release_unheld(&mut self) {
+        for ch in self.channels.iter_mut() {
+            if !ch.key_down && !ch.caught && !self.sustain {
+                ch.env.release();
+                ch.shape.release();
             }
         }
-        self.channels.push(sound.play(self.sample_rate, id));   // maybe dont need to replace finished ones
     }
 
-    pub fn stop_sound(&mut self, id: u32) {
+    pub fn set_sustain(&mut self, down: bool) {
+        self.sustain = down;
+        self.release_unheld();
+    }
+
+    // only holds whats held right as it goes down, notes played after release like normal
+    pub fn set_sostenuto(&mut self, down: bool) {
+        if down && !self.sostenuto {
+            for ch in self.channels.iter_mut() {
+                ch.caught = ch.key_down && !ch.env.is_released();
+            }
+        } else if !down {
+            for ch in self.channels.iter_mut() {
+                ch.caught = false;
+            }
+        }
+        self.sostenuto = down;
+        self.release_unheld();
+    }
+
+    // change pitch of a held sound without retriggering it
+    pub fn glide_sound(&mut self, id: u32, freq: f32, samples: u32) {
         for i in 0..self.channels.len() {
-            if self.channels[i].id == id {
-                self.channels[i].sample_released = Some(self.channels[i].sample_count);
+            if self.channels[i].active && self.channels[i].id == id {
+                self.channels[i].glide(freq, samples);
             }
         }
     }
 
+    pub fn set_bend(&mut self, semitones: f32) {
+        self.bend = detune_interval(100.0 * semitones);
+    }
+
     pub fn tick(&mut self) -> f32 {
         let mut acc = 0.0;
         for i in 0..self.channels.len() {
                 if self.channels[i].finished() {
                     // remove gracefully
                 } else {
-                    acc += self.channels[i].tick()
+                    acc += self.channels[i].tick(self.bend, &self.filter)
                 }
             }
         acc
     }
+
+    // sum of each detuned voice across everything playing, from the last tick
+    // id, freq and whether its been let go, for whats going
+    #[cfg(test)]
+    pub fn voices(&self) -> Vec<(u32, f32, bool)> {
+        self.channels.iter().filter(|ch| !ch.finished()).map(|ch| (ch.id, ch.sound.freq, ch.env.is_released())).collect()
+    }
+
+    pub fn voice_outputs(&self, out: &mut [f32]) {
+        out.iter_mut().for_each(|v| *v = 0.0);
+        for ch in self.channels.iter().filter(|ch| !ch.finished()) {
+            for (o, v) in out.iter_mut().zip(ch.voice_out.iter()) {
+                *o += v;
+            }
+        }
+    }
+}
+
+// one sample of what the device actually played, audio thread -> gui for the viewers
+#[derive(Clone, Copy)]
+pub struct AnalysisFrame {
+    pub out: f32,
+    pub voices: [f32; SCOPE_VOICES],
+    pub loops: [LoopState; LOOP_TRACKS],
 }
 
 pub enum SoundMessage {
     PlaySound(Sound, u32),   // u32 is id. also if its already playing just update the sound
     StopSound(u32),
-}
\ No newline at end of file
+    SetTransport(Transport),
+    SetPattern(Pattern, [Option<f32>; MAX_STEPS], Sound),
+    SetArp(ArpSettings),
+    ArpPeriod(f32),     // octave ratio of the tuning
+    ArpNoteOn(Sound, u32),  // held note for the arp to play with, instead of PlaySound
+    ArpNoteOff(u32),
+    Looper(usize, LoopCommand),     // track, command
+    LoopQuantize(bool),
+    SetReverb(Box<Convolver>),  // built gui side, fft planning doesnt belong on the audio thread
+    ReverbMix(f32),
+    PitchBend(f32),     // semitones, on everything playing
+    Pedals(bool, bool), // sustain, sostenuto. down is true
+    SetFilter(Box<Filter>),
+}
+
+// whatever the audio thread is done with goes back so the freeing happens on the gui side
+pub enum Garbage {
+    Filter(Box<Filter>),
+    Convolver(Box<Convolver>),
+}
+#[test]
+fn test_pedals() {
+    let mut m = Mixer::new(1000.0);
+    let released = |m: &Mixer, id: u32| m.channels.iter().find(|ch| ch.active && ch.id == id).unwrap().env.is_released();
+    let a = Sound::new();
+    let b = a.but(|s| s.freq = 550.0);
+
+    // sustain holds whatever gets let go of til it comes up
+    m.add_sound(a, 1);
+    m.set_sustain(true);
+    m.stop_sound(1);
+    assert!(!released(&m, 1));
+    // hitting it again takes the same voice over instead of adding one
+    m.add_sound(a, 2);
+    assert_eq!(m.channels.iter().filter(|ch| !ch.finished()).count(), 1);
+    m.add_sound(b, 3);
+    m.set_sustain(false);
+    assert!(!released(&m, 2) && !released(&m, 3));
+    m.stop_sound(2);
+    m.stop_sound(3);
+    assert!(released(&m, 2) && released(&m, 3));
+
+    // sostenuto only keeps what was already down, 5 comes after so it goes as normal
+    let mut m = Mixer::new(1000.0);
+    m.add_sound(a, 4);
+    m.set_sostenuto(true);
+    m.add_sound(b, 5);
+    m.stop_sound(4);
+    m.stop_sound(5);
+    assert!(!released(&m, 4) && released(&m, 5));
+    m.set_sostenuto(false);
+    assert!(released(&m, 4));
+}
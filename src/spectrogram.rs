@@ -0,0 +1,131 @@
+use crate::krenderer::*;
+use crate::kmath::*;
+use crate::fftviewer::*;
+use crate::widgets::*;
+use crate::units::*;
+
+// colours for 0..1, dark blue through purple and orange to near white
+pub fn colormap(t: f32) -> [u8; 4] {
+    let stops = [
+        Vec3::new(0.0, 0.0, 0.05),
+        Vec3::new(0.15, 0.05, 0.4),
+        Vec3::new(0.6, 0.1, 0.5),
+        Vec3::new(0.95, 0.45, 0.1),
+        Vec3::new(1.0, 0.95, 0.7),
+    ];
+    let t = t.max(0.0).min(1.0) * (stops.len() - 1) as f32;
+    let i = (t as usize).min(stops.len() - 2);
+    let c = stops[i].lerp(stops[i + 1], t - i as f32);
+    [(c.x * 255.0) as u8, (c.y * 255.0) as u8, (c.z * 255.0) as u8, 255]
+}
+
+// time goes left to right, log frequency up the side
+// columns get written into a texture that wraps, the newest one lands at head and the
+// draw just offsets the uvs so it scrolls without ever uploading the whole image
+pub struct Spectrogram {
+    pub width: usize,
+    pub height: usize,
+    head: usize,
+    pending: Vec<(usize, Vec<u8>)>,     // columns the renderer still has to upload
+
+    pub floor_db: f32,
+    pub ceil_db: f32,
+    pub min_freq: f32,
+    sample_rate: f32,
+
+    rect: Option<Rect>,
+}
+
+impl Spectrogram {
+    pub fn new(width: usize, height: usize, sample_rate: f32) -> Spectrogram {
+        Spectrogram {
+            width,
+            height,
+            head: 0,
+            pending: Vec::new(),
+            floor_db: -100.0,
+            ceil_db: 0.0,
+            min_freq: 20.0,
+            sample_rate,
+            rect: None,
+        }
+    }
+
+    // row 0 is the top so highest frequency
+    fn row_freqs(&self, row: usize) -> (f32, f32) {
+        let max = self.sample_rate / 2.0;
+        let f = |r: f32| self.min_freq * (max / self.min_freq).powf(1.0 - r / self.height as f32);
+        (f(row as f32 + 1.0), f(row as f32))
+    }
+
+    // power per bin from dc up to nyquist, like FftViewer hands out
+    pub fn push_column(&mut self, power: &[f32]) {
+        let df = self.sample_rate / (2 * power.len()) as f32;
+        let mut bytes = Vec::with_capacity(self.height * 4);
+        for row in 0..self.height {
+            let (f0, f1) = self.row_freqs(row);
+            let b0 = ((f0 / df).round() as usize).min(power.len() - 1);
+            let b1 = ((f1 / df).round() as usize).max(b0 + 1).min(power.len());
+            let db = to_db(power[b0..b1].iter().cloned().fold(0.0, f32::max));
+            bytes.extend_from_slice(&colormap(unlerp(db, self.floor_db, self.ceil_db)));
+        }
+        // renderer fell behind by a whole screen, older ones would be overwritten anyway
+        if self.pending.len() >= self.width {
+            self.pending.remove(0);
+        }
+        self.pending.push((self.head, bytes));
+        self.head = (self.head + 1) % self.width;
+    }
+
+    pub fn take_pending(&mut self) -> Vec<(usize, Vec<u8>)> {
+        std::mem::take(&mut self.pending)
+    }
+
+    pub fn frame(&mut self, ui: &mut Ui, r: Rect) {
+        ui.push_id("spectrogram");
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.3, 0.3, 0.4, 1.0));
+        ui.kc.rect(r);
+
+        let (sliders, plot) = r.split_lr(0.15);
+        let sliders = sliders.split_ud(0.5);
+        ui.slider("floor", sliders.0, -140.0, -30.0, &mut self.floor_db, SliderMapping::Linear, Unit::Db);
+        ui.slider("ceil", sliders.1, -20.0, 20.0, &mut self.ceil_db, SliderMapping::Linear, Unit::Db);
+        self.rect = Some(plot.dilate_pc(-0.02));
+        ui.pop_id();
+    }
+
+    // goes on a canvas that gets sent with the spectrogram texture instead of the atlas
+    pub fn draw(&self, kc: &mut KRCanvas) {
+        if let Some(r) = self.rect {
+            // head is the oldest column so it goes on the left
+            let start = self.head as f32 / self.width as f32;
+            kc.set_depth(1.3);
+            kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+            kc.rect_uv(r, Rect::new(start, 0.0, 1.0, 1.0));
+        }
+    }
+}
+
+#[test]
+fn test_spectrogram_column() {
+    let mut s = Spectrogram::new(4, 64, 44100.0);
+    let bins = 2048;
+    let df = 44100.0 / (2 * bins) as f32;
+    let mut power = vec![0.0; bins];
+    power[(1000.0 / df) as usize] = 1.0;
+    for _ in 0..6 {
+        s.push_column(&power);
+    }
+    let pending = s.take_pending();
+    assert_eq!(pending.len(), 4);
+    assert_eq!(pending[0].0, 2);
+    assert_eq!(s.head, 2);
+
+    // the lit row should be the one holding 1khz
+    let col = &pending[3].1;
+    let brightest = (0..64).max_by_key(|r| col[r * 4] as u32 + col[r * 4 + 1] as u32).unwrap();
+    let (f0, f1) = s.row_freqs(brightest);
+    assert!(f0 <= 1000.0 + df && 1000.0 - df <= f1, "row {} is {}..{}", brightest, f0, f1);
+    assert_eq!(colormap(0.0), [0, 0, 12, 255]);
+}
@@ -4,176 +4,379 @@ use crate::kmath::*;
 use crate::sound::*;
 use crate::krenderer::*;
 use crate::keyboard::*;
+use crate::bendwheel::*;
 use crate::envelope::*;
+use crate::breakpoints::*;
 use crate::filter::*;
-
-use ringbuf::Producer;
+use crate::sequencer::*;
+use crate::arpeggiator::*;
+use crate::looper::*;
+use crate::recorder::*;
+use crate::pianoroll::*;
+use crate::tuning::*;
+use crate::unison::*;
+use crate::spectrogram::*;
+use crate::oscilloscope::*;
+use crate::widgets::*;
+use crate::units::*;
+use crate::midi::*;
+use crate::config::*;
+use crate::history::*;
+use crate::layout::*;
+
+use glutin::event::VirtualKeyCode;
+use ringbuf::{Consumer, Producer};
 
 // there is big dc i probably need to go negatory as well
 pub struct Synth {
     pub sound: Sound,
     pub keyboard: Keyboard,
-    pub envelope: Envelope,
+    pub bend: BendWheel,
+    pub envelope: EnvelopePanel,
+    pub shape: ShapePanel,
     pub filter: FilterPlanner,
+    pub filter_cache: FilterCache,
+    filter_sent: Option<FilterPlanner>,     // what the audio thread has, designs only go over finished
+    tuning_sent: Option<Tuning>,            // the audio thread only gets freqs and the period from it
     pub fft_viewer: FftViewer,
-
-    pub local_mixer: Mixer,
+    pub sample_rate: f32,
+    pub spectrogram: Spectrogram,
+    pub scope: Oscilloscope,
+    pub sequencer: Sequencer,
+    pub arp: ArpPanel,
+    pub looper: LooperPanel,
+    pub recorder: InputRecorder,
+    pub piano_roll: PianoRoll,
+    pub tuning: TuningPanel,
+    pub unison: UnisonPanel,
 
     pub detune: f32,
+    pub reverb_mix: f32,
     pub voices: f32,
 
     pub any_change: bool,
+    pub ui: UiState,
+    pub midi: Option<MidiInput>,
+    pub config: Config,
+    pub layout: Layout,
+    pub history: History<Patch>,
+    pub ab: [Patch; 2],     // compare slots, whichever isnt current holds the other version
+    pub ab_current: usize,
+    ab_flip: Option<usize>,
+    pedals: (bool, bool),   // last sent to the mixer
 }
 
 impl Synth {
-    pub fn new() -> Synth {
-        Synth {
-            sound: Sound::new(),
-            any_change: false,
-            keyboard: Keyboard::new(),
+    // at whatever rate the device runs, the viewers and exports all go by it
+    pub fn new(sample_rate: f32) -> Synth {
+        let fft_viewer = FftViewer::new(4096, sample_rate);
+        let sound = Sound::new();
+        let patch = Patch {
             envelope: Envelope::new(),
-            filter: FilterPlanner::new(),
-            fft_viewer: FftViewer::new(512),
-            local_mixer: Mixer::new(44100.0),
+            shape: BreakpointEnvelope::new(),
+            pitch_env: sound.pitch_env,
+            filter: FilterPlanner::new(sample_rate),
+            layers: UnisonPanel::new().layers,
+            amplitude: sound.amplitude,
             voices: 3.0,
             detune: 5.0,
+            reverb_mix: 0.0,
+        };
+        Synth {
+            spectrogram: Spectrogram::new(512, 256, sample_rate),
+            scope: Oscilloscope::new(8192, sample_rate),
+            fft_viewer,
+            sample_rate,
+            sound,
+            any_change: false,
+            ui: UiState::new(),
+            midi: None,
+            config: Config::new(),
+            layout: Layout::new(),
+            history: History::new(patch),
+            ab: [patch, patch],
+            ab_current: 0,
+            ab_flip: None,
+            pedals: (false, false),
+            keyboard: Keyboard::new(),
+            bend: BendWheel::new(),
+            envelope: EnvelopePanel::new(patch.envelope),
+            shape: ShapePanel::new(patch.shape),
+            filter: patch.filter,
+            filter_cache: FilterCache::new(),
+            filter_sent: None,
+            tuning_sent: None,
+            sequencer: Sequencer::new(sample_rate),
+            arp: ArpPanel::new(),
+            looper: LooperPanel::new(),
+            recorder: InputRecorder::new(600.0),
+            piano_roll: PianoRoll::new(),
+            tuning: TuningPanel::new(),
+            unison: UnisonPanel { layers: patch.layers },
+            voices: patch.voices,
+            detune: patch.detune,
+            reverb_mix: patch.reverb_mix,
         }
     }
 
-    // ok so we need a local mixer
-    // maybe using time to keep up to speed? hoopefully it stays in sync
+    pub fn patch(&self) -> Patch {
+        Patch {
+            envelope: self.envelope.env,
+            shape: self.shape.shape,
+            pitch_env: self.sound.pitch_env,
+            filter: self.filter,
+            layers: self.unison.layers,
+            amplitude: self.sound.amplitude,
+            voices: self.voices,
+            detune: self.detune,
+            reverb_mix: self.reverb_mix,
+        }
+    }
+
+    // panels and the sound both, the audio thread still needs telling
+    pub fn set_patch(&mut self, patch: Patch) {
+        self.envelope.env = patch.envelope;
+        self.shape.shape = patch.shape;
+        self.sound.pitch_env = patch.pitch_env;
+        self.filter = patch.filter;
+        self.unison.layers = patch.layers;
+        self.sound.layers = patch.layers;
+        self.sound.amplitude = patch.amplitude;
+        self.voices = patch.voices;
+        self.detune = patch.detune;
+        self.reverb_mix = patch.reverb_mix;
+        self.sound.envelope = patch.envelope;
+        self.sound.shape = patch.shape;
+        self.sound.voices = patch.voices as u32;
+        self.sound.detune = patch.detune;
+    }
+
     // maybe I can downsample before going into fft?
 
-    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, sound_channel: &mut Producer<SoundMessage>) {
+    pub fn frame(&mut self, inputs: &FrameInputState, kc: &mut KRCanvas, sound_channel: &mut Producer<SoundMessage>, analysis: &mut Consumer<AnalysisFrame>) {
 
-        // ffwd local mixer
-        let ticks = 44100.0f64 * inputs.dt;
-        for i in 0..ticks as i32 {
-            self.fft_viewer.tick(self.local_mixer.tick());
+        // whatever the device played since last frame
+        while let Some(f) = analysis.pop() {
+            self.fft_viewer.tick(f.out);
+            self.scope.tick(f.out, &f.voices);
+            self.looper.states = f.loops;
+        }
+        for column in self.fft_viewer.take_columns() {
+            self.spectrogram.push_column(&column);
         }
 
         kc.set_camera(inputs.screen_rect);
         kc.set_depth(1.0);
         kc.set_colour(Vec4::new(0.8, 0.4, 0.2, 1.0));
         kc.rect(inputs.screen_rect);
+        // typing a value in, the letters shouldnt also play notes
+        let quiet;
+        let inputs = if self.ui.typing() {
+            quiet = inputs.without_keys();
+            &quiet
+        } else {
+            inputs
+        };
+        // the layout goes first so dragging a split doesnt also drag whatever is under it
+        let (panels, layout_busy) = self.layout.frame(inputs, kc);
+        let still;
+        let inputs = if layout_busy {
+            still = inputs.without_mouse();
+            &still
+        } else {
+            inputs
+        };
+        let midi = match &mut self.midi {
+            Some(m) => m.poll(),
+            None => Vec::new(),
+        };
+        self.ui.midi(&midi);
+        self.keyboard.midi(&midi);
+        self.bend.midi(&midi);
+        // a/b flips from last frames click, then ctrl z and ctrl y. whatever comes back goes out with the rest of the patch
+        let mut restored = None;
+        if let Some(i) = self.ab_flip.take() {
+            self.ab[self.ab_current] = self.patch();
+            self.ab_current = i;
+            restored = Some(self.ab[i]);
+        } else if inputs.ctrl() && inputs.key_rising(VirtualKeyCode::Z) {
+            restored = self.history.undo(&self.patch());
+        } else if inputs.ctrl() && inputs.key_rising(VirtualKeyCode::Y) {
+            restored = self.history.redo(&self.patch());
+        }
+        if let Some(patch) = restored {
+            self.set_patch(patch);
+        }
+        let mut ui = Ui::new(inputs, kc, &mut self.ui);
 
-        let (top, bottom) = inputs.screen_rect.split_ud(0.65);
-
-        let tops = top.split_lrn(4);
+        let area = |p: PanelId| panels[p as usize];
 
-        self.envelope.frame(inputs, kc, tops[0]);
+        let mut patch_changed = restored.is_some();
+        patch_changed |= self.envelope.frame(&mut ui, area(PanelId::Envelope));
+        patch_changed |= self.shape.frame(&mut ui, area(PanelId::Shape));
         
-        let mids = tops[1].split_lrn(3);
+        let (mids, pitch_row) = area(PanelId::Mids).split_ud(0.5);
+        let mids = mids.split_lrn(4);
+        let (pitch_pad, pitch_row) = pitch_row.split_lr(0.5);
+        let pitch_row = pitch_row.split_lrn(2);
+
+        patch_changed |= ui.slider("voices", mids[0], 1.0, 9.0, &mut self.voices, SliderMapping::Stepped(1.0), Unit::None) |
+        ui.slider("detune", mids[1], 0.0, 316.0, &mut self.detune, SliderMapping::Linear, Unit::Cents) |
+        ui.slider("volume", mids[2], 0.0, 1.0, &mut self.sound.amplitude, SliderMapping::Linear, Unit::Gain);
+        // does nothing til theres an ir loaded
+        if ui.slider("reverb", mids[3], 0.0, 1.0, &mut self.reverb_mix, SliderMapping::Linear, Unit::Percent) || restored.is_some() {
+            sound_channel.push(SoundMessage::ReverbMix(self.reverb_mix));
+        }
 
-        label_slider("voices", mids[0], 1.0, 9.0, &mut self.voices, false, inputs, kc) |
-        label_slider("detune", mids[1], 0.0, 316.0, &mut self.detune, false, inputs, kc) |
-        label_slider("volume", mids[2], 0.0, 1.0, &mut self.sound.amplitude, false, inputs, kc);
+        // pitch envelope, time along and semitones up, then its curve. range is for the bend wheel
+        let env = &mut self.sound.pitch_env;
+        patch_changed |= ui.xy_pad("pitch env", pitch_pad.dilate_pc(-0.05), (0.001, 1.0), (-24.0, 24.0), &mut env.time, &mut env.semitones) |
+        ui.knob("pcurve", pitch_row[0], -8.0, 8.0, &mut env.curve, SliderMapping::Linear, Unit::None);
+        let (bend_range, ab_area) = pitch_row[1].split_ud(0.5);
+        if ui.number("bend", bend_range, 1.0, 24.0, 1.0, &mut self.bend.range, Unit::Semitones) {
+            sound_channel.push(SoundMessage::PitchBend(self.bend.semitones()));
+        }
+        // a and b are two goes at the same patch to flip between, flipping is undoable like anything else
+        let ab_area = ab_area.split_lrn(2);
+        for (i, label) in ["A", "B"].iter().enumerate() {
+            let mut on = self.ab_current == i;
+            if ui.toggle(label, ab_area[i], &mut on) && self.ab_current != i {
+                self.ab_flip = Some(i);
+            }
+        }
 
-        if self.filter.frame(inputs, kc, tops[2]) {
-            self.sound.filter = self.filter;
+        if self.filter.frame(&mut ui, area(PanelId::Filter), &mut self.filter_cache) {
+            patch_changed = true;
         };
+        // undo and a/b change it too, so go by what was last sent. designed here, never over there
+        if self.filter_sent != Some(self.filter) {
+            let design = Box::new(self.filter_cache.get(self.filter).clone());
+            if sound_channel.push(SoundMessage::SetFilter(design)).is_ok() {
+                self.filter_sent = Some(self.filter);
+            }
+        }
+
+        if self.tuning.frame(&mut ui, area(PanelId::Tuning)) {
+            self.keyboard.tuning = self.tuning.tuning.clone();
+        }
+
+        if self.unison.frame(&mut ui, area(PanelId::Unison)) {
+            self.sound.layers = self.unison.layers;
+            patch_changed = true;
+        }
 
         self.sound.voices = self.voices as u32;
         self.sound.detune = self.detune;
         
-        self.sound.envelope = self.envelope;
+        self.sound.envelope = self.envelope.env;
+        self.sound.shape = self.shape.shape;
 
-        let keyboard_area = bottom;
-        let keyboard_events = self.keyboard.frame(inputs, kc, keyboard_area);
+
+        let quantize = self.looper.quantize;
+        for (track, cmd) in self.looper.frame(inputs, ui.kc, area(PanelId::Looper)) {
+            sound_channel.push(SoundMessage::Looper(track, cmd));
+        }
+        if self.looper.quantize != quantize {
+            sound_channel.push(SoundMessage::LoopQuantize(self.looper.quantize));
+        }
+
+        if self.arp.frame(&mut ui, area(PanelId::Arp)) {
+            sound_channel.push(SoundMessage::SetArp(self.arp.settings));
+        }
+
+        // the sequencer plays on the audio thread so it gets a copy of the patch whenever either changes
+        let sequencer_changed = self.sequencer.frame(&mut ui, area(PanelId::Sequencer));
+        if sequencer_changed {
+            sound_channel.push(SoundMessage::SetTransport(self.sequencer.transport));
+        }
+        let tuning_changed = self.tuning_sent.as_ref() != Some(&self.tuning.tuning);
+        if tuning_changed && sound_channel.push(SoundMessage::ArpPeriod(self.tuning.tuning.period_ratio())).is_ok() {
+            self.tuning_sent = Some(self.tuning.tuning.clone());
+        }
+        if sequencer_changed || patch_changed || tuning_changed {
+            let freqs = self.sequencer.pattern.freqs(&self.tuning.tuning);
+            sound_channel.push(SoundMessage::SetPattern(self.sequencer.pattern, freqs, self.sound));
+        }
+
+        if self.bend.frame(inputs, ui.kc, area(PanelId::Bend)) {
+            sound_channel.push(SoundMessage::PitchBend(self.bend.semitones()));
+        }
+        let keyboard_events = self.keyboard.frame(&mut ui, area(PanelId::Keyboard), self.sequencer.sounding().as_slice());
+        // ahead of the notes so a key let go of the same frame the pedal goes down is held
+        let pedals = (self.keyboard.sustain, self.keyboard.sostenuto);
+        if pedals != self.pedals {
+            self.pedals = pedals;
+            sound_channel.push(SoundMessage::Pedals(pedals.0, pedals.1));
+        }
 
 
         if keyboard_events.len() > 0 {
             println!("keyboard events: {:?}", keyboard_events);
         }
 
+        for ke in keyboard_events.iter() {
+            self.recorder.push(inputs.t, *ke);
+        }
+
+        self.spectrogram.frame(&mut ui, area(PanelId::Spectrogram));
+        self.scope.frame(&mut ui, area(PanelId::Scope));
+
+        let grid = self.sequencer.transport.samples_per_step(self.sample_rate) / self.sample_rate as f64;
+        for action in self.piano_roll.frame(&mut ui, area(PanelId::Roll), grid) {
+            let result = match action {
+                PianoRollAction::Capture => {
+                    let clip = self.recorder.capture(inputs.t, self.piano_roll.capture_seconds as f64);
+                    self.piano_roll.set_clip(clip);
+                    Ok(())
+                },
+                PianoRollAction::ExportMidi => match &self.piano_roll.clip {
+                    Some(clip) => clip.write_midi("capture.mid", self.sequencer.transport.bpm),
+                    None => Ok(()),
+                },
+                PianoRollAction::ExportWav => match &self.piano_roll.clip {
+                    Some(clip) => clip.write_wav("capture.wav", self.sound, self.filter_cache.get(self.filter), self.sample_rate),
+                    None => Ok(()),
+                },
+            };
+            if let Err(e) = result {
+                eprintln!("piano roll: {}", e);
+            }
+        }
+
         for ke in keyboard_events {
             if ke.pressed {
+                self.scope.fundamental = Some(ke.freq);
+            }
+            // arp always gets the offs so turning it off mid chord doesnt leave notes stuck in it
+            if ke.pressed && self.arp.settings.enabled {
+                let mut s = self.sound.clone();
+                s.freq = ke.freq;
+                s.amplitude *= ke.velocity;
+                sound_channel.push(SoundMessage::ArpNoteOn(s, ke.uid));
+            } else if ke.pressed {
                 let mut s = self.sound.clone();
                 s.freq = ke.freq;
+                s.amplitude *= ke.velocity;
                 sound_channel.push(SoundMessage::PlaySound(s, ke.uid));
-                self.local_mixer.add_sound(s, ke.uid);
             } else {
+                sound_channel.push(SoundMessage::ArpNoteOff(ke.uid));
                 sound_channel.push(SoundMessage::StopSound(ke.uid));
-                self.local_mixer.stop_sound(ke.uid);
             }
         }
         // todo update sounds when sliders adn stuff are adjusted as well
 
-        self.fft_viewer.frame(kc, tops[3])
+        self.fft_viewer.frame(&mut ui, area(PanelId::Fft));
 
-    }
-}
-
-
-
-// so remapping the exponential
-// 10, 1000, 0.5 => 100
-// 10, 1000, 0.99 => 1000
-// 10, 1000, 0.01 => 11
-
-// min + (max - min) * t // linear
-// min + 2^t(log2 max)
-// min + f((max - min), t) s.t. if t = 0, = 0 t = 1, 1
+        // mouse down could be any of the hand rolled editors mid drag
+        let busy = inputs.lmb == KeyStatus::Pressed || inputs.lmb == KeyStatus::JustPressed || self.ui.typing();
+        self.history.track(&self.patch(), inputs.t, busy);
 
-// f(min, max, t) s.t. t = 0 min, t = 1 max, t 0.5 log_min max
-
-pub fn label_slider(label: &str, r: Rect, min: f32, max: f32, val: &mut f32, log: bool, inputs: &FrameInputState, kc: &mut KRCanvas) -> bool {
-    kc.set_depth(1.5);
-    let r = r.dilate_pc(-0.02);
-    let (text, slider_rect) = r.split_ud(0.05);
-    kc.text_center(label.as_bytes(), text);
-    slider(slider_rect, min, max, val, log, inputs, kc)
-}
-
-pub fn slider(r: Rect, min: f32, max: f32, val: &mut f32, log: bool, inputs: &FrameInputState, kc: &mut KRCanvas) -> bool {
-    let r = r.fit_aspect_ratio(0.25);
-
-    kc.set_depth(2.0);
-    kc.set_colour(Vec4::new(0.2, 0.2, 0.2, 1.0));
-    kc.rect(r);
-    kc.set_depth(2.1);
-    kc.set_colour(Vec4::new(0.9, 0.9, 0.9, 1.0));
-    kc.rect(r.fit_aspect_ratio(0.01));
-    kc.set_depth(2.2);
-    kc.set_colour(Vec4::new(0.7, 0.7, 0.7, 1.0));
-
-    
-    let mut slider_t = 0.0f32;
-    let change = r.contains(inputs.mouse_pos) && inputs.lmb == KeyStatus::Pressed;
-    if change {
-        slider_t = unlerp(inputs.mouse_pos.y, r.bot(), r.top());
-        if slider_t < 0.01 {
-            slider_t = 0.0;
-        }
-        if slider_t > 1.0 - 0.01 {
-            slider_t = 1.0;
-        }
-        if log {
-            *val = min + 2.0f32.powf(slider_t * (max - min).log2()) - 1.0;
-        } else {
-            *val = lerp(min, max, slider_t);
-        }
-    } else {
-        if log {
-            // slider t is inverse of that log formula
-            slider_t = (*val + 1.0 - min).log2() / (max - min).log2();
-        } else {
-            // slider t is linear inverse thing
-            slider_t = unlerp(*val, min, max);
+        let keymap_changed = self.keyboard.keymap != self.config.keymap;
+        self.config.keymap = self.keyboard.keymap;
+        if self.ui.save_bindings(&mut self.config) || keymap_changed {
+            if let Err(e) = self.config.save(CONFIG_PATH) {
+                eprintln!("saving {}: {}", CONFIG_PATH, e);
+            }
         }
     }
-
-
-    // linear sliders are wrong when not change, but right when change
-    // i mean it looks so simple and correct, remap val, but actually it is wrong anyway / i dont understand why its to low top
-
-    let slider_pos = lerp(r.bot(), r.top(), slider_t);
-    let rect_ish = r.dilate_pc(-0.05).fit_aspect_ratio(2.0);
-    let slider_rect = Rect::new_centered(r.centroid().x, slider_pos, rect_ish.w, rect_ish.h);
-    kc.rect(slider_rect);
-    kc.set_depth(2.3);
-    kc.text_center(format!("{:.2}", *val).as_bytes(), slider_rect);
-    change
-
-    // also render text and name of contained value
-}
\ No newline at end of file
+}
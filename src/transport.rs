@@ -0,0 +1,129 @@
+// tempo clock. lives on the audio thread and counts samples so steps land on the exact sample
+// the gui keeps its own copy going off dt just for drawing the playhead
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transport {
+    pub bpm: f32,
+    pub swing: f32,             // 0 is straight, 1 pushes every second step back to 75% of the pair (hard shuffle)
+    pub beats_per_bar: u32,
+    pub beat_unit: u32,         // 4 for x/4, 8 for x/8 etc. bpm is always quarter notes
+    pub steps_per_beat: u32,
+    pub playing: bool,
+}
+
+impl Transport {
+    pub fn new() -> Transport {
+        Transport {
+            bpm: 120.0,
+            swing: 0.0,
+            beats_per_bar: 4,
+            beat_unit: 4,
+            steps_per_beat: 4,
+            playing: false,
+        }
+    }
+
+    pub fn samples_per_beat(&self, sample_rate: f32) -> f64 {
+        let quarter = 60.0 / self.bpm.max(1.0) as f64;
+        sample_rate as f64 * quarter * 4.0 / self.beat_unit.max(1) as f64
+    }
+
+    pub fn samples_per_step(&self, sample_rate: f32) -> f64 {
+        self.samples_per_beat(sample_rate) / self.steps_per_beat.max(1) as f64
+    }
+
+    pub fn steps_per_bar(&self) -> u32 {
+        self.beats_per_bar.max(1) * self.steps_per_beat.max(1)
+    }
+
+    // where step n actually lands, in steps. odd steps get pushed back by the swing
+    pub fn step_position(&self, n: u64) -> f64 {
+        if n % 2 == 1 {
+            n as f64 + 0.5 * self.swing.max(0.0).min(1.0) as f64
+        } else {
+            n as f64
+        }
+    }
+}
+
+pub struct Clock {
+    pub transport: Transport,
+    sample_rate: f32,
+    // position is base + samples since base / step len, rebased when tempo changes
+    // instead of accumulating 1/step_len every sample and drifting
+    base_pos: f64,
+    samples: u64,
+    next_step: u64,
+}
+
+impl Clock {
+    pub fn new(sample_rate: f32) -> Clock {
+        Clock {
+            transport: Transport::new(),
+            sample_rate,
+            base_pos: 0.0,
+            samples: 0,
+            next_step: 0,
+        }
+    }
+
+    // keeps position, so you can nudge tempo while its playing
+    pub fn set_transport(&mut self, t: Transport) {
+        if t.playing && !self.transport.playing {
+            self.reset();
+        }
+        self.base_pos = self.pos();
+        self.samples = 0;
+        self.transport = t;
+    }
+
+    pub fn reset(&mut self) {
+        self.base_pos = 0.0;
+        self.samples = 0;
+        self.next_step = 0;
+    }
+
+    pub fn step_len(&self) -> f64 {
+        self.transport.samples_per_step(self.sample_rate)
+    }
+
+    // position in steps, for drawing
+    pub fn pos(&self) -> f64 {
+        self.base_pos + self.samples as f64 / self.step_len()
+    }
+
+    // returns the step that starts on this sample if any
+    pub fn tick(&mut self) -> Option<u64> {
+        if !self.transport.playing {
+            return None;
+        }
+        let mut fired = None;
+        if self.pos() >= self.transport.step_position(self.next_step) {
+            fired = Some(self.next_step);
+            self.next_step += 1;
+        }
+        self.samples += 1;
+        fired
+    }
+
+    pub fn advance_seconds(&mut self, dt: f64) {
+        if !self.transport.playing {
+            return;
+        }
+        self.samples += (dt * self.sample_rate as f64) as u64;
+    }
+}
+
+#[test]
+fn test_clock() {
+    let mut c = Clock::new(1000.0);
+    c.set_transport(Transport { bpm: 60.0, swing: 0.0, beats_per_bar: 4, beat_unit: 4, steps_per_beat: 4, playing: true });
+    // 250 samples per step
+    let fired: Vec<usize> = (0..1000).filter(|_| c.tick().is_some()).collect();
+    assert_eq!(fired, vec![0, 250, 500, 750]);
+
+    let mut c = Clock::new(1000.0);
+    c.set_transport(Transport { bpm: 60.0, swing: 1.0, beats_per_bar: 4, beat_unit: 4, steps_per_beat: 4, playing: true });
+    let fired: Vec<usize> = (0..1000).filter(|_| c.tick().is_some()).collect();
+    assert_eq!(fired, vec![0, 375, 500, 875]);
+}
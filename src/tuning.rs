@@ -0,0 +1,301 @@
+use crate::kmath::*;
+use crate::widgets::*;
+use crate::units::*;
+
+// scala style: degrees in cents above the root, last one is the period (eg 1200 for an octave)
+// degree 0 is the root and isnt stored
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scale {
+    pub name: String,
+    pub cents: Vec<f32>,
+}
+
+impl Scale {
+    pub fn equal(divisions: u32) -> Scale {
+        let n = divisions.max(1);
+        Scale {
+            name: format!("{}-edo", n),
+            cents: (1..=n).map(|i| 1200.0 * i as f32 / n as f32).collect(),
+        }
+    }
+
+    // 5 limit just intonation
+    pub fn just() -> Scale {
+        let ratios: [(f32, f32); 12] = [
+            (16.0, 15.0), (9.0, 8.0), (6.0, 5.0), (5.0, 4.0), (4.0, 3.0), (45.0, 32.0),
+            (3.0, 2.0), (8.0, 5.0), (5.0, 3.0), (9.0, 5.0), (15.0, 8.0), (2.0, 1.0),
+        ];
+        Scale {
+            name: "just".to_string(),
+            cents: ratios.iter().map(|(a, b)| ratio_cents(a / b)).collect(),
+        }
+    }
+
+    pub fn len(&self) -> i32 {
+        self.cents.len() as i32
+    }
+
+    // the interval it repeats at, usually an octave but not always
+    pub fn period_cents(&self) -> f32 {
+        self.cents.last().copied().unwrap_or(1200.0)
+    }
+
+    // any degree, wraps around the period
+    pub fn degree_cents(&self, degree: i32) -> f32 {
+        let n = self.len();
+        if n == 0 {
+            return 0.0;
+        }
+        let period = self.cents[n as usize - 1];
+        let d = degree.rem_euclid(n);
+        let oct = degree.div_euclid(n);
+        let within = if d == 0 { 0.0 } else { self.cents[d as usize - 1] };
+        oct as f32 * period + within
+    }
+
+    pub fn parse_scl(text: &str) -> anyhow::Result<Scale> {
+        let mut lines = text.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.starts_with('!'));
+        let name = lines.next().ok_or(anyhow::Error::msg("scl: missing description"))?.to_string();
+        let count: usize = lines.next()
+            .ok_or(anyhow::Error::msg("scl: missing note count"))?
+            .split_whitespace().next().unwrap_or("")
+            .parse()?;
+        let mut cents = Vec::new();
+        for line in lines.filter(|l| l.len() > 0).take(count) {
+            // anything after the number is a comment
+            let tok = line.split_whitespace().next().unwrap();
+            cents.push(parse_pitch(tok)?);
+        }
+        if cents.len() != count {
+            return Err(anyhow::Error::msg(format!("scl: expected {} notes, got {}", count, cents.len())));
+        }
+        Ok(Scale { name, cents })
+    }
+}
+
+pub fn ratio_cents(r: f32) -> f32 {
+    1200.0 * r.log2()
+}
+
+// scala pitch: has a dot -> cents, otherwise ratio like 3/2 or a bare integer
+fn parse_pitch(tok: &str) -> anyhow::Result<f32> {
+    if tok.contains('.') {
+        return Ok(tok.parse()?);
+    }
+    let r = match tok.split_once('/') {
+        Some((a, b)) => a.parse::<f32>()? / b.parse::<f32>()?,
+        None => tok.parse::<f32>()?,
+    };
+    if r <= 0.0 {
+        return Err(anyhow::Error::msg(format!("scl: bad ratio {}", tok)));
+    }
+    Ok(ratio_cents(r))
+}
+
+// scala .kbm, which midi note is which scale degree and where the reference pitch is
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyMapping {
+    pub middle_note: i32,           // midi note that gets degree 0
+    pub reference_note: i32,
+    pub reference_freq: f32,
+    pub octave_degree: i32,         // degree the map repeats at
+    pub map: Vec<Option<i32>>,      // empty is linear, None is an unmapped key
+}
+
+impl KeyMapping {
+    pub fn linear(reference_note: i32, reference_freq: f32) -> KeyMapping {
+        KeyMapping { middle_note: reference_note, reference_note, reference_freq, octave_degree: 0, map: Vec::new() }
+    }
+
+    pub fn parse_kbm(text: &str) -> anyhow::Result<KeyMapping> {
+        let mut lines = text.lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.starts_with('!') && l.len() > 0)
+            .map(|l| l.split_whitespace().next().unwrap());
+        let mut next = |what: &str| lines.next().ok_or(anyhow::Error::msg(format!("kbm: missing {}", what)));
+        let size: usize = next("map size")?.parse()?;
+        let _first: i32 = next("first note")?.parse()?;
+        let _last: i32 = next("last note")?.parse()?;
+        let middle_note = next("middle note")?.parse()?;
+        let reference_note = next("reference note")?.parse()?;
+        let reference_freq = next("reference frequency")?.parse()?;
+        let octave_degree = next("octave degree")?.parse()?;
+        let mut map = Vec::new();
+        for _ in 0..size {
+            // the spec lets trailing entries be left off, theyre unmapped
+            map.push(match lines.next() {
+                Some("x") | None => None,
+                Some(d) => Some(d.parse()?),
+            });
+        }
+        Ok(KeyMapping { middle_note, reference_note, reference_freq, octave_degree, map })
+    }
+
+    pub fn degree(&self, note: i32) -> Option<i32> {
+        let i = note - self.middle_note;
+        if self.map.len() == 0 {
+            return Some(i);
+        }
+        let m = self.map.len() as i32;
+        self.map[i.rem_euclid(m) as usize].map(|d| d + i.div_euclid(m) * self.octave_degree)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    pub scale: Scale,
+    pub mapping: KeyMapping,
+}
+
+impl Tuning {
+    pub fn new() -> Tuning {
+        Tuning { scale: Scale::equal(12), mapping: KeyMapping::linear(69, 440.0) }
+    }
+
+    // None for keys the mapping leaves out
+    pub fn freq(&self, midi_note: i32) -> Option<f32> {
+        let d = self.mapping.degree(midi_note)?;
+        let r = self.mapping.degree(self.mapping.reference_note).unwrap_or(0);
+        let cents = self.scale.degree_cents(d) - self.scale.degree_cents(r);
+        Some(self.mapping.reference_freq * 2.0f32.powf(cents / 1200.0))
+    }
+
+    // what the octave keys and the arp go up by
+    pub fn period_ratio(&self) -> f32 {
+        2.0f32.powf(self.scale.period_cents() / 1200.0)
+    }
+
+    // how many keys make a period. a mapping repeats every map.len() keys, linear is one key a degree
+    pub fn period_keys(&self) -> i32 {
+        if self.mapping.map.len() > 0 {
+            self.mapping.map.len() as i32
+        } else {
+            self.scale.len().max(1)
+        }
+    }
+}
+
+#[test]
+fn test_tuning() {
+    let t = Tuning::new();
+    assert_eq!(t.freq(69), Some(440.0));
+    assert_eq!(t.freq(81), Some(880.0));
+    assert_eq!(t.freq(57), Some(220.0));
+    assert!((t.freq(72).unwrap() - 523.2511).abs() < 0.01);
+
+    let mut t = Tuning::new();
+    t.scale = Scale::just();
+    assert!((t.freq(76).unwrap() - 660.0).abs() < 0.01);   // a fifth up from A
+    assert!((t.freq(73).unwrap() - 550.0).abs() < 0.01);   // major third
+
+    let mut t = Tuning::new();
+    t.scale = Scale::equal(24);
+    assert!((t.freq(93).unwrap() - 880.0).abs() < 0.01);
+    assert_eq!(t.period_keys(), 24);
+    assert_eq!(t.period_ratio(), 2.0);
+
+    // bohlen pierce, 13 steps to a tritave
+    let mut t = Tuning::new();
+    t.scale = Scale { name: "bp".to_string(), cents: (1..=13).map(|i| ratio_cents(3.0) * i as f32 / 13.0).collect() };
+    assert_eq!(t.period_keys(), 13);
+    assert!((t.period_ratio() - 3.0).abs() < 0.001);
+    assert!((t.freq(69 + t.period_keys()).unwrap() - 1320.0).abs() < 0.1);
+}
+
+#[test]
+fn test_scala() {
+    let scl = "! meantone.scl\n!\nquarter comma meantone, sort of\n 4\n!\n 193.157\n 5/4  major third\n 3/2\n 2\n";
+    let s = Scale::parse_scl(scl).unwrap();
+    assert_eq!(s.name, "quarter comma meantone, sort of");
+    assert_eq!(s.cents.len(), 4);
+    assert!((s.cents[0] - 193.157).abs() < 0.001);
+    assert!((s.cents[1] - 386.3137).abs() < 0.001);
+    assert!((s.cents[3] - 1200.0).abs() < 0.001);
+    assert!(Scale::parse_scl("bad\n3\n100.0\n").is_err());
+
+    let kbm = "! white keys only\n7\n0\n127\n60\n69\n440.0\n7\n0\nx\n1\nx\n2\n3\nx\n";
+    let k = KeyMapping::parse_kbm(kbm).unwrap();
+    assert_eq!(k.map, vec![Some(0), None, Some(1), None, Some(2), Some(3), None]);
+    assert_eq!(k.degree(60), Some(0));
+    assert_eq!(k.degree(61), None);
+    assert_eq!(k.degree(67), Some(7));
+    assert_eq!(k.degree(59), None);
+}
+
+// gui side, reference pitch and which scale
+pub struct TuningPanel {
+    pub tuning: Tuning,
+    pub reference: f32,
+    reference_base: f32,    // the slider goes a fifth either side of this, so a kbm far from 440 still fits
+    pub edo: f32,
+    pub just: bool,
+    pub file_scale: Option<Scale>,  // loaded from .scl, overrides the rest until cleared
+}
+
+impl TuningPanel {
+    pub fn new() -> TuningPanel {
+        TuningPanel { tuning: Tuning::new(), reference: 440.0, reference_base: 440.0, edo: 12.0, just: false, file_scale: None }
+    }
+
+    pub fn load_scl(&mut self, path: &str) -> anyhow::Result<()> {
+        self.file_scale = Some(Scale::parse_scl(&std::fs::read_to_string(path)?)?);
+        self.update();
+        Ok(())
+    }
+
+    pub fn load_kbm(&mut self, path: &str) -> anyhow::Result<()> {
+        let k = KeyMapping::parse_kbm(&std::fs::read_to_string(path)?)?;
+        self.reference = k.reference_freq;
+        self.reference_base = k.reference_freq;
+        self.tuning.mapping = k;
+        self.update();
+        Ok(())
+    }
+
+    fn update(&mut self) {
+        self.tuning.mapping.reference_freq = self.reference;
+        self.tuning.scale = if let Some(s) = &self.file_scale {
+            s.clone()
+        } else if self.just {
+            Scale::just()
+        } else {
+            Scale::equal(self.edo.round() as u32)
+        };
+    }
+
+    // returns modification
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect) -> bool {
+        ui.push_id("tuning");
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.5, 0.5, 0.4, 1.0));
+        ui.kc.rect(rect);
+
+        let (title, rest) = rect.split_ud(0.15);
+        let (buttons, sliders) = rest.split_ud(0.15);
+        ui.kc.set_depth(1.2);
+        ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        ui.kc.text_center(self.tuning.scale.name.as_bytes(), title.dilate_pc(-0.1));
+
+        let mut changed = false;
+        let (just_rect, clear_rect) = buttons.split_lr(0.5);
+        changed |= ui.toggle("just", just_rect, &mut self.just);
+        if ui.button("reset", clear_rect) {
+            self.file_scale = None;
+            self.reference_base = self.reference;
+            self.tuning.mapping = KeyMapping::linear(69, self.reference);
+            changed = true;
+        }
+
+        let sliders = sliders.split_lrn(2);
+        let (lo, hi) = (self.reference_base / 1.5, self.reference_base * 1.5);
+        changed |= ui.slider("ref", sliders[0], lo, hi, &mut self.reference, SliderMapping::Exp, Unit::Hz) |
+            ui.slider("edo", sliders[1], 5.0, 53.0, &mut self.edo, SliderMapping::Stepped(1.0), Unit::None);
+        if changed {
+            self.update();
+        }
+        ui.pop_id();
+        changed
+    }
+}
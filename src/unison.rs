@@ -0,0 +1,83 @@
+use crate::kmath::*;
+use crate::widgets::*;
+use crate::units::*;
+use crate::tuning::*;
+
+pub const MAX_LAYERS: usize = 4;
+
+// the whole detuned voice stack again at some interval above the played note
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layer {
+    pub cents: f32,
+    pub level: f32,     // 0 is off
+}
+
+// full level for each interval given, rest off
+pub fn interval_layers(cents: &[f32]) -> [Layer; MAX_LAYERS] {
+    let mut layers = [Layer { cents: 0.0, level: 0.0 }; MAX_LAYERS];
+    for (i, c) in cents.iter().take(MAX_LAYERS).enumerate() {
+        layers[i] = Layer { cents: *c, level: 1.0 };
+    }
+    layers
+}
+
+// just intervals so the stacks dont beat against each other
+pub fn layer_presets() -> Vec<(&'static str, Vec<f32>)> {
+    let fifth = ratio_cents(3.0 / 2.0);
+    let maj3 = ratio_cents(5.0 / 4.0);
+    let min3 = ratio_cents(6.0 / 5.0);
+    vec![
+        ("1", vec![0.0]),
+        ("oct", vec![0.0, -1200.0]),
+        ("5th", vec![0.0, fifth]),
+        ("pwr", vec![0.0, fifth, 1200.0]),
+        ("maj", vec![0.0, maj3, fifth]),
+        ("min", vec![0.0, min3, fifth]),
+    ]
+}
+
+pub struct UnisonPanel {
+    pub layers: [Layer; MAX_LAYERS],
+}
+
+impl UnisonPanel {
+    pub fn new() -> UnisonPanel {
+        UnisonPanel { layers: interval_layers(&[0.0]) }
+    }
+
+    // returns modification
+    pub fn frame(&mut self, ui: &mut Ui, rect: Rect) -> bool {
+        ui.push_id("layers");
+        let mut changed = false;
+        ui.kc.set_depth(1.1);
+        ui.kc.set_colour(Vec4::new(0.4, 0.5, 0.5, 1.0));
+        ui.kc.rect(rect);
+
+        let (title, rest) = rect.split_ud(0.1);
+        let (presets, sliders) = rest.split_ud(0.1);
+        ui.kc.set_depth(1.2);
+        ui.kc.set_colour(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        ui.kc.text_center("layers".as_bytes(), title);
+
+        let presets_list = layer_presets();
+        let preset_rects = presets.split_lrn(presets_list.len() as i32);
+        for ((name, cents), r) in presets_list.iter().zip(preset_rects) {
+            if ui.button(name, r) {
+                self.layers = interval_layers(cents);
+                changed = true;
+            }
+        }
+
+        // cents on top, level underneath
+        let columns = sliders.split_lrn(MAX_LAYERS as i32);
+        for i in 0..MAX_LAYERS {
+            let (c, l) = columns[i].split_ud(0.5);
+            ui.push_id(&i.to_string());
+            changed |= ui.slider("ct", c, -2400.0, 2400.0, &mut self.layers[i].cents, SliderMapping::Linear, Unit::Cents) |
+                ui.slider("lv", l, 0.0, 1.0, &mut self.layers[i].level, SliderMapping::Linear, Unit::Percent);
+            ui.pop_id();
+        }
+        ui.pop_id();
+        changed
+    }
+}
@@ -0,0 +1,139 @@
+use crate::sound::*;
+
+// what a controls value means, for showing it and for reading back whatever gets typed in
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    None,
+    Hz,         // also takes note names, A4 C#3 Bb2
+    Seconds,    // shown as ms under a second, and a bare typed number is ms
+    Cents,
+    Semitones,
+    Db,
+    Gain,       // linear amplitude but shown and typed as db, or typed as %
+    Percent,    // 0..1 shown as 0..100
+    Bpm,
+}
+
+// "-6.5db" -> (-6.5, "db"). nothing after the number is ""
+fn split_number(s: &str) -> Option<(f32, &str)> {
+    let end = s.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+')).unwrap_or(s.len());
+    let num = s[..end].parse::<f32>().ok()?;
+    Some((num, &s[end..]))
+}
+
+// scientific pitch, a4 is 440
+pub fn parse_note(s: &str) -> Option<f32> {
+    let mut chars = s.chars().peekable();
+    let pc = match chars.next()?.to_ascii_lowercase() {
+        'c' => 0, 'd' => 2, 'e' => 4, 'f' => 5, 'g' => 7, 'a' => 9, 'b' => 11,
+        _ => return None,
+    };
+    let rest: String = chars.collect();
+    // b straight after the letter is a flat unless its the only thing before the octave, "b2" is just b
+    let (accidental, octave) = if let Some(o) = rest.strip_prefix('#') {
+        (1, o)
+    } else if rest.len() > 1 && rest.starts_with('b') {
+        (-1, &rest[1..])
+    } else {
+        (0, &rest[..])
+    };
+    let octave: i32 = octave.parse().ok()?;
+    let midi = (octave + 1) * 12 + pc + accidental;
+    Some(note_freq(midi - 69))
+}
+
+// nearest note and how far off it is, "A4" or "A4+12"
+pub fn note_label(hz: f32) -> String {
+    let note = 12.0 * (hz / 440.0).log2();
+    let nearest = note.round();
+    let cents = ((note - nearest) * 100.0).round() as i32;
+    if cents == 0 {
+        note_name(nearest as i32)
+    } else {
+        format!("{}{:+}", note_name(nearest as i32), cents)
+    }
+}
+
+impl Unit {
+    pub fn format(&self, val: f32) -> String {
+        match self {
+            Unit::None => format!("{:.2}", val),
+            Unit::Hz if val >= 1000.0 => format!("{:.2}khz {}", val / 1000.0, note_label(val)),
+            Unit::Hz if val <= 0.0 => "0hz".to_string(),
+            Unit::Hz => format!("{:.0}hz {}", val, note_label(val)),
+            Unit::Seconds if val < 0.01 => format!("{:.1}ms", val * 1000.0),
+            Unit::Seconds if val < 1.0 => format!("{:.0}ms", val * 1000.0),
+            Unit::Seconds => format!("{:.2}s", val),
+            Unit::Cents => format!("{:+.0}ct", val),
+            Unit::Semitones => format!("{:+.1}st", val),
+            Unit::Db => format!("{:.1}db", val),
+            Unit::Gain if val <= 0.0 => "-infdb".to_string(),
+            Unit::Gain => format!("{:.1}db", 20.0 * val.log10()),
+            Unit::Percent => format!("{:.0}%", val * 100.0),
+            Unit::Bpm => format!("{:.0}bpm", val),
+        }
+    }
+
+    // case and spaces dont matter. a bare number is in whatever the display shows
+    pub fn parse(&self, s: &str) -> Option<f32> {
+        let s: String = s.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+        if *self == Unit::Hz {
+            if let Some(f) = parse_note(&s) {
+                return Some(f);
+            }
+        }
+        let (x, suffix) = split_number(&s)?;
+        let db = |x: f32| 10.0f32.powf(x / 20.0);
+        match (self, suffix) {
+            (Unit::Seconds, "") | (Unit::Seconds, "ms") => Some(x / 1000.0),
+            (Unit::Seconds, "s") => Some(x),
+            (Unit::Percent, "") | (Unit::Percent, "%") | (Unit::Gain, "%") => Some(x / 100.0),
+            (Unit::Gain, "") | (Unit::Gain, "db") => Some(db(x)),
+            (Unit::Hz, "k") | (Unit::Hz, "khz") => Some(x * 1000.0),
+            (Unit::Hz, "hz") => Some(x),
+            (Unit::Cents, "c") | (Unit::Cents, "ct") | (Unit::Cents, "cents") => Some(x),
+            (Unit::Cents, "st") => Some(x * 100.0),
+            (Unit::Semitones, "st") | (Unit::Semitones, "semi") | (Unit::Semitones, "semis") => Some(x),
+            (Unit::Semitones, "c") | (Unit::Semitones, "ct") => Some(x / 100.0),
+            (Unit::Db, "db") => Some(x),
+            (Unit::Bpm, "bpm") => Some(x),
+            (_, "") => Some(x),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_units() {
+    let close = |a: Option<f32>, b: f32| a.map_or(false, |a| (a - b).abs() < 1e-3 * b.abs().max(1.0));
+    assert!(close(Unit::Hz.parse("440hz"), 440.0));
+    assert!(close(Unit::Hz.parse(" 1.2 kHz"), 1200.0));
+    assert!(close(Unit::Hz.parse("A4"), 440.0));
+    assert!(close(Unit::Hz.parse("a3"), 220.0));
+    assert!(close(Unit::Hz.parse("C4"), 261.626));
+    assert!(close(Unit::Hz.parse("C#4"), 277.183));
+    assert!(close(Unit::Hz.parse("Bb3"), 233.082));
+    assert!(close(Unit::Hz.parse("B3"), 246.942));
+    assert!(close(Unit::Hz.parse("c-1"), 8.176));
+    assert!(close(Unit::Gain.parse("-6db"), 0.5012));
+    assert!(close(Unit::Gain.parse("50%"), 0.5));
+    assert!(close(Unit::Db.parse("-6db"), -6.0));
+    assert!(close(Unit::Seconds.parse("250"), 0.25));
+    assert!(close(Unit::Seconds.parse("1.5s"), 1.5));
+    assert!(close(Unit::Cents.parse("+25ct"), 25.0));
+    assert!(close(Unit::Cents.parse("1st"), 100.0));
+    assert!(close(Unit::Percent.parse("75"), 0.75));
+    assert_eq!(Unit::Hz.parse("440db"), None);
+    assert_eq!(Unit::Hz.parse("h4"), None);
+    assert_eq!(Unit::None.parse("abc"), None);
+
+    assert_eq!(Unit::Hz.format(440.0), "440hz A4");
+    assert_eq!(Unit::Hz.format(452.0), "452hz A4+47");
+    assert_eq!(Unit::Seconds.format(0.25), "250ms");
+    assert_eq!(Unit::Gain.format(1.0), "0.0db");
+    assert_eq!(Unit::Cents.format(-12.0), "-12ct");
+    // what gets shown reads back as about the same thing
+    for (u, v) in [(Unit::Seconds, 0.123), (Unit::Percent, 0.42), (Unit::Db, -12.5), (Unit::Semitones, 7.0), (Unit::Bpm, 128.0)] {
+        assert!(close(u.parse(&u.format(v)), v), "{:?} {}", u, u.format(v));
+    }
+}
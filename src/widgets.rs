@@ -0,0 +1,803 @@
+use crate::krenderer::*;
+use crate::kinput::*;
+use crate::kmath::*;
+use crate::units::*;
+use crate::midi::*;
+use crate::config::*;
+use glutin::event::VirtualKeyCode;
+
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+// immediate mode widgets. layout is still rects handed in, but each widget gets an id from
+// its label and whatever panel pushed ids around it, so the ui can remember who has the mouse
+// between frames. the widget that got the press keeps the drag even after leaving its rect
+pub type WidgetId = u64;
+
+// the bit that survives between frames, lives with whoever owns the panels
+pub struct UiState {
+    active: Option<WidgetId>,   // has the mouse captured
+    open: Option<WidgetId>,     // dropdown thats open, nothing else gets clicks while it is
+    drag_mouse: Vec2,           // where the mouse was last frame of the drag
+    drag_value: f32,            // 0..1 position along the control, unrounded so steps dont stick
+    last_press: Option<(WidgetId, f64)>,    // for double clicks
+    defaults: HashMap<WidgetId, f32>,       // whatever a value control held the first time it was drawn
+    drag_moved: bool,           // so letting go of a plain click can open the text box
+    reset: bool,                // the press was a double click, dont open the text box on release
+    editing: Option<(WidgetId, String)>,    // value control being typed into and whats typed so far
+    edit_drawn: bool,           // the control being typed into was drawn this frame
+    learning: Option<String>,   // control waiting on a cc to bind to, by path
+    bindings: HashMap<String, MidiBinding>,
+    bindings_changed: bool,
+    midi_in: Vec<(u8, u8, u8)>, // channel cc value for the ccs that came in this frame
+}
+
+impl UiState {
+    pub fn new() -> UiState {
+        UiState {
+            active: None,
+            open: None,
+            drag_mouse: Vec2::new(0.0, 0.0),
+            drag_value: 0.0,
+            last_press: None,
+            defaults: HashMap::new(),
+            drag_moved: false,
+            reset: false,
+            editing: None,
+            edit_drawn: false,
+            learning: None,
+            bindings: HashMap::new(),
+            bindings_changed: false,
+            midi_in: Vec::new(),
+        }
+    }
+
+    // call every frame before the panels, even with nothing, or the last ccs stick
+    pub fn midi(&mut self, messages: &[MidiMessage]) {
+        self.midi_in.clear();
+        for m in messages {
+            if let MidiMessage::ControlChange { channel, cc, value } = *m {
+                self.midi_in.push((channel, cc, value));
+            }
+        }
+    }
+
+    pub fn load_bindings(&mut self, config: &Config) {
+        self.bindings = config.midi.iter().cloned().collect();
+    }
+
+    // true once after a binding was learned or forgotten, then the config wants saving
+    pub fn save_bindings(&mut self, config: &mut Config) -> bool {
+        if !self.bindings_changed {
+            return false;
+        }
+        self.bindings_changed = false;
+        config.midi = self.bindings.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        config.midi.sort_by(|a, b| a.0.cmp(&b.0));
+        true
+    }
+
+    // a text box has the keyboard, key presses shouldnt play notes or hit shortcuts
+    pub fn typing(&self) -> bool {
+        self.editing.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Response {
+    hovered: bool,
+    pressed: bool,      // got the mouse this frame
+    held: bool,         // has it captured
+    clicked: bool,      // let go while still over it
+}
+
+// how the 0..1 along a control maps onto its range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SliderMapping {
+    Linear,
+    Exp,            // same ratio for the same distance, for frequencies and gains. min has to be above 0
+    Stepped(f32),   // linear but snapped to whole steps up from min, Stepped(1.0) for counts
+}
+
+impl SliderMapping {
+    pub fn value(&self, t: f32, min: f32, max: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        match self {
+            SliderMapping::Linear => lerp(min, max, t),
+            SliderMapping::Exp => min * (max / min).powf(t),
+            SliderMapping::Stepped(step) => (min + ((lerp(min, max, t) - min) / step).round() * step).min(max),
+        }
+    }
+
+    pub fn t(&self, val: f32, min: f32, max: f32) -> f32 {
+        let t = match self {
+            SliderMapping::Exp => (val / min).ln() / (max / min).ln(),
+            _ => unlerp(val, min, max),
+        };
+        t.max(0.0).min(1.0)
+    }
+}
+
+fn format_value(val: f32, mapping: SliderMapping, unit: Unit) -> String {
+    match (unit, mapping) {
+        (Unit::None, SliderMapping::Stepped(step)) if step >= 1.0 => format!("{:.0}", val),
+        _ => unit.format(val),
+    }
+}
+
+// fraction of the control per scroll notch, and how much slower shift makes everything
+const SCROLL_T: f32 = 0.02;
+const FINE: f32 = 0.1;
+const DOUBLE_CLICK: f64 = 0.3;
+
+const BG: Vec4 = Vec4 { x: 0.2, y: 0.2, z: 0.2, w: 1.0 };
+const HOVER: Vec4 = Vec4 { x: 0.3, y: 0.3, z: 0.3, w: 1.0 };
+const HELD: Vec4 = Vec4 { x: 0.45, y: 0.4, z: 0.25, w: 1.0 };
+const ON: Vec4 = Vec4 { x: 0.4, y: 0.9, z: 0.4, w: 1.0 };
+const FG: Vec4 = Vec4 { x: 0.7, y: 0.7, z: 0.7, w: 1.0 };
+const TEXT: Vec4 = Vec4 { x: 1.0, y: 1.0, z: 1.0, w: 1.0 };
+const BAD: Vec4 = Vec4 { x: 1.0, y: 0.3, z: 0.3, w: 1.0 };
+
+pub struct Ui<'a> {
+    pub inputs: &'a FrameInputState,
+    pub kc: &'a mut KRCanvas,
+    state: &'a mut UiState,
+    ids: Vec<u64>,
+    names: Vec<String>,     // same as ids but readable, for the config file
+    taken: bool,    // a popup ate this frames click
+}
+
+impl<'a> Ui<'a> {
+    pub fn new(inputs: &'a FrameInputState, kc: &'a mut KRCanvas, state: &'a mut UiState) -> Ui<'a> {
+        // whatever had the mouse might not have been drawn since, dont leave it stuck
+        if inputs.lmb == KeyStatus::Released {
+            state.active = None;
+        }
+        // same for the text box, if its control went away so does it
+        if !state.edit_drawn {
+            state.editing = None;
+        }
+        state.edit_drawn = false;
+        Ui { inputs, kc, state, ids: Vec::new(), names: Vec::new(), taken: false }
+    }
+
+    // ids under here are scoped to it, so two panels can both have a "cutoff"
+    pub fn push_id(&mut self, s: &str) {
+        let id = self.id(s);
+        self.ids.push(id);
+        self.names.push(s.to_string());
+    }
+
+    pub fn pop_id(&mut self) {
+        self.ids.pop();
+        self.names.pop();
+    }
+
+    // the id as a string that stays the same between builds, hashes might not
+    pub fn path(&self, label: &str) -> String {
+        let mut path = self.names.join("/");
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path + label
+    }
+
+    pub fn id(&self, label: &str) -> WidgetId {
+        let mut h = DefaultHasher::new();
+        self.ids.hash(&mut h);
+        label.hash(&mut h);
+        h.finish()
+    }
+
+    pub fn popup_open(&self) -> bool {
+        self.state.open.is_some() || self.taken
+    }
+
+    pub fn typing(&self) -> bool {
+        self.state.typing()
+    }
+
+    // for the hand rolled bits that read inputs themselves, no clicks while a popup has them
+    pub fn raw_inputs(&self) -> FrameInputState {
+        let mut inputs = self.inputs.clone();
+        if self.popup_open() {
+            inputs.lmb = KeyStatus::Released;
+            inputs.rmb = KeyStatus::Released;
+        }
+        inputs
+    }
+
+    fn interact(&mut self, id: WidgetId, r: Rect) -> Response {
+        let blocked = self.taken || matches!(self.state.open, Some(o) if o != id);
+        let free = self.state.active.is_none() || self.state.active == Some(id);
+        let mut resp = Response { hovered: !blocked && free && r.contains(self.inputs.mouse_pos), ..Default::default() };
+        if resp.hovered && self.inputs.lmb == KeyStatus::JustPressed {
+            self.state.active = Some(id);
+            self.state.drag_mouse = self.inputs.mouse_pos;
+            resp.pressed = true;
+        }
+        resp.held = self.state.active == Some(id);
+        if resp.held && (self.inputs.lmb == KeyStatus::JustReleased || self.inputs.lmb == KeyStatus::Released) {
+            self.state.active = None;
+            resp.held = false;
+            resp.clicked = r.contains(self.inputs.mouse_pos);
+        }
+        resp
+    }
+
+    fn fine(&self) -> bool {
+        self.inputs.key_held(VirtualKeyCode::LShift) || self.inputs.key_held(VirtualKeyCode::RShift)
+    }
+
+    // the shared bit of every value control. drags move it relative to where it was (per_unit
+    // of the range per screen unit), shift slows it down, double click puts it back to where it
+    // started, scroll nudges it when hovered, a click without moving opens a box to type it in
+    fn drag_value(&mut self, id: WidgetId, resp: Response, min: f32, max: f32, mapping: SliderMapping, per_unit: f32, val: &mut f32) -> bool {
+        let before = *val;
+        let default = *self.state.defaults.entry(id).or_insert(*val);
+        let scale = if self.fine() { FINE } else { 1.0 };
+        if resp.pressed {
+            let now = self.inputs.t;
+            let double = matches!(self.state.last_press, Some((last, t)) if last == id && now - t < DOUBLE_CLICK);
+            if double {
+                // the first click of it opened the text box, thats not what they meant
+                *val = default;
+                self.state.last_press = None;
+                self.state.editing = None;
+            } else {
+                self.state.last_press = Some((id, now));
+            }
+            self.state.drag_value = mapping.t(*val, min, max);
+            self.state.drag_moved = false;
+            self.state.reset = double;
+        }
+        // clicks on the box while typing dont drag it about
+        if self.editing(id) {
+            return *val != before;
+        }
+        if resp.clicked && !self.state.drag_moved && !self.state.reset {
+            self.state.editing = Some((id, String::new()));
+        }
+        if resp.held {
+            // only touch it once the mouse actually moves, just clicking shouldnt round it
+            let dy = self.state.drag_mouse.y - self.inputs.mouse_pos.y;
+            if dy != 0.0 {
+                self.state.drag_moved = true;
+                self.state.drag_value = (self.state.drag_value + dy * per_unit * scale).max(0.0).min(1.0);
+                *val = mapping.value(self.state.drag_value, min, max);
+            }
+            self.state.drag_mouse = self.inputs.mouse_pos;
+        } else if resp.hovered && self.inputs.scroll != 0.0 {
+            *val = match mapping {
+                // a notch is a step, fine or not
+                SliderMapping::Stepped(step) => (*val + self.inputs.scroll.round() * step).max(min).min(max),
+                _ => mapping.value(mapping.t(*val, min, max) + self.inputs.scroll * SCROLL_T * scale, min, max),
+            };
+        }
+        *val != before
+    }
+
+    fn editing(&self, id: WidgetId) -> bool {
+        matches!(&self.state.editing, Some((e, _)) if *e == id)
+    }
+
+    // the typing half of a value control, drawn over r while its open. enter sets it if it
+    // parses, escape or clicking somewhere else leaves it alone. the keys come through as
+    // characters so they still work when the key presses are being swallowed
+    fn text_entry(&mut self, id: WidgetId, r: Rect, min: f32, max: f32, unit: Unit, val: &mut f32) -> bool {
+        let text = match &mut self.state.editing {
+            Some((e, text)) if *e == id => text,
+            _ => return false,
+        };
+        self.state.edit_drawn = true;
+        let mut commit = false;
+        let mut cancel = self.inputs.lmb == KeyStatus::JustPressed && !r.contains(self.inputs.mouse_pos);
+        for c in self.inputs.text.chars() {
+            match c {
+                '\r' | '\n' => commit = true,
+                '\x1b' => cancel = true,
+                '\x08' => { text.pop(); },
+                // only what the font has
+                ' '..='~' => text.push(c),
+                _ => {},
+            }
+        }
+        // nothing typed yet shows whats there now, greyed out
+        let parsed = unit.parse(text);
+        let colour = if text.is_empty() { FG } else if parsed.is_some() { TEXT } else { BAD };
+        let shown = if text.is_empty() { unit.format(*val) } else { text.clone() };
+
+        let mut changed = false;
+        if commit {
+            if let Some(v) = parsed {
+                let v = v.max(min).min(max);
+                changed = v != *val;
+                *val = v;
+            }
+            self.state.editing = None;
+        } else if cancel {
+            self.state.editing = None;
+        }
+
+        self.kc.set_depth(2.5);
+        self.kc.set_colour(Vec4::new(0.05, 0.05, 0.05, 1.0));
+        self.kc.rect(r);
+        self.kc.set_depth(2.6);
+        self.kc.set_colour(colour);
+        self.kc.text_center(shown.as_bytes(), r.dilate_pc(-0.1));
+        changed
+    }
+
+    // right click a control then move a knob on the controller to bind that cc to it, right
+    // click it again while its waiting to forget it. mark goes in the corner of marker
+    fn midi_learn(&mut self, label: &str, r: Rect, marker: Rect, min: f32, max: f32, mapping: SliderMapping, val: &mut f32) -> bool {
+        let path = self.path(label);
+        if self.inputs.rmb == KeyStatus::JustPressed && r.contains(self.inputs.mouse_pos) && !self.popup_open() {
+            if self.state.learning.as_ref() == Some(&path) {
+                self.state.learning = None;
+                self.state.bindings_changed |= self.state.bindings.remove(&path).is_some();
+            } else {
+                self.state.learning = Some(path.clone());
+            }
+        }
+        let learning = self.state.learning.as_ref() == Some(&path);
+        if learning {
+            if let Some(&(channel, cc, _)) = self.state.midi_in.last() {
+                self.state.bindings.insert(path.clone(), MidiBinding::new(channel, cc));
+                self.state.bindings_changed = true;
+                self.state.learning = None;
+            }
+        }
+
+        let mut changed = false;
+        let binding = self.state.bindings.get(&path).copied();
+        if let Some(b) = binding {
+            // only the latest, a fast twist sends a pile of them
+            if let Some(&(_, _, value)) = self.state.midi_in.iter().rev().find(|(ch, cc, _)| *ch == b.channel && *cc == b.cc) {
+                let v = mapping.value(b.t(value), min, max);
+                changed = v != *val;
+                *val = v;
+            }
+        }
+
+        if learning || binding.is_some() {
+            let h = marker.h * 0.5;
+            self.kc.set_depth(2.4);
+            // blinks while its waiting
+            self.kc.set_colour(if learning && self.inputs.t.fract() < 0.5 { Vec4::new(1.0, 0.9, 0.3, 1.0) } else if learning { BG } else { ON });
+            self.kc.rect(Rect::new(marker.right() - h, marker.y, h, h));
+        }
+        changed
+    }
+
+    fn fill(&self, resp: Response) -> Vec4 {
+        if resp.held { HELD } else if resp.hovered { HOVER } else { BG }
+    }
+
+    pub fn label(&mut self, text: &str, r: Rect) {
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(TEXT);
+        self.kc.text_center(text.as_bytes(), r);
+    }
+
+    pub fn button(&mut self, label: &str, r: Rect) -> bool {
+        let id = self.id(label);
+        let resp = self.interact(id, r);
+        let r = r.dilate_pc(-0.1);
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(self.fill(resp));
+        self.kc.rect(r);
+        self.kc.set_depth(1.6);
+        self.kc.set_colour(TEXT);
+        self.kc.text_center(label.as_bytes(), r.dilate_pc(-0.1));
+        resp.clicked
+    }
+
+    // label shown is the label, not the state, so the state goes in the colour
+    pub fn toggle(&mut self, label: &str, r: Rect, on: &mut bool) -> bool {
+        let id = self.id(label);
+        let resp = self.interact(id, r);
+        if resp.clicked {
+            *on = !*on;
+        }
+        let r = r.dilate_pc(-0.1);
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(if *on { ON } else { self.fill(resp) });
+        self.kc.rect(r);
+        self.kc.set_depth(1.6);
+        self.kc.set_colour(TEXT);
+        self.kc.text_center(label.as_bytes(), r.dilate_pc(-0.1));
+        resp.clicked
+    }
+
+    // vertical slider with the label over it. drag it anywhere along the track, it moves from
+    // where it was rather than jumping to the mouse
+    pub fn slider(&mut self, label: &str, r: Rect, min: f32, max: f32, val: &mut f32, mapping: SliderMapping, unit: Unit) -> bool {
+        let r = r.dilate_pc(-0.02);
+        let (text, track) = r.split_ud(0.05);
+        self.label(label, text);
+        let track = track.fit_aspect_ratio(0.25);
+
+        let id = self.id(label);
+        let resp = self.interact(id, track);
+        let mut changed = self.drag_value(id, resp, min, max, mapping, 1.0 / track.h, val);
+        changed |= self.text_entry(id, text, min, max, unit, val);
+        changed |= self.midi_learn(label, track, text, min, max, mapping, val);
+
+        self.kc.set_depth(2.0);
+        self.kc.set_colour(self.fill(resp));
+        self.kc.rect(track);
+        self.kc.set_depth(2.1);
+        self.kc.set_colour(Vec4::new(0.9, 0.9, 0.9, 1.0));
+        self.kc.rect(track.fit_aspect_ratio(0.01));
+        let t = mapping.t(*val, min, max);
+        let handle = track.dilate_pc(-0.05).fit_aspect_ratio(2.0);
+        let handle = Rect::new_centered(track.centroid().x, lerp(track.bot(), track.top(), t), handle.w, handle.h);
+        self.kc.set_depth(2.2);
+        self.kc.set_colour(if resp.held { Vec4::new(1.0, 0.9, 0.3, 1.0) } else { FG });
+        self.kc.rect(handle);
+        self.kc.set_depth(2.3);
+        self.kc.set_colour(TEXT);
+        self.kc.text_center(format_value(*val, mapping, unit).as_bytes(), handle);
+        changed
+    }
+
+    // drag up and down anywhere once grabbed, a screen height is 5 times round the range
+    pub fn knob(&mut self, label: &str, r: Rect, min: f32, max: f32, val: &mut f32, mapping: SliderMapping, unit: Unit) -> bool {
+        let r = r.dilate_pc(-0.02);
+        let (dial, text) = r.split_ud(0.8);
+        let id = self.id(label);
+        let resp = self.interact(id, dial);
+        let mut changed = self.drag_value(id, resp, min, max, mapping, 5.0, val);
+        changed |= self.text_entry(id, text, min, max, unit, val);
+        changed |= self.midi_learn(label, dial, text, min, max, mapping, val);
+
+        let c = dial.centroid();
+        let radius = dial.w.min(dial.h) * 0.4;
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(self.fill(resp));
+        self.kc.circle(c, radius);
+        // 270 degrees of travel with the gap at the bottom
+        let angle = lerp(-0.75, 0.75, mapping.t(*val, min, max)) * std::f32::consts::PI;
+        let tip = Vec2::new(c.x + angle.sin() * radius, c.y - angle.cos() * radius);
+        self.kc.set_depth(1.6);
+        self.kc.set_colour(if resp.held { Vec4::new(1.0, 0.9, 0.3, 1.0) } else { FG });
+        self.kc.line(c, tip, radius * 0.15);
+        self.kc.set_colour(TEXT);
+        let shown = if resp.held || resp.hovered { format_value(*val, mapping, unit) } else { label.to_string() };
+        self.kc.text_center(shown.as_bytes(), text);
+        changed
+    }
+
+    // value as text, drag up or down a step per hundredth of the screen
+    pub fn number(&mut self, label: &str, r: Rect, min: f32, max: f32, step: f32, val: &mut f32, unit: Unit) -> bool {
+        let id = self.id(label);
+        let resp = self.interact(id, r);
+        let mapping = SliderMapping::Stepped(step);
+        let mut changed = self.drag_value(id, resp, min, max, mapping, step / (0.01 * (max - min)), val);
+        // typed values still land on a step
+        let mut typed = *val;
+        if self.text_entry(id, r, min, max, unit, &mut typed) {
+            *val = mapping.value(mapping.t(typed, min, max), min, max);
+            changed = true;
+        }
+        let r = r.dilate_pc(-0.1);
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(self.fill(resp));
+        self.kc.rect(r);
+        self.kc.set_depth(1.6);
+        self.kc.set_colour(TEXT);
+        self.kc.text_center(format!("{} {}", label, format_value(*val, mapping, unit)).as_bytes(), r.dilate_pc(-0.1));
+        changed
+    }
+
+    // two values at once, x along and y up
+    pub fn xy_pad(&mut self, label: &str, r: Rect, x_range: (f32, f32), y_range: (f32, f32), x: &mut f32, y: &mut f32) -> bool {
+        let id = self.id(label);
+        let resp = self.interact(id, r);
+        let before = (*x, *y);
+        if resp.held {
+            let m = self.inputs.mouse_pos;
+            *x = lerp(x_range.0, x_range.1, unlerp(m.x, r.left(), r.right()).max(0.0).min(1.0));
+            *y = lerp(y_range.0, y_range.1, unlerp(m.y, r.bot(), r.top()).max(0.0).min(1.0));
+        }
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(self.fill(resp));
+        self.kc.rect(r);
+        let p = Vec2::new(
+            lerp(r.left(), r.right(), unlerp(*x, x_range.0, x_range.1)),
+            lerp(r.bot(), r.top(), unlerp(*y, y_range.0, y_range.1)),
+        );
+        self.kc.set_depth(1.6);
+        self.kc.set_colour(Vec4::new(0.4, 0.4, 0.4, 1.0));
+        self.kc.rect(Rect::new(r.x, p.y, r.w, 0.001));
+        self.kc.rect(Rect::new(p.x, r.y, 0.001, r.h));
+        self.kc.set_colour(if resp.held { Vec4::new(1.0, 0.9, 0.3, 1.0) } else { FG });
+        self.kc.rect(Rect::new_centered(p.x, p.y, 0.01, 0.01));
+        self.kc.set_colour(TEXT);
+        self.kc.text_left(label.as_bytes(), r.child(0.02, 0.02, 1.0, 0.1));
+        (*x, *y) != before
+    }
+
+    // click to open a list under it, click one to pick it. clicking anywhere else just closes it
+    pub fn dropdown(&mut self, label: &str, r: Rect, options: &[&str], selected: &mut usize) -> bool {
+        let id = self.id(label);
+        let is_open = self.state.open == Some(id);
+        let resp = self.interact(id, r);
+        let mut changed = false;
+        let item = |i: usize| Rect::new(r.x, r.y + r.h * (i + 1) as f32, r.w, r.h);
+
+        if is_open {
+            let mouse = self.inputs.mouse_pos;
+            if self.inputs.lmb == KeyStatus::JustPressed {
+                if let Some(i) = (0..options.len()).find(|i| item(*i).contains(mouse)) {
+                    changed = *selected != i;
+                    *selected = i;
+                }
+                self.state.open = None;
+                // nothing under the list gets this click
+                if !r.contains(mouse) {
+                    self.state.active = None;
+                }
+                self.taken = true;
+            }
+            self.kc.set_depth(3.0);
+            for (i, o) in options.iter().enumerate() {
+                let ir = item(i);
+                self.kc.set_colour(if ir.contains(mouse) { HOVER } else if i == *selected { HELD } else { BG });
+                self.kc.rect(ir);
+                self.kc.set_depth(3.1);
+                self.kc.set_colour(TEXT);
+                self.kc.text_center(o.as_bytes(), ir.dilate_pc(-0.15));
+                self.kc.set_depth(3.0);
+            }
+        } else if resp.pressed {
+            self.state.open = Some(id);
+        }
+
+        let r = r.dilate_pc(-0.1);
+        self.kc.set_depth(1.5);
+        self.kc.set_colour(self.fill(resp));
+        self.kc.rect(r);
+        self.kc.set_depth(1.6);
+        self.kc.set_colour(TEXT);
+        let (text, arrow) = r.split_lr(0.85);
+        self.kc.text_center(options.get(*selected).unwrap_or(&"").as_bytes(), text.dilate_pc(-0.1));
+        self.kc.text_center(if is_open { "^".as_bytes() } else { "v".as_bytes() }, arrow);
+        changed
+    }
+}
+
+#[cfg(test)]
+pub fn test_inputs(mouse: Vec2, lmb: KeyStatus) -> FrameInputState {
+    FrameInputState {
+        screen_rect: Rect::new(0.0, 0.0, 1.0, 1.0),
+        px: 0.001,
+        mouse_pos: mouse,
+        mouse_delta: Vec2::new(0.0, 0.0),
+        prev_keys: Default::default(),
+        curr_keys: Default::default(),
+        lmb,
+        rmb: KeyStatus::Released,
+        mmb: KeyStatus::Released,
+        scroll: 0.0,
+        text: String::new(),
+        t: 0.0,
+        dt: 0.016,
+        frame: 0,
+        seed: 0,
+    }
+}
+
+#[test]
+fn test_widget_capture() {
+    let mut state = UiState::new();
+    let mut kc = KRCanvas::new();
+    let knob_rect = Rect::new(0.0, 0.0, 0.1, 0.1);
+    let button_rect = Rect::new(0.5, 0.0, 0.1, 0.1);
+    let mut val = 0.5;
+
+    // grab the knob then drag way off it and over the button, the knob keeps it
+    let frames = [
+        (Vec2::new(0.05, 0.05), KeyStatus::JustPressed),
+        (Vec2::new(0.55, 0.03), KeyStatus::Pressed),
+        (Vec2::new(0.55, 0.04), KeyStatus::JustReleased),
+    ];
+    let mut clicked = false;
+    for (mouse, lmb) in frames {
+        let inputs = test_inputs(mouse, lmb);
+        let mut ui = Ui::new(&inputs, &mut kc, &mut state);
+        ui.knob("k", knob_rect, 0.0, 1.0, &mut val, SliderMapping::Linear, Unit::None);
+        clicked |= ui.button("b", button_rect);
+    }
+    assert!(!clicked);
+    assert!(val > 0.5 && val <= 1.0);
+    assert!(state.active.is_none());
+
+    // same id under a different scope is a different widget
+    let inputs = test_inputs(Vec2::new(0.0, 0.0), KeyStatus::Released);
+    let mut ui = Ui::new(&inputs, &mut kc, &mut state);
+    let a = ui.id("cutoff");
+    ui.push_id("filter");
+    assert_ne!(a, ui.id("cutoff"));
+    ui.pop_id();
+    assert_eq!(a, ui.id("cutoff"));
+
+    // open a dropdown, pick the second thing, the button under the list doesnt see it
+    let options = ["a", "b", "c"];
+    let mut selected = 0;
+    let drop_rect = Rect::new(0.5, 0.0, 0.1, 0.05);
+    let mut clicked = false;
+    let frames = [
+        (Vec2::new(0.55, 0.02), KeyStatus::JustPressed),
+        (Vec2::new(0.55, 0.02), KeyStatus::JustReleased),
+        (Vec2::new(0.55, 0.12), KeyStatus::JustPressed),
+        (Vec2::new(0.55, 0.12), KeyStatus::JustReleased),
+    ];
+    for (mouse, lmb) in frames {
+        let inputs = test_inputs(mouse, lmb);
+        let mut ui = Ui::new(&inputs, &mut kc, &mut state);
+        ui.dropdown("d", drop_rect, &options, &mut selected);
+        clicked |= ui.button("under", Rect::new(0.5, 0.1, 0.1, 0.05));
+    }
+    assert_eq!(selected, 1);
+    assert!(!clicked);
+    assert!(state.open.is_none());
+}
+
+#[test]
+fn test_slider_mapping() {
+    // exp should land back where it started and have equal ratios for equal steps
+    let m = SliderMapping::Exp;
+    for v in [50.0, 99.0, 440.0, 1234.5, 3000.0] {
+        assert!((m.value(m.t(v, 50.0, 3000.0), 50.0, 3000.0) - v).abs() / v < 1e-5, "{}", v);
+    }
+    let r1 = m.value(0.3, 20.0, 20000.0) / m.value(0.2, 20.0, 20000.0);
+    let r2 = m.value(0.9, 20.0, 20000.0) / m.value(0.8, 20.0, 20000.0);
+    assert!((r1 - r2).abs() < 1e-4);
+    assert_eq!(m.value(0.0, 20.0, 20000.0), 20.0);
+    assert!((m.value(1.0, 20.0, 20000.0) - 20000.0).abs() < 0.01);
+
+    let m = SliderMapping::Linear;
+    for v in [-3.0, 0.0, 0.25, 7.5] {
+        assert!((m.value(m.t(v, -3.0, 7.5), -3.0, 7.5) - v).abs() < 1e-5);
+    }
+
+    let m = SliderMapping::Stepped(1.0);
+    assert_eq!(m.value(0.49, 1.0, 9.0), 5.0);
+    assert_eq!(m.value(0.0, 1.0, 9.0), 1.0);
+    assert_eq!(m.value(1.0, 1.0, 9.0), 9.0);
+    for v in 1..=9 {
+        assert_eq!(m.value(m.t(v as f32, 1.0, 9.0), 1.0, 9.0), v as f32);
+    }
+    // steps count from min even when the range isnt a whole number of them
+    assert_eq!(SliderMapping::Stepped(0.5).value(1.0, 0.25, 1.9), 1.75);
+}
+
+#[test]
+fn test_slider_drag() {
+    let mut state = UiState::new();
+    let mut kc = KRCanvas::new();
+    let r = Rect::new(0.0, 0.0, 0.2, 1.0);
+    let mut val = 0.5;
+    let mut frame = |mouse: Vec2, lmb: KeyStatus, t: f64, shift: bool, scroll: f32, val: &mut f32| {
+        let mut inputs = test_inputs(mouse, lmb);
+        inputs.t = t;
+        inputs.scroll = scroll;
+        if shift {
+            inputs.curr_keys.insert(VirtualKeyCode::LShift);
+        }
+        let mut ui = Ui::new(&inputs, &mut kc, &mut state);
+        ui.slider("s", r, 0.0, 1.0, val, SliderMapping::Linear, Unit::None)
+    };
+    let track = r.dilate_pc(-0.02).split_ud(0.05).1.fit_aspect_ratio(0.25);
+    let c = track.centroid();
+
+    // clicking near the top doesnt jump there
+    assert!(!frame(Vec2::new(c.x, track.top() + 0.01), KeyStatus::JustPressed, 0.0, false, 0.0, &mut val));
+    assert_eq!(val, 0.5);
+    // a tenth of the track up is a tenth of the range
+    frame(Vec2::new(c.x, track.top() + 0.01 - track.h * 0.1), KeyStatus::Pressed, 0.1, false, 0.0, &mut val);
+    assert!((val - 0.6).abs() < 1e-4);
+    // off the side still drags, shift makes it a tenth as much
+    frame(Vec2::new(c.x + 0.5, track.top() + 0.01 - track.h * 0.2), KeyStatus::Pressed, 0.2, true, 0.0, &mut val);
+    assert!((val - 0.61).abs() < 1e-4);
+    frame(Vec2::new(c.x, c.y), KeyStatus::JustReleased, 0.3, false, 0.0, &mut val);
+
+    // scroll nudges
+    frame(Vec2::new(c.x, c.y), KeyStatus::Released, 1.0, false, 1.0, &mut val);
+    assert!((val - 0.63).abs() < 1e-4);
+
+    // double click goes back to what it was when first drawn
+    frame(Vec2::new(c.x, c.y), KeyStatus::JustPressed, 2.0, false, 0.0, &mut val);
+    frame(Vec2::new(c.x, c.y), KeyStatus::JustReleased, 2.1, false, 0.0, &mut val);
+    assert!(frame(Vec2::new(c.x, c.y), KeyStatus::JustPressed, 2.2, false, 0.0, &mut val));
+    assert_eq!(val, 0.5);
+}
+
+#[test]
+fn test_text_entry() {
+    let mut state = UiState::new();
+    let mut kc = KRCanvas::new();
+    let r = Rect::new(0.0, 0.0, 0.2, 1.0);
+    let mut val = 1000.0;
+    let mut t = 0.0;
+    let mut frame = |lmb: KeyStatus, text: &str, val: &mut f32, state: &mut UiState| {
+        // far enough apart that no two clicks are a double
+        t += 1.0;
+        let mut inputs = test_inputs(Vec2::new(0.1, 0.5), lmb);
+        inputs.t = t;
+        inputs.text = text.to_string();
+        let mut ui = Ui::new(&inputs, &mut kc, state);
+        ui.slider("cutoff", r, 50.0, 3000.0, val, SliderMapping::Exp, Unit::Hz)
+    };
+
+    // a plain click opens it, typing doesnt change anything til enter
+    frame(KeyStatus::JustPressed, "", &mut val, &mut state);
+    frame(KeyStatus::JustReleased, "", &mut val, &mut state);
+    assert!(state.typing());
+    assert!(!frame(KeyStatus::Released, "A4x", &mut val, &mut state));
+    assert_eq!(val, 1000.0);
+    assert!(frame(KeyStatus::Released, "\x08\r", &mut val, &mut state));
+    assert!((val - 440.0).abs() < 1e-3);
+    assert!(!state.typing());
+
+    // rubbish leaves it alone, out of range gets clamped, escape gives up
+    frame(KeyStatus::JustPressed, "", &mut val, &mut state);
+    frame(KeyStatus::JustReleased, "", &mut val, &mut state);
+    assert!(!frame(KeyStatus::Released, "loud\r", &mut val, &mut state));
+    assert!((val - 440.0).abs() < 1e-3);
+    frame(KeyStatus::JustPressed, "", &mut val, &mut state);
+    frame(KeyStatus::JustReleased, "", &mut val, &mut state);
+    assert!(frame(KeyStatus::Released, "20khz\r", &mut val, &mut state));
+    assert_eq!(val, 3000.0);
+    frame(KeyStatus::JustPressed, "", &mut val, &mut state);
+    frame(KeyStatus::JustReleased, "", &mut val, &mut state);
+    frame(KeyStatus::Released, "100\x1b", &mut val, &mut state);
+    assert!(!state.typing());
+    assert_eq!(val, 3000.0);
+}
+
+#[test]
+fn test_midi_learn() {
+    let mut state = UiState::new();
+    let mut kc = KRCanvas::new();
+    let r = Rect::new(0.0, 0.0, 0.2, 1.0);
+    let mut val = 1.0;
+    let mut frame = |rmb: KeyStatus, ccs: &[(u8, u8, u8)], val: &mut f32, state: &mut UiState| {
+        let mut inputs = test_inputs(Vec2::new(0.1, 0.5), KeyStatus::Released);
+        inputs.rmb = rmb;
+        let messages: Vec<_> = ccs.iter().map(|&(channel, cc, value)| MidiMessage::ControlChange { channel, cc, value }).collect();
+        state.midi(&messages);
+        let mut ui = Ui::new(&inputs, &mut kc, state);
+        ui.push_id("filter");
+        let changed = ui.slider("width", r, 10.0, 1000.0, val, SliderMapping::Exp, Unit::Hz);
+        ui.pop_id();
+        changed
+    };
+
+    // nothing bound, ccs do nothing
+    assert!(!frame(KeyStatus::Released, &[(0, 74, 127)], &mut val, &mut state));
+    // right click, wiggle a knob, now its bound
+    frame(KeyStatus::JustPressed, &[], &mut val, &mut state);
+    frame(KeyStatus::Released, &[(2, 71, 64)], &mut val, &mut state);
+    let mut config = Config::new();
+    assert!(state.save_bindings(&mut config));
+    assert!(!state.save_bindings(&mut config));
+    assert_eq!(config.midi, vec![("filter/width".to_string(), MidiBinding::new(2, 71))]);
+    // scaled through the mapping, other ccs ignored, last one wins
+    assert!(frame(KeyStatus::Released, &[(2, 71, 0), (0, 71, 127)], &mut val, &mut state));
+    assert!((val - 10.0).abs() < 1e-3);
+    assert!(frame(KeyStatus::Released, &[(2, 71, 0), (2, 71, 127)], &mut val, &mut state));
+    assert!((val - 1000.0).abs() < 1e-2);
+
+    // loads back, and right clicking twice forgets it
+    let mut state = UiState::new();
+    state.load_bindings(&config);
+    frame(KeyStatus::JustPressed, &[], &mut val, &mut state);
+    frame(KeyStatus::JustPressed, &[], &mut val, &mut state);
+    assert!(state.save_bindings(&mut config));
+    assert!(config.midi.is_empty());
+    assert!(!frame(KeyStatus::Released, &[(2, 71, 0)], &mut val, &mut state));
+}